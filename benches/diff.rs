@@ -0,0 +1,60 @@
+//! Diff throughput on documents of growing size.
+//!
+//! Each group diffs documents of 1k, 10k and 100k members. The time per
+//! element should stay flat across sizes in every diff mode.
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use serde_json::{Value, json};
+use spatch::diff::{DiffOptions, diff};
+
+const SIZES: [usize; 3] = [1_000, 10_000, 100_000];
+
+/// A document with `size` users, each nested a few levels deep. Every tenth
+/// user has a different name in the right-hand document.
+fn documents(size: usize) -> (Value, Value) {
+    let user = |i: usize, name: &str| {
+        json!({
+            "id": format!("u-{i}"),
+            "profile": {"name": name, "address": {"city": "London", "zip": i}},
+            "roles": ["reader", "writer"],
+        })
+    };
+
+    let left: Vec<Value> = (0..size).map(|i| user(i, "Ada")).collect();
+    let right: Vec<Value> = (0..size)
+        .map(|i| user(i, if i % 10 == 0 { "Grace" } else { "Ada" }))
+        .collect();
+
+    (json!({"users": left}), json!({"users": right}))
+}
+
+fn bench_diff(c: &mut Criterion) {
+    let schema = json!({
+        "properties": {
+            "users": { "x-spatch-indexKey": "id" }
+        }
+    });
+
+    let mut group = c.benchmark_group("diff");
+    group.sample_size(10);
+
+    for size in SIZES {
+        let (left, right) = documents(size);
+        group.throughput(Throughput::Elements(size as u64));
+
+        group.bench_with_input(BenchmarkId::new("compact", size), &size, |b, _| {
+            b.iter(|| diff(&left, &right, DiffOptions::new()).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("granular", size), &size, |b, _| {
+            b.iter(|| diff(&left, &right, DiffOptions::new().granular()).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("keyed", size), &size, |b, _| {
+            b.iter(|| diff(&left, &right, DiffOptions::new().with_schema(&schema)).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_diff);
+criterion_main!(benches);
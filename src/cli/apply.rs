@@ -0,0 +1,129 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, Read, Write},
+};
+
+use spatch::{diff::PatchOp, patch::apply, stream::apply_reader};
+
+use crate::cli::{
+    ApplyArgs,
+    format::{Format, Input, load, load_json_text, to_string, yaml_stream},
+};
+
+pub fn handle_apply_command(args: ApplyArgs) -> Result<(), Box<dyn Error>> {
+    let patch = load(Some(&args.patch), Format::Auto)?.into_json();
+    let patch: Vec<PatchOp> = serde_json::from_value(patch)?;
+
+    if args.stream {
+        let format = args.format.detect(args.file.as_deref());
+        if format != Format::Json {
+            return Err(format!("--stream only supports JSON documents, not {format}").into());
+        }
+
+        let input: Box<dyn Read> = match &args.file {
+            Some(file_path) => Box::new(File::open(file_path)?),
+            None => Box::new(io::stdin().lock()),
+        };
+        let mut output = io::stdout().lock();
+        apply_reader(input, &mut output, &patch)?;
+        writeln!(output)?;
+        return Ok(());
+    }
+
+    let input = match &args.file {
+        // JSON files edited in place keep their formatting, like JSONC files
+        Some(file_path) if args.in_place && args.format.detect(Some(file_path)) == Format::Json => {
+            Input::Jsonc(load_json_text(file_path)?)
+        }
+        _ => load(args.file.as_deref(), args.format)?,
+    };
+
+    let output = match input {
+        Input::Json(json) => to_string(&apply(&json, &patch)?, Format::Json)?,
+        // Only the values the patch changes are rewritten, keeping comments
+        // and formatting
+        Input::Jsonc(doc) => doc.apply(&patch)?,
+        Input::Yaml(documents) => apply_yaml(documents, &patch)?,
+        Input::Toml(toml) => to_string(&apply(&toml, &patch)?, Format::Toml)?,
+    };
+
+    match &args.file {
+        Some(file_path) if args.in_place => std::fs::write(file_path, output)?,
+        _ => print!("{output}"),
+    }
+    Ok(())
+}
+
+/// Patches the documents of a YAML file.
+///
+/// The documents of a stream are patched as a sequence, so they are addressed
+/// by their index and can be added or removed. A single document is patched
+/// directly, unless the patch only applies to it as a stream of one, like
+/// patches diffed against a stream with more documents.
+fn apply_yaml(
+    documents: Vec<serde_yaml::Value>,
+    patch: &[PatchOp],
+) -> Result<String, Box<dyn Error>> {
+    let stream = serde_yaml::Value::Sequence(documents);
+    let patched = match &stream {
+        serde_yaml::Value::Sequence(documents) if documents.len() == 1 => {
+            match apply(&documents[0], patch) {
+                Ok(document) => return to_string(&document, Format::Yaml),
+                // The error of the direct addressing is the one that matters
+                Err(error) => apply(&stream, patch).map_err(|_| error)?,
+            }
+        }
+        _ => apply(&stream, patch)?,
+    };
+    match patched {
+        serde_yaml::Value::Sequence(documents) => yaml_stream(&documents),
+        _ => Err("a patched YAML stream must stay a sequence of documents".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert2::check;
+    use spatch::diff::DiffOptions;
+
+    use super::*;
+    use crate::cli::diff::diff_yaml_streams;
+
+    fn documents(yaml: &str) -> Vec<serde_yaml::Value> {
+        serde_yaml::Deserializer::from_str(yaml)
+            .map(serde::Deserialize::deserialize)
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn apply_yaml_should_round_trip_diffs_of_streams_of_different_lengths() {
+        for (left, right) in [
+            ("a: 1\n", "a: 2\n---\nb: 3\n"),
+            ("a: 1\n---\nb: 3\n", "a: 2\n"),
+            ("a: 1\n", "a: 2\n"),
+            ("a: 1\n---\nb: 3\n", "a: 2\n---\nb: 4\n"),
+        ] {
+            let patch =
+                diff_yaml_streams(&documents(left), &documents(right), DiffOptions::new()).unwrap();
+
+            let patched = apply_yaml(documents(left), &patch).unwrap();
+
+            check!(
+                documents(&patched) == documents(right),
+                "{left:?} to {right:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn apply_yaml_should_report_errors_of_single_documents_directly() {
+        let patch = [PatchOp::remove("/missing".try_into().unwrap())];
+
+        let error = apply_yaml(documents("a: 1\n"), &patch).unwrap_err();
+
+        let direct = apply(&documents("a: 1\n")[0], &patch).unwrap_err();
+        check!(error.to_string() == direct.to_string());
+    }
+}
@@ -1,24 +1,274 @@
-use spatch::diff::{DiffOptions, diff};
+use std::{
+    fs::File,
+    io::{self, IsTerminal, Write},
+};
 
-use crate::cli::{DiffArgs, query::load_json_file};
+use clap::ColorChoice;
+
+use spatch::{
+    diff::{DiffErrorSummary, DiffOptions, Patch, PatchOp, PatchOpRef, PatchSink, diff},
+    document::Document,
+    merge_patch,
+    path::{PathPattern, Segment, Spath},
+    stream::{StreamOptions, diff_readers},
+};
+
+use crate::cli::{
+    DiffArgs, Output,
+    format::{Format, Input, load, to_string, yaml_roots},
+    report::{self, ReportFormat},
+    text,
+};
 
 pub fn handle_diff_command(args: DiffArgs) -> Result<(), Box<dyn std::error::Error>> {
-    let file1 = load_json_file(&args.file1)?;
-    let file2 = load_json_file(&args.file2)?;
-    let schema = if let Some(schema_path) = args.schema {
-        Some(&load_json_file(&schema_path)?)
+    let schema = if let Some(schema_path) = &args.schema {
+        Some(load(Some(schema_path), Format::Auto)?.into_json())
     } else {
         None
     };
 
-    let diff_options = if let Some(schema) = schema {
+    let patterns = |raw: &[String]| -> Result<Vec<PathPattern>, String> {
+        raw.iter()
+            .map(|pattern| {
+                PathPattern::try_from(pattern.as_str())
+                    .map_err(|e| format!("invalid pattern {pattern}: {e}"))
+            })
+            .collect()
+    };
+    let (ignore, only) = (patterns(&args.ignore)?, patterns(&args.only)?);
+    let atomic = patterns(&args.atomic)?;
+
+    let diff_options = if let Some(schema) = &schema {
         DiffOptions::new().with_schema(schema)
     } else {
         DiffOptions::new()
     };
+    let mut diff_options = diff_options.ignore(&ignore).only(&only).atomic(&atomic);
+    if let Some(depth) = args.depth {
+        diff_options = diff_options.max_depth(depth);
+    }
+
+    if args.stream {
+        if args.output != Output::JsonPatch {
+            return Err("--stream only writes JSON Patches".into());
+        }
+        return stream_diff(&args, diff_options);
+    }
+
+    let file1 = load(Some(&args.file1), args.format)?;
+    let file2 = load(Some(&args.file2), args.format)?;
+    if args.output == Output::MergePatch {
+        if !diff_options.path_filter.is_empty() {
+            return Err("--ignore and --only don't apply to merge patches".into());
+        }
+        return merge_patch_diff(file1, file2, schema.as_ref());
+    }
+
+    let (patch, patch_format, left) = match (file1, file2) {
+        // JSON and JSONC documents can be compared with each other
+        (left @ (Input::Json(_) | Input::Jsonc(_)), right @ (Input::Json(_) | Input::Jsonc(_))) => {
+            let (left, right) = (left.into_json(), right.into_json());
+            (diff(&left, &right, diff_options)?, Format::Json, left)
+        }
+        (Input::Yaml(left), Input::Yaml(right)) => {
+            let patch = diff_yaml_streams(&left, &right, diff_options)?;
+            let (left, _) = yaml_roots(left, right);
+            (patch, Format::Yaml, left.to_json().into_owned())
+        }
+        // TOML can't hold a top-level array, nor the nulls a patch may contain
+        (Input::Toml(left), Input::Toml(right)) => (
+            diff(&left, &right, diff_options)?,
+            Format::Json,
+            left.to_json().into_owned(),
+        ),
+        (left, right) => {
+            return Err(format!(
+                "can't diff {} against {}, use --format to read both files the same way",
+                left.format(),
+                right.format()
+            )
+            .into());
+        }
+    };
+
+    if args.stat {
+        println!("{}", serde_json::to_string_pretty(&patch.stats(&left))?);
+        return Ok(());
+    }
+
+    let output = match args.output {
+        Output::Text => text::render(&patch, &left, use_color(args.color)),
+        Output::Markdown => report::render(&patch, &left, ReportFormat::Markdown),
+        Output::Html => report::render(&patch, &left, ReportFormat::Html),
+        _ => to_string(&patch, patch_format)?,
+    };
+    print!("{output}");
+    Ok(())
+}
+
+/// Colors output on terminals, unless `NO_COLOR` is set.
+fn use_color(choice: ColorChoice) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => std::env::var_os("NO_COLOR").is_none() && io::stdout().is_terminal(),
+    }
+}
+
+/// Writes the merge patch between two documents, as YAML for YAML documents
+/// and as JSON otherwise.
+fn merge_patch_diff(
+    file1: Input,
+    file2: Input,
+    schema: Option<&serde_json::Value>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (left, right) = (file1.format(), file2.format());
+    let json_like = |format| matches!(format, Format::Json | Format::Jsonc);
+    if left != right && !(json_like(left) && json_like(right)) {
+        return Err(format!(
+            "can't diff {left} against {right}, use --format to read both files the same way"
+        )
+        .into());
+    }
+    let format = if left == Format::Yaml {
+        Format::Yaml
+    } else {
+        Format::Json
+    };
+
+    let (left, right) = match (file1, file2) {
+        (Input::Yaml(left), Input::Yaml(right)) => {
+            let (left, right) = yaml_roots(left, right);
+            (left.to_json().into_owned(), right.to_json().into_owned())
+        }
+        (left, right) => (left.into_json(), right.into_json()),
+    };
+    let merge = match schema {
+        Some(schema) => merge_patch::diff_with_schema(&left, &right, schema)?,
+        None => merge_patch::diff(&left, &right)?,
+    };
+    print!("{}", to_string(&merge, format)?);
+    Ok(())
+}
+
+/// Diffs the documents of two YAML streams pairwise. Unless both files hold a
+/// single document, paths are prefixed by the document index, e.g.
+/// `/1/spec/replicas`, and documents are added or removed at the end, like
+/// [`yaml_roots`] addresses them.
+pub(super) fn diff_yaml_streams(
+    left: &[serde_yaml::Value],
+    right: &[serde_yaml::Value],
+    diff_options: DiffOptions,
+) -> Result<Patch, DiffErrorSummary> {
+    if let ([left], [right]) = (left, right) {
+        return diff(left, right, diff_options);
+    }
+
+    let document_path = |index: usize| Spath::default().push(Segment::Field(index.to_string()));
+    let mut patch = Patch::default();
+    for (index, (left, right)) in left.iter().zip(right).enumerate() {
+        let prefix = document_path(index);
+        for op in diff(left, right, diff_options)? {
+            patch.push(prefixed(op, &prefix));
+        }
+    }
+    for (index, document) in right.iter().enumerate().skip(left.len()) {
+        patch.push(PatchOp::add(
+            document_path(index),
+            document.to_json().into_owned(),
+        ));
+    }
+    // Removed from the last one, so the remaining indices stay valid
+    for index in (right.len()..left.len()).rev() {
+        patch.push(PatchOp::remove(document_path(index)));
+    }
+    Ok(patch)
+}
+
+fn prefixed(op: PatchOp, prefix: &Spath) -> PatchOp {
+    match op {
+        PatchOp::Add { path, value } => PatchOp::add(prefix.join(&path), value),
+        PatchOp::Remove { path } => PatchOp::remove(prefix.join(&path)),
+        PatchOp::Replace { path, value } => PatchOp::replace(prefix.join(&path), value),
+        PatchOp::Move { from, path } => PatchOp::move_op(prefix.join(&from), prefix.join(&path)),
+        PatchOp::Copy { from, path } => PatchOp::copy(prefix.join(&from), prefix.join(&path)),
+        PatchOp::Test { path, value } => PatchOp::test(prefix.join(&path), value),
+    }
+}
+
+fn stream_diff(
+    args: &DiffArgs,
+    diff_options: DiffOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for file in [&args.file1, &args.file2] {
+        let format = args.format.detect(Some(file));
+        if format != Format::Json {
+            return Err(format!("--stream only supports JSON documents, not {format}").into());
+        }
+    }
 
-    let result = diff(&file1, &file2, diff_options)?;
+    let file1 = File::open(&args.file1)?;
+    let file2 = File::open(&args.file2)?;
 
-    println!("{}", serde_json::to_string_pretty(&result)?);
+    let mut writer = PatchWriter::new(io::stdout().lock());
+    diff_readers(
+        file1,
+        file2,
+        diff_options,
+        StreamOptions::new(),
+        &mut writer,
+    )?;
+    writer.finish()?;
     Ok(())
 }
+
+/// Writes operations as a pretty-printed JSON Patch array as they arrive.
+struct PatchWriter<W> {
+    output: W,
+    written: usize,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> PatchWriter<W> {
+    fn new(output: W) -> Self {
+        PatchWriter {
+            output,
+            written: 0,
+            error: None,
+        }
+    }
+
+    fn write_op(&mut self, op: &PatchOpRef) -> io::Result<()> {
+        let separator = if self.written == 0 { "[\n" } else { ",\n" };
+        self.output.write_all(separator.as_bytes())?;
+
+        let pretty = serde_json::to_string_pretty(op)?;
+        for (i, line) in pretty.lines().enumerate() {
+            if i > 0 {
+                self.output.write_all(b"\n")?;
+            }
+            write!(self.output, "  {line}")?;
+        }
+        self.written += 1;
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        let end = if self.written == 0 { "[]\n" } else { "\n]\n" };
+        self.output.write_all(end.as_bytes())?;
+        self.output.flush()
+    }
+}
+
+impl<'a, W: Write> PatchSink<'a> for PatchWriter<W> {
+    fn push(&mut self, op: PatchOpRef<'a>) {
+        if self.error.is_none()
+            && let Err(error) = self.write_op(&op)
+        {
+            self.error = Some(error);
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use std::{error::Error, ffi::OsStr, fmt, io::Read, path::Path};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use spatch::{cst::CstDocument, document::Document};
+
+/// The format of documents read and written by the CLI.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Detect the format from the file extension, falling back to JSON
+    #[default]
+    Auto,
+    Json,
+    /// JSON with comments and trailing commas
+    Jsonc,
+    Yaml,
+    Toml,
+}
+
+impl Format {
+    /// Resolves [`Format::Auto`] from the extension of `path`. Standard input
+    /// and unknown extensions are read as JSON.
+    pub(super) fn detect(self, path: Option<&Path>) -> Format {
+        if self != Format::Auto {
+            return self;
+        }
+        match path.and_then(Path::extension).and_then(OsStr::to_str) {
+            Some("jsonc") => Format::Jsonc,
+            Some("yaml" | "yml") => Format::Yaml,
+            Some("toml") => Format::Toml,
+            _ => Format::Json,
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Format::Auto => "auto",
+            Format::Json => "JSON",
+            Format::Jsonc => "JSONC",
+            Format::Yaml => "YAML",
+            Format::Toml => "TOML",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A document loaded in its native representation.
+#[derive(Debug)]
+pub(super) enum Input {
+    Json(serde_json::Value),
+    /// A JSONC document, keeping its comments for in-place edits.
+    Jsonc(CstDocument),
+    /// The documents of a YAML stream, in order.
+    Yaml(Vec<serde_yaml::Value>),
+    Toml(toml::Value),
+}
+
+impl Input {
+    pub(super) fn format(&self) -> Format {
+        match self {
+            Input::Json(_) => Format::Json,
+            Input::Jsonc(_) => Format::Jsonc,
+            Input::Yaml(_) => Format::Yaml,
+            Input::Toml(_) => Format::Toml,
+        }
+    }
+
+    /// Converts the document to JSON, e.g. to read a schema or a patch.
+    pub(super) fn into_json(self) -> serde_json::Value {
+        match self {
+            Input::Json(json) => json,
+            Input::Jsonc(doc) => doc.to_value(),
+            Input::Yaml(documents) => yaml_root(documents).to_json().into_owned(),
+            Input::Toml(toml) => toml.to_json().into_owned(),
+        }
+    }
+}
+
+/// Loads a document from `path`, or from stdin when it's `None`.
+pub(super) fn load(path: Option<&Path>, format: Format) -> Result<Input, Box<dyn Error>> {
+    let data = match path {
+        Some(path) => std::fs::read_to_string(path)?,
+        None => {
+            let mut buffer = String::new();
+            std::io::stdin().read_to_string(&mut buffer)?;
+            buffer
+        }
+    };
+    parse(&data, format.detect(path))
+}
+
+/// Loads a strict JSON document along with its text, so it can be edited
+/// without reformatting it.
+pub(super) fn load_json_text(path: &Path) -> Result<CstDocument, Box<dyn Error>> {
+    let data = std::fs::read_to_string(path)?;
+    serde_json::from_str::<serde::de::IgnoredAny>(&data)?;
+    Ok(CstDocument::parse(data)?)
+}
+
+fn parse(data: &str, format: Format) -> Result<Input, Box<dyn Error>> {
+    let input = match format {
+        Format::Auto | Format::Json => Input::Json(serde_json::from_str(data)?),
+        Format::Jsonc => Input::Jsonc(CstDocument::parse(data)?),
+        Format::Yaml => {
+            let mut documents = serde_yaml::Deserializer::from_str(data)
+                .map(serde_yaml::Value::deserialize)
+                .collect::<Result<Vec<_>, _>>()?;
+            if documents.is_empty() {
+                documents.push(serde_yaml::Value::Null);
+            }
+            Input::Yaml(documents)
+        }
+        Format::Toml => Input::Toml(toml::Value::Table(data.parse()?)),
+    };
+    Ok(input)
+}
+
+/// Addresses a single YAML document directly, and the documents of a stream
+/// by their index, e.g. `/1/metadata/name`.
+pub(super) fn yaml_root(mut documents: Vec<serde_yaml::Value>) -> serde_yaml::Value {
+    if documents.len() == 1 {
+        documents.remove(0)
+    } else {
+        serde_yaml::Value::Sequence(documents)
+    }
+}
+
+/// Roots of two YAML streams compared with each other. Documents are only
+/// addressed directly when both streams hold a single one, otherwise both are
+/// addressed by index, so that documents can be added or removed.
+pub(super) fn yaml_roots(
+    left: Vec<serde_yaml::Value>,
+    right: Vec<serde_yaml::Value>,
+) -> (serde_yaml::Value, serde_yaml::Value) {
+    if left.len() == 1 && right.len() == 1 {
+        (yaml_root(left), yaml_root(right))
+    } else {
+        (
+            serde_yaml::Value::Sequence(left),
+            serde_yaml::Value::Sequence(right),
+        )
+    }
+}
+
+/// Formats `value` in `format`. Values TOML can only represent inline, like
+/// scalars and arrays, are written inline.
+pub(super) fn to_string<D: serde::Serialize>(
+    value: &D,
+    format: Format,
+) -> Result<String, Box<dyn Error>> {
+    let output = match format {
+        Format::Auto | Format::Json | Format::Jsonc => serde_json::to_string_pretty(value)? + "\n",
+        Format::Yaml => serde_yaml::to_string(value)?,
+        Format::Toml => match toml::Value::try_from(value)? {
+            toml::Value::Table(table) => toml::to_string(&table)?,
+            inline => format!("{inline}\n"),
+        },
+    };
+    Ok(output)
+}
+
+/// Formats YAML documents as a stream, separated by `---`.
+pub(super) fn yaml_stream(documents: &[serde_yaml::Value]) -> Result<String, Box<dyn Error>> {
+    let documents = documents
+        .iter()
+        .map(serde_yaml::to_string)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(documents.join("---\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use assert2::{assert, check};
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn detect_should_use_the_file_extension() {
+        check!(Format::Auto.detect(Some(Path::new("deploy.yml"))) == Format::Yaml);
+        check!(Format::Auto.detect(Some(Path::new("Cargo.toml"))) == Format::Toml);
+        check!(Format::Auto.detect(Some(Path::new("tsconfig.jsonc"))) == Format::Jsonc);
+        check!(Format::Auto.detect(Some(Path::new("data.txt"))) == Format::Json);
+        check!(Format::Auto.detect(None) == Format::Json);
+        check!(Format::Yaml.detect(Some(Path::new("data.json"))) == Format::Yaml);
+    }
+
+    #[test]
+    fn parse_should_split_yaml_streams_into_documents() {
+        let input = parse("a: 1\n---\nb: 2\n", Format::Yaml).unwrap();
+
+        assert!(let Input::Yaml(documents) = input);
+        check!(documents.len() == 2);
+        check!(yaml_stream(&documents).unwrap() == "a: 1\n---\nb: 2\n");
+    }
+
+    #[test]
+    fn into_json_should_index_yaml_streams_by_document() {
+        let single = parse("a: 1\n", Format::Yaml).unwrap();
+        let stream = parse("a: 1\n---\nb: 2\n", Format::Yaml).unwrap();
+
+        check!(single.into_json() == json!({"a": 1}));
+        check!(stream.into_json() == json!([{"a": 1}, {"b": 2}]));
+    }
+
+    #[test]
+    fn to_string_should_write_toml_scalars_inline() {
+        check!(to_string(&json!({"a": 1}), Format::Toml).unwrap() == "a = 1\n");
+        check!(to_string(&json!("text"), Format::Toml).unwrap() == "\"text\"\n");
+    }
+}
@@ -1,9 +1,15 @@
+pub mod apply;
 pub mod diff;
+pub mod format;
 pub mod query;
+pub mod report;
+pub mod text;
 
 use std::path::PathBuf;
 
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, ColorChoice, Parser, Subcommand, ValueEnum};
+
+use crate::cli::format::Format;
 
 #[derive(Debug, Parser)]
 #[command(name = "spatch", version, about)]
@@ -19,6 +25,9 @@ pub enum Command {
 
     /// Diff two JSON files and output the differences
     Diff(DiffArgs),
+
+    /// Apply a JSON Patch to a JSON file and output the result
+    Apply(ApplyArgs),
 }
 
 #[derive(Debug, Args)]
@@ -32,6 +41,13 @@ pub struct QueryArgs {
 
     /// Path to the JSON file to be processed
     pub file: Option<PathBuf>,
+
+    /// Format of the documents: json, jsonc, yaml, toml, or auto to detect it from the file extension
+    ///
+    /// YAML streams with several documents are addressed by document index, e.g. /1/spec.
+    /// Results are written in the same format as the input.
+    #[arg(short, long, value_enum, default_value_t = Format::Auto)]
+    pub format: Format,
 }
 
 #[derive(Debug, Args)]
@@ -45,4 +61,113 @@ pub struct DiffArgs {
     /// Path to the optional JSON Schema file for validation and generating semantic paths
     #[arg(short, long)]
     pub schema: Option<PathBuf>,
+
+    /// Format of the documents: json, jsonc, yaml, toml, or auto to detect it from the file extension
+    ///
+    /// YAML streams with several documents are addressed by document index, e.g. /1/spec.
+    /// Results are written in the same format as the input.
+    /// Patches are written as YAML for YAML documents, and as JSON otherwise.
+    #[arg(short, long, value_enum, default_value_t = Format::Auto)]
+    pub format: Format,
+
+    /// Kind of patch to write
+    #[arg(short, long, value_enum, default_value_t = Output::JsonPatch)]
+    pub output: Output,
+
+    /// When to color the text output
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+
+    /// Skip changes inside the matching subtrees, e.g. /meta/updatedAt or /items/*/etag
+    ///
+    /// A `*` segment matches any member, array index, or semantic filter. Can be repeated.
+    #[arg(long, value_name = "PATTERN")]
+    pub ignore: Vec<String>,
+
+    /// Only diff the matching subtrees, using the same patterns as --ignore
+    #[arg(long, value_name = "PATTERN")]
+    pub only: Vec<String>,
+
+    /// Replace the matching subtrees as a whole when they differ, using the same patterns as --ignore
+    #[arg(long, value_name = "PATTERN")]
+    pub atomic: Vec<String>,
+
+    /// Stop diffing N levels below the root, replacing differing subtrees there as a whole
+    ///
+    /// Each member name, array index, or keyed array identity is one level.
+    #[arg(long, value_name = "N")]
+    pub depth: Option<usize>,
+
+    /// Stream both files instead of loading them into memory
+    ///
+    /// Only the parts that differ are materialized, and keyed arrays spill to temporary
+    /// files when they are large. Operations are written as soon as they are found,
+    /// in document order, and changed objects are never collapsed into a single replace.
+    #[arg(long)]
+    pub stream: bool,
+
+    /// Write a summary of the patch as JSON instead of the patch
+    ///
+    /// Counts operations by kind and by top-level key, the elements touched in each keyed
+    /// array, and the bytes added and removed.
+    #[arg(long, conflicts_with_all = ["output", "stream"])]
+    pub stat: bool,
+}
+
+/// What `spatch diff` writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Output {
+    /// A JSON Patch (RFC 6902)
+    JsonPatch,
+    /// A JSON Merge Patch (RFC 7396), replacing changed arrays as a whole
+    ///
+    /// With a schema, arrays with an x-spatch-indexKey are merged by identity instead: they
+    /// list a patch for each changed element, and {"id": ..., "$delete": true} for each
+    /// removed one. Fails when the second file sets a member to null, since merge patches
+    /// remove members set to null.
+    MergePatch,
+    /// A colored, unified-diff-like view for reviewers
+    ///
+    /// Changes are grouped by array element identity or by their object, with the old
+    /// values in red, the new ones in green, and a few unchanged members as context.
+    Text,
+    /// A Markdown report for pull requests and release notes
+    ///
+    /// Each changed subtree is a collapsible section, and keyed arrays summarize the
+    /// elements that were added, removed, and modified.
+    Markdown,
+    /// The report of --output markdown, as an HTML fragment
+    Html,
+}
+
+#[derive(Debug, Args)]
+pub struct ApplyArgs {
+    /// Path to the JSON Patch file to apply
+    pub patch: PathBuf,
+
+    /// Path to the JSON file to be patched, reads from stdin when omitted
+    pub file: Option<PathBuf>,
+
+    /// Format of the documents: json, jsonc, yaml, toml, or auto to detect it from the file extension
+    ///
+    /// YAML streams with several documents are addressed by document index, e.g. /1/spec.
+    /// Results are written in the same format as the input.
+    #[arg(short, long, value_enum, default_value_t = Format::Auto)]
+    pub format: Format,
+
+    /// Stream the document instead of loading it into memory
+    ///
+    /// Only the values the patch targets are materialized, and the result is written
+    /// as compact JSON while the document is read. Move and copy operations are not
+    /// supported. If an operation fails, the output is incomplete.
+    #[arg(long, conflicts_with = "in_place")]
+    pub stream: bool,
+
+    /// Write the result back to the patched file instead of printing it
+    ///
+    /// JSON and JSONC files keep their comments and formatting: only the values the patch
+    /// changes are rewritten, and new values follow the file's indentation and line endings.
+    /// The file is left untouched if an operation fails.
+    #[arg(short, long, requires = "file")]
+    pub in_place: bool,
 }
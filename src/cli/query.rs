@@ -1,37 +1,42 @@
-use std::{error::Error, io::Read};
+use std::error::Error;
 
-use spatch::resolve::SerdeValueExt;
+use serde::Serialize;
+use spatch::{
+    document::Document,
+    path::Spath,
+    resolve::{ResolveError, resolve_ref},
+};
 
-use crate::cli::QueryArgs;
+use crate::cli::{
+    QueryArgs,
+    format::{Format, Input, load, to_string, yaml_root},
+};
 
 pub fn handle_query_command(args: QueryArgs) -> Result<(), Box<dyn Error>> {
-    let json = if let Some(file_path) = args.file {
-        load_json_file(&file_path)?
-    } else {
-        read_from_stdin()?
-    };
-
-    json.get_value_at(args.path.as_str())
-        .map(|value| {
-            println!("{}", value);
-        })
-        .map_err(|e| {
-            eprintln!("Error: {}", e);
-            e
-        })?;
-    Ok(())
-    // Implementation for the read command goes here
+    match load(args.file.as_deref(), args.format)? {
+        Input::Json(json) => print_value_at(&json, &args.path, Format::Json),
+        Input::Jsonc(doc) => print_value_at(&doc.to_value(), &args.path, Format::Json),
+        Input::Yaml(documents) => print_value_at(&yaml_root(documents), &args.path, Format::Yaml),
+        Input::Toml(toml) => print_value_at(&toml, &args.path, Format::Toml),
+    }
 }
 
-pub(super) fn load_json_file(path: &std::path::Path) -> Result<serde_json::Value, Box<dyn Error>> {
-    let data = std::fs::read_to_string(path)?;
-    let json: serde_json::Value = serde_json::from_str(&data)?;
-    Ok(json)
-}
+fn print_value_at<D: Document + Serialize>(
+    doc: &D,
+    path: &str,
+    format: Format,
+) -> Result<(), Box<dyn Error>> {
+    let value = Spath::try_from(path)
+        .map_err(ResolveError::from)
+        .and_then(|path| resolve_ref(doc, &path))
+        .inspect_err(|e| eprintln!("Error: {}", e))?;
 
-fn read_from_stdin() -> Result<serde_json::Value, Box<dyn Error>> {
-    let mut buffer = String::new();
-    std::io::stdin().read_to_string(&mut buffer)?;
-    let json: serde_json::Value = serde_json::from_str(&buffer)?;
-    Ok(json)
+    match format {
+        // JSON values are printed compactly, on a single line
+        Format::Auto | Format::Json | Format::Jsonc => {
+            println!("{}", serde_json::to_string(value)?)
+        }
+        _ => print!("{}", to_string(value, format)?),
+    }
+    Ok(())
 }
@@ -0,0 +1,532 @@
+use serde_json::Value;
+use spatch::{
+    diff::{Patch, PatchOp},
+    patch::apply_in_place,
+    path::{Segment, Spath},
+    resolve::resolve_ref,
+};
+
+/// Values longer than this are shortened in reports.
+const VALUE_WIDTH: usize = 80;
+
+/// Changed elements listed in the summary of a keyed array.
+const SUMMARY_ELEMENTS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum ReportFormat {
+    Markdown,
+    Html,
+}
+
+/// Renders `patch` as a report of the changed subtrees, for pull requests and
+/// release notes.
+///
+/// Each changed subtree is a collapsible section listing its changes, with
+/// the old, moved, and copied values resolved from `left` with the preceding
+/// operations applied. Sections of keyed arrays summarize the elements that
+/// were added, removed, and modified.
+pub(super) fn render(patch: &Patch, left: &Value, format: ReportFormat) -> String {
+    let mut root = Node::default();
+    let mut count = 0;
+    let mut doc = left.clone();
+    for op in patch.iter() {
+        // Tests are preconditions, not changes
+        if !matches!(op, PatchOp::Test { .. }) {
+            root.insert(op, change(op, &doc));
+            count += 1;
+        }
+        // Later operations see the document as this one left it
+        let _ = apply_in_place(&mut doc, std::slice::from_ref(op));
+    }
+
+    let items = root.items(&Spath::default());
+    let title = match count {
+        0 => "No changes".to_owned(),
+        1 => "1 change".to_owned(),
+        count => format!("{count} changes"),
+    };
+
+    match format {
+        ReportFormat::Markdown => {
+            let mut out = format!("**{title}**\n\n");
+            markdown(&items, 0, &mut out);
+            out
+        }
+        ReportFormat::Html => {
+            let mut out =
+                format!("<div class=\"spatch-report\">\n<p><strong>{title}</strong></p>\n");
+            html(&items, &mut out);
+            out.push_str("</div>\n");
+            out
+        }
+    }
+}
+
+/// A changed value and the changes below it.
+#[derive(Default)]
+struct Node<'p> {
+    children: Vec<(Segment, Node<'p>)>,
+    /// Operations on the value itself, and elements appended to it.
+    ops: Vec<(&'p PatchOp, Change)>,
+}
+
+/// A section of the report, or a single change.
+enum Item {
+    Section {
+        path: Spath,
+        summary: String,
+        items: Vec<Item>,
+    },
+    Change {
+        path: Spath,
+        change: Change,
+    },
+}
+
+#[derive(Clone)]
+enum Change {
+    Replaced { old: Option<String>, new: String },
+    Added(String),
+    Removed(Option<String>),
+    Moved { from: Spath, value: Option<String> },
+    Copied { from: Spath, value: Option<String> },
+}
+
+impl<'p> Node<'p> {
+    fn insert(&mut self, op: &'p PatchOp, change: Change) {
+        let segments: Vec<&Segment> = op.path().into_iter().collect();
+        // Appended elements belong to their array
+        let segments = match segments.split_last() {
+            Some((Segment::Field(last), parents)) if last == "-" => parents,
+            _ => &segments[..],
+        };
+
+        let mut node = self;
+        for segment in segments {
+            let index = match node.children.iter().position(|(s, _)| s == *segment) {
+                Some(index) => index,
+                None => {
+                    node.children.push(((*segment).clone(), Node::default()));
+                    node.children.len() - 1
+                }
+            };
+            node = &mut node.children[index].1;
+        }
+        node.ops.push((op, change));
+    }
+
+    fn change_count(&self) -> usize {
+        self.ops.len()
+            + self
+                .children
+                .iter()
+                .map(|(_, child)| child.change_count())
+                .sum::<usize>()
+    }
+
+    /// Lists the changes of the node, with a section for each changed child
+    /// that has changes below it. Chains of single children are shown as one
+    /// section.
+    fn items(&self, path: &Spath) -> Vec<Item> {
+        let mut items: Vec<Item> = self.ops.iter().map(change_item).collect();
+
+        for (segment, child) in &self.children {
+            let mut child_path = path.push(segment.clone());
+            let mut child = child;
+            while child.ops.is_empty() && child.children.len() == 1 {
+                let (segment, grandchild) = &child.children[0];
+                child_path = child_path.push(segment.clone());
+                child = grandchild;
+            }
+
+            if child.children.is_empty() {
+                items.extend(child.ops.iter().map(change_item));
+            } else {
+                items.push(Item::Section {
+                    summary: child.summary(),
+                    items: child.items(&child_path),
+                    path: child_path,
+                });
+            }
+        }
+        items
+    }
+
+    /// Counts changed elements by identity for keyed arrays, e.g.
+    /// `1 added, 1 removed, 1 modified: [id=u-2].name`, and changes otherwise.
+    fn summary(&self) -> String {
+        let keyed = self
+            .children
+            .iter()
+            .any(|(segment, _)| matches!(segment, Segment::Filter(_)));
+        let count = self.change_count();
+        if !keyed {
+            return if count == 1 {
+                "1 change".to_owned()
+            } else {
+                format!("{count} changes")
+            };
+        }
+
+        let added = self
+            .ops
+            .iter()
+            .filter(|(op, _)| matches!(op, PatchOp::Add { .. }))
+            .count();
+        let mut removed = 0;
+        let mut modified = Vec::new();
+        for (segment, child) in &self.children {
+            if child
+                .ops
+                .iter()
+                .any(|(op, _)| matches!(op, PatchOp::Remove { .. }))
+            {
+                removed += 1;
+            } else {
+                let element = Spath::default().push(segment.clone()).to_string();
+                modified.extend(child.changed_fields().into_iter().map(|field| {
+                    if field.is_empty() {
+                        element[1..].to_owned()
+                    } else {
+                        format!("{}.{field}", &element[1..])
+                    }
+                }));
+            }
+        }
+        let modified_elements = self.children.len() - removed;
+
+        let mut summary = format!("{added} added, {removed} removed, {modified_elements} modified");
+        if !modified.is_empty() {
+            summary.push_str(": ");
+            summary.push_str(&modified[..modified.len().min(SUMMARY_ELEMENTS)].join(", "));
+            if modified.len() > SUMMARY_ELEMENTS {
+                summary.push('…');
+            }
+        }
+        summary
+    }
+
+    /// The paths of the changes below the node, joined with dots.
+    fn changed_fields(&self) -> Vec<String> {
+        let mut fields = vec![String::new(); self.ops.len()];
+        for (segment, child) in &self.children {
+            let name = Spath::default().push(segment.clone()).to_string()[1..].to_owned();
+            fields.extend(child.changed_fields().into_iter().map(|field| {
+                if field.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{name}.{field}")
+                }
+            }));
+        }
+        fields
+    }
+}
+
+/// Describes `op`, with the values it changes resolved from `doc`, the
+/// document it applies to.
+fn change(op: &PatchOp, doc: &Value) -> Change {
+    let old = || resolve_ref(doc, op.path()).ok().map(short);
+    match op {
+        PatchOp::Replace { value, .. } => Change::Replaced {
+            old: old(),
+            new: short(value),
+        },
+        PatchOp::Add { value, .. } => Change::Added(short(value)),
+        PatchOp::Remove { .. } => Change::Removed(old()),
+        PatchOp::Move { from, .. } => Change::Moved {
+            from: from.clone(),
+            value: resolve_ref(doc, from).ok().map(short),
+        },
+        PatchOp::Copy { from, .. } => Change::Copied {
+            from: from.clone(),
+            value: resolve_ref(doc, from).ok().map(short),
+        },
+        // Tests are never inserted
+        PatchOp::Test { value, .. } => Change::Added(short(value)),
+    }
+}
+
+fn change_item((op, change): &(&PatchOp, Change)) -> Item {
+    Item::Change {
+        path: op.path().clone(),
+        change: change.clone(),
+    }
+}
+
+/// Formats a path, writing the document root as `/`.
+fn path_name(path: &Spath) -> String {
+    if path.is_empty() {
+        "/".to_owned()
+    } else {
+        path.to_string()
+    }
+}
+
+/// Formats a value as compact JSON, shortened when it's long.
+fn short(value: &Value) -> String {
+    let compact = value.to_string();
+    match compact.char_indices().nth(VALUE_WIDTH) {
+        Some((end, _)) => format!("{}…", &compact[..end]),
+        None => compact,
+    }
+}
+
+fn markdown(items: &[Item], depth: usize, out: &mut String) {
+    let indent = "  ".repeat(depth);
+    for item in items {
+        match item {
+            Item::Section {
+                path,
+                summary,
+                items,
+            } => {
+                // HTML blocks need a blank line to end the list before them
+                if !out.ends_with("\n\n") {
+                    out.push('\n');
+                }
+                out.push_str(&format!(
+                    "{indent}<details>\n{indent}<summary><code>{}</code>: {}</summary>\n\n",
+                    escape_html(&path_name(path)),
+                    escape_html(summary)
+                ));
+                markdown(items, depth + 1, out);
+                if !out.ends_with("\n\n") {
+                    out.push('\n');
+                }
+                out.push_str(&format!("{indent}</details>\n\n"));
+            }
+            Item::Change { path, change } => {
+                let path = code(&path_name(path));
+                let line = match change {
+                    Change::Replaced {
+                        old: Some(old),
+                        new,
+                    } => {
+                        format!("{path}: {} → {}", code(old), code(new))
+                    }
+                    Change::Replaced { old: None, new } => format!("{path}: → {}", code(new)),
+                    Change::Added(value) => format!("{path} added: {}", code(value)),
+                    Change::Removed(Some(old)) => format!("{path} removed: {}", code(old)),
+                    Change::Removed(None) => format!("{path} removed"),
+                    Change::Moved { from, value } => moved_line(
+                        &path,
+                        "moved",
+                        &code(&from.to_string()),
+                        value.as_deref().map(code),
+                    ),
+                    Change::Copied { from, value } => moved_line(
+                        &path,
+                        "copied",
+                        &code(&from.to_string()),
+                        value.as_deref().map(code),
+                    ),
+                };
+                out.push_str(&format!("{indent}- {line}\n"));
+            }
+        }
+    }
+}
+
+fn html(items: &[Item], out: &mut String) {
+    out.push_str("<ul>\n");
+    for item in items {
+        match item {
+            Item::Section {
+                path,
+                summary,
+                items,
+            } => {
+                out.push_str(&format!(
+                    "<li><details>\n<summary><code>{}</code>: {}</summary>\n",
+                    escape_html(&path_name(path)),
+                    escape_html(summary)
+                ));
+                html(items, out);
+                out.push_str("</details></li>\n");
+            }
+            Item::Change { path, change } => {
+                let tag = |tag: &str, value: &str| {
+                    format!("<{tag}><code>{}</code></{tag}>", escape_html(value))
+                };
+                let path = format!("<code>{}</code>", escape_html(&path_name(path)));
+                let from =
+                    |from: &Spath| format!("<code>{}</code>", escape_html(&from.to_string()));
+                let line = match change {
+                    Change::Replaced {
+                        old: Some(old),
+                        new,
+                    } => {
+                        format!("{path}: {} → {}", tag("del", old), tag("ins", new))
+                    }
+                    Change::Replaced { old: None, new } => format!("{path}: → {}", tag("ins", new)),
+                    Change::Added(value) => format!("{path} added: {}", tag("ins", value)),
+                    Change::Removed(Some(old)) => format!("{path} removed: {}", tag("del", old)),
+                    Change::Removed(None) => format!("{path} removed"),
+                    Change::Moved {
+                        from: source,
+                        value,
+                    } => moved_line(
+                        &path,
+                        "moved",
+                        &from(source),
+                        value.as_deref().map(|v| tag("ins", v)),
+                    ),
+                    Change::Copied {
+                        from: source,
+                        value,
+                    } => moved_line(
+                        &path,
+                        "copied",
+                        &from(source),
+                        value.as_deref().map(|v| tag("ins", v)),
+                    ),
+                };
+                out.push_str(&format!("<li>{line}</li>\n"));
+            }
+        }
+    }
+    out.push_str("</ul>\n");
+}
+
+fn moved_line(path: &str, verb: &str, from: &str, value: Option<String>) -> String {
+    match value {
+        Some(value) => format!("{path} {verb} from {from}: {value}"),
+        None => format!("{path} {verb} from {from}"),
+    }
+}
+
+/// Formats inline code, with a fence longer than any backticks inside.
+fn code(text: &str) -> String {
+    let mut longest = 0;
+    let mut run = 0;
+    for c in text.chars() {
+        run = if c == '`' { run + 1 } else { 0 };
+        longest = longest.max(run);
+    }
+    let fence = "`".repeat(longest + 1);
+    if longest > 0 {
+        format!("{fence} {text} {fence}")
+    } else {
+        format!("{fence}{text}{fence}")
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use assert2::check;
+    use serde_json::json;
+    use spatch::diff::{DiffOptions, diff};
+
+    use super::*;
+
+    fn documents() -> (Value, Patch) {
+        let schema = json!({"properties": {"users": {"x-spatch-indexKey": "id"}}});
+        let left = json!({
+            "name": "team",
+            "users": [
+                {"id": "u-1", "name": "Ada", "age": 36},
+                {"id": "u-2", "name": "Grace"}
+            ]
+        });
+        let right = json!({
+            "name": "core team",
+            "users": [
+                {"id": "u-1", "name": "Ada Lovelace", "age": 37},
+                {"id": "u-3", "name": "Linus"}
+            ]
+        });
+        let patch = diff(
+            &left,
+            &right,
+            DiffOptions::new().with_schema(&schema).granular(),
+        )
+        .unwrap();
+        (left, patch)
+    }
+
+    #[test]
+    fn render_should_summarize_keyed_arrays_by_element() {
+        let (left, patch) = documents();
+
+        check!(
+            render(&patch, &left, ReportFormat::Markdown)
+                == "\
+**5 changes**
+
+- `/name`: `\"team\"` → `\"core team\"`
+
+<details>
+<summary><code>/users</code>: 1 added, 1 removed, 1 modified: [id=u-1].age, [id=u-1].name</summary>
+
+  - `/users/-` added: `{\"id\":\"u-3\",\"name\":\"Linus\"}`
+  - `/users/[id=u-2]` removed: `{\"id\":\"u-2\",\"name\":\"Grace\"}`
+
+  <details>
+  <summary><code>/users/[id=u-1]</code>: 2 changes</summary>
+
+    - `/users/[id=u-1]/age`: `36` → `37`
+    - `/users/[id=u-1]/name`: `\"Ada\"` → `\"Ada Lovelace\"`
+
+  </details>
+
+</details>
+
+"
+        );
+    }
+
+    #[test]
+    fn render_should_escape_html() {
+        let left = json!({"a": "<b>"});
+        let right = json!({"a": "&"});
+        let patch = diff(&left, &right, DiffOptions::new()).unwrap();
+
+        check!(
+            render(&patch, &left, ReportFormat::Html)
+                == "<div class=\"spatch-report\">\n<p><strong>1 change</strong></p>\n<ul>\n\
+                    <li><code>/a</code>: <del><code>&quot;&lt;b&gt;&quot;</code></del> → \
+                    <ins><code>&quot;&amp;&quot;</code></ins></li>\n</ul>\n</div>\n"
+        );
+    }
+
+    #[test]
+    fn render_should_resolve_old_values_after_the_preceding_operations() {
+        let left = json!(["alpha-long-value-1", "beta-long-value-2", "c"]);
+        let patch = diff(&left, &json!(["c"]), DiffOptions::new().granular()).unwrap();
+
+        check!(
+            render(&patch, &left, ReportFormat::Markdown)
+                == "\
+**2 changes**
+
+- `/0` removed: `\"alpha-long-value-1\"`
+- `/0` removed: `\"beta-long-value-2\"`
+"
+        );
+    }
+
+    #[test]
+    fn render_should_name_the_document_root() {
+        let left = json!({"a": 1});
+        let patch = Patch::new(vec![PatchOp::replace(Spath::default(), json!([1]))]);
+
+        check!(
+            render(&patch, &left, ReportFormat::Markdown)
+                == "**1 change**\n\n- `/`: `{\"a\":1}` → `[1]`\n"
+        );
+    }
+
+    #[test]
+    fn code_should_fence_backticks() {
+        check!(code("a") == "`a`");
+        check!(code("a`b") == "`` a`b ``");
+    }
+}
@@ -0,0 +1,325 @@
+use serde_json::Value;
+use spatch::{
+    diff::{Patch, PatchOp},
+    patch::apply_in_place,
+    path::{Segment, Spath},
+    resolve::resolve_ref,
+};
+
+/// Untouched members shown around the changes of a group, besides identity
+/// keys.
+const CONTEXT_LINES: usize = 3;
+
+/// Values longer than this are pretty-printed over several lines.
+const INLINE_WIDTH: usize = 60;
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const CYAN: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+/// Renders `patch` as a unified-diff-like view of `left`.
+///
+/// Operations are grouped by the array element they change, or by the object
+/// holding the changed member, under a `@@ path @@` header. Each group shows
+/// the identity keys and a few untouched members as context, then the old
+/// values and the new ones. Old values are resolved from `left` with the
+/// preceding operations applied, so they match what each operation changes.
+pub(super) fn render(patch: &Patch, left: &Value, color: bool) -> String {
+    let mut groups: Vec<Group> = Vec::new();
+    let mut doc = left.clone();
+    for op in patch.iter() {
+        // Tests are preconditions, not changes
+        if let PatchOp::Test { .. } = op {
+            continue;
+        }
+        let old = match op {
+            PatchOp::Replace { path, .. } | PatchOp::Remove { path } => {
+                resolve_ref(&doc, path).ok()
+            }
+            PatchOp::Move { from, .. } | PatchOp::Copy { from, .. } => resolve_ref(&doc, from).ok(),
+            PatchOp::Add { .. } | PatchOp::Test { .. } => None,
+        };
+        let change = (op, old.cloned());
+
+        let path = group_of(op.path());
+        match groups.iter_mut().find(|group| group.path == path) {
+            Some(group) => group.changes.push(change),
+            None => groups.push(Group {
+                before: resolve_ref(&doc, &path).ok().cloned(),
+                path,
+                changes: vec![change],
+            }),
+        }
+        // Later operations see the document as this one left it
+        let _ = apply_in_place(&mut doc, std::slice::from_ref(op));
+    }
+
+    let mut out = String::new();
+    for Group {
+        path: group,
+        before,
+        changes,
+    } in groups
+    {
+        let header = if group.is_empty() {
+            "/".to_owned()
+        } else {
+            group.to_string()
+        };
+        push_line(&mut out, &format!("@@ {header} @@"), CYAN, color);
+        let ops: Vec<&PatchOp> = changes.iter().map(|(op, _)| *op).collect();
+        render_context(&group, &ops, before.as_ref(), &mut out);
+
+        for (op, old) in &changes {
+            let label = label(&group, op.path());
+            match op {
+                PatchOp::Replace { value, .. } => {
+                    if let Some(old) = old {
+                        push_value(&mut out, '-', &label, old, color);
+                    }
+                    push_value(&mut out, '+', &label, value, color);
+                }
+                PatchOp::Add { value, .. } => push_value(&mut out, '+', &label, value, color),
+                PatchOp::Remove { .. } => match old {
+                    Some(old) => push_value(&mut out, '-', &label, old, color),
+                    None => push_line(&mut out, &format!("- {label}"), RED, color),
+                },
+                PatchOp::Move { from, .. } | PatchOp::Copy { from, .. } => {
+                    let moved = matches!(op, PatchOp::Move { .. });
+                    match old {
+                        Some(value) => {
+                            if moved {
+                                push_value(&mut out, '-', &self::label(&group, from), value, color);
+                            }
+                            push_value(&mut out, '+', &label, value, color);
+                        }
+                        None => {
+                            let verb = if moved { "moved" } else { "copied" };
+                            push_line(
+                                &mut out,
+                                &format!("+ {label} ({verb} from {from})"),
+                                GREEN,
+                                color,
+                            );
+                        }
+                    }
+                }
+                PatchOp::Test { .. } => {}
+            }
+        }
+    }
+    out
+}
+
+/// Changes shown under one `@@ path @@` header.
+struct Group<'p> {
+    path: Spath,
+
+    /// The value at `path` before the first of the changes.
+    before: Option<Value>,
+
+    /// Operations with the value they replace, remove, move, or copy.
+    changes: Vec<(&'p PatchOp, Option<Value>)>,
+}
+
+/// The element of a keyed array a change is in, or else the parent of the
+/// changed value.
+fn group_of(path: &Spath) -> Spath {
+    let segments: Vec<&Segment> = path.into_iter().collect();
+    let end = match segments
+        .iter()
+        .rposition(|segment| matches!(segment, Segment::Filter(_)))
+    {
+        Some(filter) => filter + 1,
+        None => segments.len().saturating_sub(1),
+    };
+    segments[..end]
+        .iter()
+        .fold(Spath::default(), |group, segment| {
+            group.push((*segment).clone())
+        })
+}
+
+/// The path of a change relative to its group. Appended elements and changes
+/// of the group itself have no label.
+fn label(group: &Spath, path: &Spath) -> String {
+    if !group.is_parent_of(path) {
+        return if path == group {
+            String::new()
+        } else {
+            path.to_string()
+        };
+    }
+    let rest = path
+        .into_iter()
+        .skip(group.into_iter().count())
+        .fold(Spath::default(), |rest, segment| rest.push(segment.clone()))
+        .to_string();
+    match rest.as_str() {
+        "/-" => String::new(),
+        _ => rest[1..].to_owned(),
+    }
+}
+
+/// Writes the identity keys of a keyed element, and a few members the group
+/// doesn't change.
+fn render_context(group: &Spath, ops: &[&PatchOp], before: Option<&Value>, out: &mut String) {
+    // A group that is itself replaced or removed is shown whole
+    if ops.iter().any(|op| op.path() == group) {
+        return;
+    }
+    let Some(Value::Object(members)) = before else {
+        return;
+    };
+    let identity: Vec<&str> = match group.last_segment() {
+        Some(Segment::Filter(conditions)) => {
+            conditions.iter().map(|(key, _)| key.as_str()).collect()
+        }
+        _ => Vec::new(),
+    };
+    let touched = |name: &str| {
+        ops.iter()
+            .flat_map(|op| [Some(op.path()), op.from()])
+            .flatten()
+            .any(|path| label(group, path).split('/').next() == Some(name))
+    };
+
+    let keys = members
+        .iter()
+        .filter(|(name, _)| identity.contains(&name.as_str()));
+    let others = members
+        .iter()
+        .filter(|(name, _)| !identity.contains(&name.as_str()) && !touched(name))
+        .take(CONTEXT_LINES);
+    for (name, value) in keys.chain(others) {
+        let summary = match value {
+            Value::Object(_) => "{…}".to_owned(),
+            Value::Array(_) => "[…]".to_owned(),
+            scalar => scalar.to_string(),
+        };
+        out.push_str(&format!("  {name}: {summary}\n"));
+    }
+}
+
+/// Writes `value` on lines starting with `sign`, pretty-printed when it's
+/// long.
+fn push_value(out: &mut String, sign: char, label: &str, value: &Value, color: bool) {
+    let code = if sign == '-' { RED } else { GREEN };
+    let prefix = if label.is_empty() {
+        format!("{sign} ")
+    } else {
+        format!("{sign} {label}: ")
+    };
+
+    let compact = value.to_string();
+    if compact.chars().count() <= INLINE_WIDTH {
+        push_line(out, &format!("{prefix}{compact}"), code, color);
+        return;
+    }
+    let pretty = serde_json::to_string_pretty(value).unwrap_or(compact);
+    for (index, line) in pretty.lines().enumerate() {
+        if index == 0 {
+            push_line(out, &format!("{prefix}{line}"), code, color);
+        } else {
+            push_line(out, &format!("{sign} {line}"), code, color);
+        }
+    }
+}
+
+fn push_line(out: &mut String, line: &str, code: &str, color: bool) {
+    if color {
+        out.push_str(&format!("{code}{line}{RESET}\n"));
+    } else {
+        out.push_str(line);
+        out.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert2::check;
+    use serde_json::json;
+    use spatch::diff::{DiffOptions, diff};
+
+    use super::*;
+
+    #[test]
+    fn render_should_group_changes_by_element_identity() {
+        let schema = json!({"properties": {"users": {"x-spatch-indexKey": "id"}}});
+        let left = json!({
+            "name": "team",
+            "users": [
+                {"id": "u-1", "name": "Ada", "role": "dev", "age": 36},
+                {"id": "u-2", "name": "Grace"}
+            ]
+        });
+        let right = json!({
+            "name": "core team",
+            "users": [
+                {"id": "u-1", "name": "Ada Lovelace", "role": "dev"},
+                {"id": "u-3", "name": "Linus"}
+            ]
+        });
+        let patch = diff(
+            &left,
+            &right,
+            DiffOptions::new().with_schema(&schema).granular(),
+        )
+        .unwrap();
+
+        check!(
+            render(&patch, &left, false)
+                == "\
+@@ / @@
+  users: […]
+- name: \"team\"
++ name: \"core team\"
+@@ /users/[id=u-2] @@
+- {\"id\":\"u-2\",\"name\":\"Grace\"}
+@@ /users @@
++ {\"id\":\"u-3\",\"name\":\"Linus\"}
+@@ /users/[id=u-1] @@
+  id: \"u-1\"
+  role: \"dev\"
+- name: \"Ada\"
++ name: \"Ada Lovelace\"
+- age: 36
+"
+        );
+    }
+
+    #[test]
+    fn render_should_resolve_old_values_after_the_preceding_operations() {
+        let left = json!(["alpha-long-value-1", "beta-long-value-2", "c"]);
+        let patch = diff(&left, &json!(["c"]), DiffOptions::new().granular()).unwrap();
+
+        check!(
+            render(&patch, &left, false)
+                == "@@ / @@\n- 0: \"alpha-long-value-1\"\n- 0: \"beta-long-value-2\"\n"
+        );
+    }
+
+    #[test]
+    fn render_should_color_old_and_new_values() {
+        let left = json!({"a": 1});
+        let patch = diff(&left, &json!({"a": 2}), DiffOptions::new()).unwrap();
+
+        check!(
+            render(&patch, &left, true)
+                == "\x1b[36m@@ / @@\x1b[0m\n\x1b[31m- a: 1\x1b[0m\n\x1b[32m+ a: 2\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn render_should_pretty_print_long_values() {
+        let left = json!({"a": null});
+        let value = json!({"description": "a value that is too long to fit on one line"});
+        let patch = diff(&left, &json!({"a": value}), DiffOptions::new()).unwrap();
+
+        check!(
+            render(&patch, &left, false)
+                == "@@ / @@\n- a: null\n+ a: {\n+   \"description\": \"a value that is too long to fit on one line\"\n+ }\n"
+        );
+    }
+}
@@ -0,0 +1,292 @@
+//! Patch JSON and JSONC files without reformatting them.
+//!
+//! [`CstDocument`] keeps the source text of a document next to its syntax
+//! tree. Patches are applied to the values, and only the text of values that
+//! changed is rewritten: comments, member order, and whitespace elsewhere are
+//! copied from the original. Comments are accepted anywhere whitespace is,
+//! and arrays and objects may end with a trailing comma.
+//!
+//! ```rust
+//! use serde_json::json;
+//! use spatch::{cst::CstDocument, diff::PatchOp};
+//!
+//! let text = r#"{
+//!   // Listen on all interfaces
+//!   "host": "0.0.0.0",
+//!   "port": 8080,
+//! }"#;
+//!
+//! let doc = CstDocument::parse(text).unwrap();
+//! let patch = [PatchOp::replace("/port".try_into().unwrap(), json!(9090))];
+//!
+//! assert_eq!(
+//!     doc.apply(&patch).unwrap(),
+//!     r#"{
+//!   // Listen on all interfaces
+//!   "host": "0.0.0.0",
+//!   "port": 9090,
+//! }"#
+//! );
+//! ```
+mod parser;
+mod rewrite;
+
+use serde_json::Value;
+
+use crate::{
+    cst::{
+        parser::Node,
+        rewrite::{Rewriter, Style},
+    },
+    diff::PatchOp,
+    patch::PatchError,
+};
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq, Clone)]
+pub enum CstError {
+    #[error("Invalid JSON at line {line}, column {column}: {message}")]
+    Syntax {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+}
+
+impl CstError {
+    fn at(text: &str, offset: usize, message: &str) -> Self {
+        let before = &text[..offset];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        CstError::Syntax {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            message: message.to_owned(),
+        }
+    }
+}
+
+/// A JSON or JSONC document together with its source text.
+#[derive(Debug, Clone)]
+pub struct CstDocument {
+    text: String,
+    root: Node,
+}
+
+impl CstDocument {
+    /// Parses JSON, allowing `//` and `/* */` comments and trailing commas.
+    pub fn parse(text: impl Into<String>) -> Result<Self, CstError> {
+        let text = text.into();
+        let root = parser::parse(&text)?;
+        Ok(CstDocument { text, root })
+    }
+
+    /// The source text of the document.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The value of the document, without its comments.
+    pub fn to_value(&self) -> Value {
+        self.root.to_value()
+    }
+
+    /// Applies `patch` and returns the new text of the document.
+    ///
+    /// The patch is applied like [`patch::apply`](crate::patch::apply) and
+    /// fails the same way. Removed members and elements are dropped along
+    /// with the comments above them, and new ones are added after the last
+    /// member, or where they are inserted in arrays, indented like their
+    /// siblings.
+    pub fn apply(&self, patch: &[PatchOp]) -> Result<String, PatchError> {
+        let patched = crate::patch::apply(&self.to_value(), patch)?;
+        Ok(self.rewrite(&patched))
+    }
+
+    /// Returns the text of the document holding `value` instead, rewriting
+    /// only the values that differ.
+    ///
+    /// New values follow the style of the document: its indentation unit and
+    /// line endings, the spacing around colons and commas of their siblings,
+    /// and single lines in single-line arrays, objects, and documents.
+    pub fn rewrite(&self, value: &Value) -> String {
+        let span = &self.root.span;
+        let rewriter = Rewriter {
+            text: &self.text,
+            style: Style::detect(&self.text),
+        };
+        let mut out = String::with_capacity(self.text.len());
+        out.push_str(&self.text[..span.start]);
+        rewriter.write_node(&self.root, value, false, &mut out);
+        out.push_str(&self.text[span.end..]);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert2::{assert, check};
+    use serde_json::json;
+
+    use super::*;
+    use crate::path::Spath;
+
+    fn path(raw: &str) -> Spath {
+        raw.try_into().unwrap()
+    }
+
+    fn apply(text: &str, patch: &[PatchOp]) -> String {
+        let patched = CstDocument::parse(text).unwrap().apply(patch).unwrap();
+        // The rewritten text holds the patched value
+        check!(
+            CstDocument::parse(patched.as_str()).unwrap().to_value()
+                == crate::patch::apply(&CstDocument::parse(text).unwrap().to_value(), patch)
+                    .unwrap()
+        );
+        patched
+    }
+
+    const CONFIG: &str = r#"// Service settings
+{
+  "name": "api", // shown in dashboards
+  /* ports */
+  "ports": [80, 443],
+  "tls": {
+    "enabled": true,
+  },
+}
+"#;
+
+    #[test]
+    fn parse_should_accept_comments_and_trailing_commas() {
+        let doc = CstDocument::parse(CONFIG).unwrap();
+
+        check!(
+            doc.to_value() == json!({"name": "api", "ports": [80, 443], "tls": {"enabled": true}})
+        );
+        check!(doc.text() == CONFIG);
+    }
+
+    #[test]
+    fn parse_should_report_the_line_and_column_of_errors() {
+        assert!(let Err(CstError::Syntax { line: 2, column: 7, .. }) = CstDocument::parse("{\n  \"a\" 1\n}"));
+        assert!(let Err(CstError::Syntax { .. }) = CstDocument::parse("[1,,2]"));
+        assert!(let Err(CstError::Syntax { .. }) = CstDocument::parse("{\"a\": 1 /* open"));
+        assert!(let Err(CstError::Syntax { .. }) = CstDocument::parse("[1] 2"));
+    }
+
+    #[test]
+    fn apply_should_only_rewrite_changed_values() {
+        let patched = apply(
+            CONFIG,
+            &[PatchOp::replace(path("/tls/enabled"), json!(false))],
+        );
+
+        check!(patched == CONFIG.replace("true", "false"));
+    }
+
+    #[test]
+    fn apply_should_append_members_after_the_last_one() {
+        let patched = apply(
+            CONFIG,
+            &[PatchOp::add(
+                path("/tls/cert"),
+                json!({"path": "/etc/cert.pem"}),
+            )],
+        );
+
+        check!(
+            patched
+                == CONFIG.replace(
+                    "    \"enabled\": true,\n",
+                    "    \"enabled\": true,\n    \"cert\": {\n      \"path\": \"/etc/cert.pem\"\n    },\n"
+                )
+        );
+    }
+
+    #[test]
+    fn apply_should_remove_members_with_their_comments() {
+        let patched = apply(CONFIG, &[PatchOp::remove(path("/ports"))]);
+
+        check!(patched == CONFIG.replace("  /* ports */\n  \"ports\": [80, 443],\n", ""));
+    }
+
+    #[test]
+    fn apply_should_fix_commas_when_removing_the_last_member() {
+        let text = "{\n  \"a\": 1, // first\n  \"b\": 2\n}\n";
+
+        let patched = apply(text, &[PatchOp::remove(path("/b"))]);
+
+        check!(patched == "{\n  \"a\": 1 // first\n}\n");
+    }
+
+    #[test]
+    fn apply_should_keep_single_line_arrays_on_one_line() {
+        let text = r#"{"ports": [80, 443, 8080]}"#;
+
+        check!(apply(text, &[PatchOp::remove(path("/ports/0"))]) == r#"{"ports": [443, 8080]}"#);
+        check!(apply(text, &[PatchOp::remove(path("/ports/2"))]) == r#"{"ports": [80, 443]}"#);
+        check!(
+            apply(text, &[PatchOp::add(path("/ports/1"), json!(81))])
+                == r#"{"ports": [80, 81, 443, 8080]}"#
+        );
+        check!(
+            apply(text, &[PatchOp::add(path("/ports/0"), json!(79))])
+                == r#"{"ports": [79, 80, 443, 8080]}"#
+        );
+    }
+
+    #[test]
+    fn apply_should_match_array_elements_by_filter() {
+        let text =
+            "[\n  {\"id\": \"a\", \"qty\": 2},\n  // second\n  {\"id\": \"b\", \"qty\": 5}\n]";
+
+        let patched = apply(text, &[PatchOp::remove(path("/[id=a]"))]);
+
+        check!(patched == "[\n  // second\n  {\"id\": \"b\", \"qty\": 5}\n]");
+    }
+
+    #[test]
+    fn apply_should_indent_new_values_like_the_document() {
+        let text = "{\r\n\t\"a\": 1\r\n}\r\n";
+
+        let patched = apply(text, &[PatchOp::add(path("/b"), json!({"c": [1]}))]);
+
+        check!(
+            patched
+                == "{\r\n\t\"a\": 1,\r\n\t\"b\": {\r\n\t\t\"c\": [\r\n\t\t\t1\r\n\t\t]\r\n\t}\r\n}\r\n"
+        );
+    }
+
+    #[test]
+    fn apply_should_keep_minified_documents_minified() {
+        let text = r#"{"a":{"b":1},"c":[1,2]}"#;
+
+        let patched = apply(
+            text,
+            &[
+                PatchOp::add(path("/a/d"), json!({"e": [true]})),
+                PatchOp::add(path("/c/-"), json!(3)),
+            ],
+        );
+
+        check!(patched == r#"{"a":{"b":1,"d":{"e":[true]}},"c":[1,2,3]}"#);
+    }
+
+    #[test]
+    fn apply_should_write_values_inline_in_single_line_containers() {
+        let text = "{\n  \"ports\": [80, 443]\n}\n";
+
+        let patched = apply(
+            text,
+            &[PatchOp::add(path("/ports/-"), json!({"port": 8080}))],
+        );
+
+        check!(patched == "{\n  \"ports\": [80, 443, {\"port\": 8080}]\n}\n");
+    }
+
+    #[test]
+    fn apply_should_fail_like_patch_apply() {
+        let doc = CstDocument::parse(CONFIG).unwrap();
+
+        assert!(let Err(_) = doc.apply(&[PatchOp::remove(path("/missing"))]));
+    }
+}
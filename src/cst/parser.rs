@@ -0,0 +1,236 @@
+use std::ops::Range;
+
+use serde_json::Value;
+
+use crate::cst::CstError;
+
+/// A value and its span in the source text.
+#[derive(Debug, Clone)]
+pub(super) struct Node {
+    pub(super) span: Range<usize>,
+    pub(super) kind: Kind,
+}
+
+#[derive(Debug, Clone)]
+pub(super) enum Kind {
+    Scalar(Value),
+    Array(Vec<Item>),
+    Object(Vec<Item>),
+}
+
+/// An array element or an object member.
+#[derive(Debug, Clone)]
+pub(super) struct Item {
+    /// The member name, for object members.
+    pub(super) key: Option<String>,
+    /// Where the item starts: at its key for members, at its value for
+    /// elements.
+    pub(super) start: usize,
+    pub(super) value: Node,
+    /// Position of the comma following the item, if any.
+    pub(super) comma: Option<usize>,
+}
+
+impl Node {
+    pub(super) fn to_value(&self) -> Value {
+        match &self.kind {
+            Kind::Scalar(value) => value.clone(),
+            Kind::Array(items) => {
+                Value::Array(items.iter().map(|item| item.value.to_value()).collect())
+            }
+            Kind::Object(items) => Value::Object(
+                items
+                    .iter()
+                    .map(|item| (item.key.clone().unwrap_or_default(), item.value.to_value()))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Compares the node with `value` without converting it.
+    pub(super) fn matches(&self, value: &Value) -> bool {
+        match (&self.kind, value) {
+            (Kind::Scalar(scalar), _) => scalar == value,
+            (Kind::Array(items), Value::Array(values)) => {
+                items.len() == values.len()
+                    && items
+                        .iter()
+                        .zip(values)
+                        .all(|(item, value)| item.value.matches(value))
+            }
+            (Kind::Object(items), Value::Object(map)) => {
+                items.len() == map.len()
+                    && items.iter().all(|item| {
+                        let key = item.key.as_deref().unwrap_or_default();
+                        map.get(key).is_some_and(|value| item.value.matches(value))
+                    })
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Parses `text` as JSON with comments and trailing commas. Returns the root
+/// node, whose span excludes the surrounding whitespace and comments.
+pub(super) fn parse(text: &str) -> Result<Node, CstError> {
+    let mut parser = Parser { text, pos: 0 };
+    parser.skip_trivia()?;
+    let root = parser.value()?;
+    parser.skip_trivia()?;
+    if parser.pos < text.len() {
+        return Err(parser.error("unexpected characters after the document"));
+    }
+    Ok(root)
+}
+
+struct Parser<'t> {
+    text: &'t str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    fn error(&self, message: &str) -> CstError {
+        CstError::at(self.text, self.pos, message)
+    }
+
+    /// Skips whitespace, `// line` comments, and `/* block */` comments.
+    fn skip_trivia(&mut self) -> Result<(), CstError> {
+        loop {
+            let rest = &self.text[self.pos..];
+            if rest.starts_with("//") {
+                self.pos += rest.find('\n').unwrap_or(rest.len());
+            } else if let Some(comment) = rest.strip_prefix("/*") {
+                let end = comment
+                    .find("*/")
+                    .ok_or_else(|| self.error("unterminated block comment"))?;
+                self.pos += end + 4;
+            } else if let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+                self.pos += 1;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Node, CstError> {
+        let start = self.pos;
+        let kind = match self.peek() {
+            Some(b'{') => Kind::Object(self.items(b'}')?),
+            Some(b'[') => Kind::Array(self.items(b']')?),
+            Some(b'"') => Kind::Scalar(Value::String(self.string()?)),
+            Some(b'-' | b'0'..=b'9') => Kind::Scalar(self.number()?),
+            Some(b't' | b'f' | b'n') => Kind::Scalar(self.literal()?),
+            _ => return Err(self.error("expected a value")),
+        };
+        Ok(Node {
+            span: start..self.pos,
+            kind,
+        })
+    }
+
+    /// Parses the items of an array or an object, up to `close`.
+    fn items(&mut self, close: u8) -> Result<Vec<Item>, CstError> {
+        let is_object = close == b'}';
+        self.pos += 1;
+        let mut items = Vec::new();
+
+        loop {
+            self.skip_trivia()?;
+            if self.peek() == Some(close) {
+                self.pos += 1;
+                return Ok(items);
+            }
+
+            let start = self.pos;
+            let key = if is_object {
+                if self.peek() != Some(b'"') {
+                    return Err(self.error("expected a member name"));
+                }
+                let key = self.string()?;
+                self.skip_trivia()?;
+                if self.peek() != Some(b':') {
+                    return Err(self.error("expected `:`"));
+                }
+                self.pos += 1;
+                self.skip_trivia()?;
+                Some(key)
+            } else {
+                None
+            };
+            let value = self.value()?;
+
+            self.skip_trivia()?;
+            let comma = match self.peek() {
+                Some(b',') => {
+                    self.pos += 1;
+                    Some(self.pos - 1)
+                }
+                Some(byte) if byte == close => None,
+                _ => {
+                    return Err(self.error(if is_object {
+                        "expected `,` or `}`"
+                    } else {
+                        "expected `,` or `]`"
+                    }));
+                }
+            };
+            items.push(Item {
+                key,
+                start,
+                value,
+                comma,
+            });
+        }
+    }
+
+    fn string(&mut self) -> Result<String, CstError> {
+        let start = self.pos;
+        let bytes = self.text.as_bytes();
+        let mut pos = start + 1;
+        loop {
+            match bytes.get(pos) {
+                Some(b'"') => break,
+                Some(b'\\') => pos += 2,
+                Some(_) => pos += 1,
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+        self.pos = pos + 1;
+        serde_json::from_str(&self.text[start..self.pos])
+            .map_err(|e| CstError::at(self.text, start, &e.to_string()))
+    }
+
+    fn number(&mut self) -> Result<Value, CstError> {
+        let start = self.pos;
+        let len = self.text[start..]
+            .find(|c: char| !matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E'))
+            .unwrap_or(self.text.len() - start);
+        self.pos += len;
+        match serde_json::from_str(&self.text[start..self.pos]) {
+            Ok(number @ Value::Number(_)) => Ok(number),
+            _ => Err(CstError::at(self.text, start, "invalid number")),
+        }
+    }
+
+    fn literal(&mut self) -> Result<Value, CstError> {
+        let rest = &self.text[self.pos..];
+        let (value, len) = [
+            ("true", Value::Bool(true)),
+            ("false", Value::Bool(false)),
+            ("null", Value::Null),
+        ]
+        .into_iter()
+        .find(|(word, _)| {
+            rest.starts_with(word)
+                && !rest[word.len()..].starts_with(|c: char| c.is_ascii_alphanumeric())
+        })
+        .map(|(word, value)| (value, word.len()))
+        .ok_or_else(|| self.error("expected a value"))?;
+        self.pos += len;
+        Ok(value)
+    }
+}
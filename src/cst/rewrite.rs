@@ -0,0 +1,379 @@
+use serde::Serialize;
+use serde_json::{Map, Value, ser::PrettyFormatter};
+
+use crate::cst::parser::{Item, Kind, Node};
+
+/// How new values are laid out, detected from the existing text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Style {
+    /// One level of indentation, such as two spaces or a tab.
+    pub(super) indent: String,
+    pub(super) newline: &'static str,
+    /// Whether the document is written on a single line.
+    pub(super) compact: bool,
+}
+
+impl Style {
+    pub(super) fn detect(text: &str) -> Self {
+        let newline = if text.contains("\r\n") { "\r\n" } else { "\n" };
+        let indents = text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| &line[..line.len() - line.trim_start_matches([' ', '\t']).len()]);
+
+        // The smallest indentation is one level, unless tabs are used
+        let mut indent: Option<&str> = None;
+        for line_indent in indents {
+            if line_indent.starts_with('\t') {
+                indent = Some("\t");
+                break;
+            }
+            if !line_indent.is_empty()
+                && indent.is_none_or(|indent| line_indent.len() < indent.len())
+            {
+                indent = Some(line_indent);
+            }
+        }
+
+        Style {
+            indent: indent.unwrap_or("  ").to_owned(),
+            newline,
+            compact: !text.trim_end().contains('\n'),
+        }
+    }
+}
+
+/// Writes nodes with new values, copying the text of everything that didn't
+/// change.
+pub(super) struct Rewriter<'t> {
+    pub(super) text: &'t str,
+    pub(super) style: Style,
+}
+
+/// An item of a rewritten container.
+enum Planned<'n, 'v> {
+    /// An existing item, with its new value.
+    Old(usize, &'n Item, &'v Value),
+    /// A new item, rendered without indentation.
+    New(String),
+}
+
+/// Where a container's items are, and how they're separated.
+struct Layout<'t> {
+    /// Whether items start on lines of their own.
+    own_line: bool,
+    /// The indentation of new items on lines of their own.
+    indent: &'t str,
+    /// The text between a member's name and its value.
+    colon: &'t str,
+    /// The text between items on the same line.
+    gap: &'t str,
+}
+
+impl Rewriter<'_> {
+    /// Writes `node` holding `value`. Values of nodes inside single-line
+    /// containers are written on a single line too.
+    pub(super) fn write_node(&self, node: &Node, value: &Value, inline: bool, out: &mut String) {
+        if node.matches(value) {
+            out.push_str(&self.text[node.span.clone()]);
+            return;
+        }
+
+        match (&node.kind, value) {
+            (Kind::Object(items), Value::Object(map)) if !items.is_empty() => {
+                let layout = self.layout(node, items);
+                let planned = self.plan_object(items, map, &layout);
+                self.write_container(node, items, planned, &layout, out);
+            }
+            (Kind::Array(items), Value::Array(values)) if !items.is_empty() => {
+                let layout = self.layout(node, items);
+                let planned = self.plan_array(items, values, &layout);
+                self.write_container(node, items, planned, &layout, out);
+            }
+            _ => out.push_str(&self.render(value, self.line_indent(node.span.start), inline)),
+        }
+    }
+
+    fn layout(&self, node: &Node, items: &[Item]) -> Layout<'_> {
+        let text = self.text;
+        let own_line = text[node.span.start + 1..items[0].start].contains('\n');
+        let last = &items[items.len() - 1];
+
+        let colon = match &last.key {
+            Some(_) => {
+                let between = &text[last.start..last.value.span.start];
+                // The name ends at its closing quote
+                &between[between.rfind('"').map_or(0, |quote| quote + 1)..]
+            }
+            None => "",
+        };
+        let gap = match items {
+            [.., before, last] => {
+                let after = before
+                    .comma
+                    .map_or(before.value.span.end, |comma| comma + 1);
+                &text[after..last.start]
+            }
+            _ if self.style.compact => "",
+            _ => " ",
+        };
+
+        Layout {
+            own_line,
+            indent: self.line_indent(last.start),
+            colon: if colon.contains(':') && !colon.contains('/') {
+                colon
+            } else {
+                ": "
+            },
+            gap: if gap.contains(['\n', '/']) { " " } else { gap },
+        }
+    }
+
+    /// Keeps the members still present, in their order, and appends new ones.
+    fn plan_object<'n, 'v>(
+        &self,
+        items: &'n [Item],
+        map: &'v Map<String, Value>,
+        layout: &Layout,
+    ) -> Vec<Planned<'n, 'v>> {
+        let mut planned: Vec<_> = items
+            .iter()
+            .enumerate()
+            .filter_map(|(index, item)| {
+                let value = map.get(item.key.as_deref().unwrap_or_default())?;
+                Some(Planned::Old(index, item, value))
+            })
+            .collect();
+
+        for (name, value) in map {
+            if !items.iter().any(|item| item.key.as_deref() == Some(name)) {
+                let name = Value::String(name.clone());
+                let value = self.render(value, layout.indent, !layout.own_line);
+                planned.push(Planned::New(format!("{name}{}{value}", layout.colon)));
+            }
+        }
+        planned
+    }
+
+    /// Keeps the elements at both ends that didn't change, and pairs up the
+    /// rest by position. Extra elements are removed or inserted where the
+    /// pairs end.
+    fn plan_array<'n, 'v>(
+        &self,
+        items: &'n [Item],
+        values: &'v [Value],
+        layout: &Layout,
+    ) -> Vec<Planned<'n, 'v>> {
+        let prefix = items
+            .iter()
+            .zip(values)
+            .take_while(|(item, value)| item.value.matches(value))
+            .count();
+        let suffix = items[prefix..]
+            .iter()
+            .rev()
+            .zip(values[prefix..].iter().rev())
+            .take_while(|(item, value)| item.value.matches(value))
+            .count();
+        let old = prefix..items.len() - suffix;
+        let new = prefix..values.len() - suffix;
+        let paired = old.len().min(new.len());
+
+        let mut planned: Vec<_> = (0..old.start + paired)
+            .map(|index| Planned::Old(index, &items[index], &values[index]))
+            .collect();
+        planned.extend(
+            values[new.start + paired..new.end]
+                .iter()
+                .map(|value| Planned::New(self.render(value, layout.indent, !layout.own_line))),
+        );
+        planned.extend((old.end..items.len()).map(|index| {
+            let value = &values[index + values.len() - items.len()];
+            Planned::Old(index, &items[index], value)
+        }));
+        planned
+    }
+
+    /// Writes a container, taking each kept item with the comments and line
+    /// around it, and fixing up commas.
+    fn write_container(
+        &self,
+        node: &Node,
+        items: &[Item],
+        planned: Vec<Planned>,
+        layout: &Layout,
+        out: &mut String,
+    ) {
+        let text = self.text;
+        let open = node.span.start;
+        let close = node.span.end - 1;
+        let own_line = layout.own_line;
+
+        // Each item owns the text from the end of the previous one up to the
+        // end of its own line, so comments above an item go with it.
+        let head_end = if own_line {
+            open + 1 + text[open + 1..].find('\n').unwrap_or_default() + 1
+        } else {
+            open + 1
+        };
+        let ends: Vec<usize> = items.iter().map(|item| self.tail_end(item)).collect();
+        let chunk_start = |index: usize| {
+            if index == 0 {
+                head_end
+            } else {
+                ends[index - 1]
+            }
+        };
+        let trailing_comma = items.last().is_some_and(|item| item.comma.is_some());
+        let count = planned.len();
+
+        out.push_str(&text[open..head_end]);
+        for (position, planned) in planned.into_iter().enumerate() {
+            let needs_comma = position + 1 < count || trailing_comma;
+            match planned {
+                Planned::Old(index, item, value) => {
+                    let prefix = match (own_line, position, index) {
+                        (false, 0, 1..) => &text[chunk_start(0)..items[0].start],
+                        (false, 1.., 0) => layout.gap,
+                        _ => &text[chunk_start(index)..item.start],
+                    };
+                    out.push_str(prefix);
+                    out.push_str(&text[item.start..item.value.span.start]);
+                    self.write_node(&item.value, value, !own_line, out);
+
+                    let end = item.value.span.end;
+                    match item.comma {
+                        Some(comma) => {
+                            out.push_str(&text[end..comma]);
+                            if needs_comma {
+                                out.push(',');
+                            }
+                            out.push_str(&text[comma + 1..ends[index]]);
+                        }
+                        None => {
+                            if needs_comma {
+                                out.push(',');
+                            }
+                            out.push_str(&text[end..ends[index]]);
+                        }
+                    }
+                }
+                Planned::New(body) => {
+                    if own_line {
+                        if !out.ends_with('\n') {
+                            out.push_str(self.style.newline);
+                        }
+                        out.push_str(layout.indent);
+                    } else if position > 0 {
+                        out.push_str(layout.gap);
+                    } else {
+                        out.push_str(&text[chunk_start(0)..items[0].start]);
+                    }
+                    out.push_str(&body);
+                    if needs_comma {
+                        out.push(',');
+                    }
+                    if own_line {
+                        out.push_str(self.style.newline);
+                    }
+                }
+            }
+        }
+        out.push_str(&text[ends[items.len() - 1]..close]);
+        out.push_str(&text[close..=close]);
+    }
+
+    /// Returns the end of an item's text: after its comma, and after the rest
+    /// of its line when only a comment follows.
+    fn tail_end(&self, item: &Item) -> usize {
+        let text = self.text;
+        let base = item.comma.map_or(item.value.span.end, |comma| comma + 1);
+        let mut pos = base;
+        pos += text[pos..].len() - text[pos..].trim_start_matches([' ', '\t']).len();
+
+        let rest = &text[pos..];
+        if rest.starts_with("//") {
+            pos += rest.find('\n').unwrap_or(rest.len());
+        } else if rest.starts_with("/*")
+            && let Some(end) = rest.find("*/")
+            && !rest[..end].contains('\n')
+        {
+            pos += end + 2;
+            pos += text[pos..].len() - text[pos..].trim_start_matches([' ', '\t']).len();
+        }
+
+        let rest = &text[pos..];
+        if rest.starts_with("\r\n") {
+            pos + 2
+        } else if rest.starts_with('\n') {
+            pos + 1
+        } else {
+            base
+        }
+    }
+
+    /// The indentation of the line `pos` is on.
+    fn line_indent(&self, pos: usize) -> &str {
+        let line_start = self.text[..pos]
+            .rfind('\n')
+            .map_or(0, |newline| newline + 1);
+        let line = &self.text[line_start..];
+        &line[..line.len() - line.trim_start_matches([' ', '\t']).len()]
+    }
+
+    /// Renders a new value in the document's style, indenting its lines after
+    /// the first by `indent`.
+    fn render(&self, value: &Value, indent: &str, inline: bool) -> String {
+        if self.style.compact {
+            return value.to_string();
+        }
+        if inline {
+            return render_inline(value);
+        }
+
+        let mut pretty = Vec::new();
+        let formatter = PrettyFormatter::with_indent(self.style.indent.as_bytes());
+        let mut serializer = serde_json::Serializer::with_formatter(&mut pretty, formatter);
+        // Values always serialize, and to valid UTF-8
+        value.serialize(&mut serializer).unwrap_or_default();
+        String::from_utf8(pretty)
+            .unwrap_or_default()
+            .replace('\n', &format!("{}{indent}", self.style.newline))
+    }
+}
+
+/// Renders a value on a single line, with spaces after colons and commas.
+fn render_inline(value: &Value) -> String {
+    match value {
+        Value::Array(items) => {
+            let items: Vec<_> = items.iter().map(render_inline).collect();
+            format!("[{}]", items.join(", "))
+        }
+        Value::Object(map) => {
+            let members: Vec<_> = map
+                .iter()
+                .map(|(name, value)| {
+                    format!("{}: {}", Value::String(name.clone()), render_inline(value))
+                })
+                .collect();
+            format!("{{{}}}", members.join(", "))
+        }
+        scalar => scalar.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert2::check;
+
+    use super::*;
+
+    #[test]
+    fn detect_should_find_the_indentation_unit() {
+        check!(Style::detect("{\n    \"a\": {\n        \"b\": 1\n    }\n}\n").indent == "    ");
+        check!(Style::detect("{\n\t\"a\": 1\n}").indent == "\t");
+        check!(Style::detect("{\r\n  \"a\": 1\r\n}\r\n").newline == "\r\n");
+        check!(Style::detect("{\"a\": 1}\n").compact);
+    }
+}
@@ -0,0 +1,299 @@
+use std::fmt;
+
+use serde_json::{Number, Value};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::path::PathPattern;
+
+/// Name of the schema property customizing how a subtree is compared.
+pub(super) const COMPARE_PROP_NAME: &str = "x-spatch-compare";
+
+/// Name of the schema property giving the tolerance of `numeric-tolerance`
+/// comparisons.
+pub(super) const TOLERANCE_PROP_NAME: &str = "x-spatch-tolerance";
+
+/// Tolerance of `numeric-tolerance` comparisons without an
+/// `x-spatch-tolerance`.
+const DEFAULT_TOLERANCE: f64 = 1e-9;
+
+/// How the values of a subtree are compared, from its `x-spatch-compare`
+/// schema annotation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Comparison {
+    /// Arrays are equal when they hold the same elements, in any order.
+    Unordered,
+
+    /// Numbers are equal when they differ by at most the tolerance.
+    NumericTolerance(f64),
+
+    /// Strings are equal when they only differ in case.
+    CaseInsensitive,
+}
+
+/// Decides whether two differing values are equal anyway, so diffs leave out
+/// differences that don't matter, like `1` and `1.0` or trailing whitespace.
+///
+/// spatch compares objects and arrays member by member and element by
+/// element. The comparator is consulted for the values in between: pairs of
+/// strings, numbers, booleans, or `null`, and values of different types. It's
+/// only called when the values aren't already equal.
+///
+/// Comparators are set for a whole diff with
+/// [`DiffOptions::with_comparator`](crate::diff::DiffOptions::with_comparator),
+/// or for the subtrees matched by path patterns with
+/// [`DiffOptions::with_path_comparators`](crate::diff::DiffOptions::with_path_comparators).
+///
+/// ```rust
+/// use serde_json::{json, Value};
+/// use spatch::diff::{diff, DiffOptions, ValueComparator};
+///
+/// /// Compares version strings without their `v` prefix.
+/// #[derive(Debug)]
+/// struct Version;
+///
+/// impl ValueComparator for Version {
+///     fn equal(&self, left: &Value, right: &Value) -> bool {
+///         match (left.as_str(), right.as_str()) {
+///             (Some(left), Some(right)) => left.trim_start_matches('v') == right.trim_start_matches('v'),
+///             _ => false,
+///         }
+///     }
+/// }
+///
+/// let before = json!({"version": "v1.2", "name": "api"});
+/// let after = json!({"version": "1.2", "name": "web"});
+///
+/// let patch = diff(&before, &after, DiffOptions::new().granular().with_comparator(&Version))
+///     .unwrap();
+///
+/// assert_eq!(patch.len(), 1);
+/// ```
+pub trait ValueComparator: fmt::Debug {
+    /// Returns `true` when `left` and `right` should be considered equal.
+    fn equal(&self, left: &Value, right: &Value) -> bool;
+}
+
+/// Considers numbers equal when they differ by at most the epsilon.
+///
+/// ```rust
+/// use serde_json::json;
+/// use spatch::diff::{NumericEpsilon, ValueComparator};
+///
+/// assert!(NumericEpsilon(1e-6).equal(&json!(0.1), &json!(0.1000000001)));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NumericEpsilon(pub f64);
+
+impl ValueComparator for NumericEpsilon {
+    fn equal(&self, left: &Value, right: &Value) -> bool {
+        match (left.as_f64(), right.as_f64()) {
+            (Some(left), Some(right)) => (left - right).abs() <= self.0,
+            _ => false,
+        }
+    }
+}
+
+/// Considers numbers equal when they have the same value, regardless of
+/// whether they are written as integers or floats, like `1` and `1.0`.
+///
+/// ```rust
+/// use serde_json::json;
+/// use spatch::diff::{NumericTypeInsensitive, ValueComparator};
+///
+/// assert!(NumericTypeInsensitive.equal(&json!(1), &json!(1.0)));
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NumericTypeInsensitive;
+
+impl ValueComparator for NumericTypeInsensitive {
+    fn equal(&self, left: &Value, right: &Value) -> bool {
+        match (left, right) {
+            (Value::Number(left), Value::Number(right)) => {
+                // Integers too large for a float are compared exactly
+                let integer =
+                    |number: &Number| number.as_i64().is_some() || number.as_u64().is_some();
+                if integer(left) && integer(right) {
+                    return left == right;
+                }
+                left.as_f64() == right.as_f64()
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Considers strings equal when they have the same Unicode normalization
+/// form C, like a precomposed `é` and an `e` followed by a combining accent.
+///
+/// ```rust
+/// use serde_json::json;
+/// use spatch::diff::{UnicodeNormalized, ValueComparator};
+///
+/// assert!(UnicodeNormalized.equal(&json!("caf\u{e9}"), &json!("cafe\u{301}")));
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UnicodeNormalized;
+
+impl ValueComparator for UnicodeNormalized {
+    fn equal(&self, left: &Value, right: &Value) -> bool {
+        match (left, right) {
+            (Value::String(left), Value::String(right)) => left.nfc().eq(right.nfc()),
+            _ => false,
+        }
+    }
+}
+
+/// Considers strings equal when they only differ in leading and trailing
+/// whitespace.
+///
+/// ```rust
+/// use serde_json::json;
+/// use spatch::diff::{TrimmedStrings, ValueComparator};
+///
+/// assert!(TrimmedStrings.equal(&json!("Ada "), &json!("Ada")));
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrimmedStrings;
+
+impl ValueComparator for TrimmedStrings {
+    fn equal(&self, left: &Value, right: &Value) -> bool {
+        match (left, right) {
+            (Value::String(left), Value::String(right)) => left.trim() == right.trim(),
+            _ => false,
+        }
+    }
+}
+
+/// Considers values equal when any of the comparators does.
+///
+/// ```rust
+/// use serde_json::json;
+/// use spatch::diff::{TrimmedStrings, UnicodeNormalized, ValueComparator};
+///
+/// let comparator: [&dyn ValueComparator; 2] = [&TrimmedStrings, &UnicodeNormalized];
+///
+/// assert!(comparator.equal(&json!(" Ada"), &json!("Ada")));
+/// ```
+impl<const N: usize> ValueComparator for [&dyn ValueComparator; N] {
+    fn equal(&self, left: &Value, right: &Value) -> bool {
+        self.iter().any(|comparator| comparator.equal(left, right))
+    }
+}
+
+/// A comparator for the subtrees matched by a pattern, see
+/// [`DiffOptions::with_path_comparators`](crate::diff::DiffOptions::with_path_comparators).
+pub type PathComparator<'a> = (PathPattern, &'a dyn ValueComparator);
+
+impl Comparison {
+    /// Reads the comparison named by an `x-spatch-compare` annotation.
+    pub(super) fn from_annotation(name: &str, tolerance: Option<f64>) -> Option<Self> {
+        match name {
+            "unordered" => Some(Comparison::Unordered),
+            "numeric-tolerance" => Some(Comparison::NumericTolerance(
+                tolerance.unwrap_or(DEFAULT_TOLERANCE),
+            )),
+            "case-insensitive" => Some(Comparison::CaseInsensitive),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` when `left` and `right` are equal under this comparison.
+    pub(crate) fn equivalent(self, left: &Value, right: &Value) -> bool {
+        match (left, right) {
+            (Value::Number(_), Value::Number(_)) => match self {
+                Comparison::NumericTolerance(tolerance) => {
+                    left == right || NumericEpsilon(tolerance).equal(left, right)
+                }
+                _ => left == right,
+            },
+            (Value::String(left), Value::String(right)) => match self {
+                Comparison::CaseInsensitive => left.to_lowercase() == right.to_lowercase(),
+                _ => left == right,
+            },
+            (Value::Array(left), Value::Array(right)) => {
+                if left.len() != right.len() {
+                    return false;
+                }
+                match self {
+                    Comparison::Unordered => {
+                        // Matches each element with the first unmatched
+                        // equivalent one
+                        let mut unmatched: Vec<&Value> = right.iter().collect();
+                        left.iter().all(|element| {
+                            match unmatched
+                                .iter()
+                                .position(|other| self.equivalent(element, other))
+                            {
+                                Some(position) => {
+                                    unmatched.swap_remove(position);
+                                    true
+                                }
+                                None => false,
+                            }
+                        })
+                    }
+                    _ => left
+                        .iter()
+                        .zip(right)
+                        .all(|(left, right)| self.equivalent(left, right)),
+                }
+            }
+            (Value::Object(left), Value::Object(right)) => {
+                left.len() == right.len()
+                    && left.iter().all(|(key, left)| {
+                        right
+                            .get(key)
+                            .is_some_and(|right| self.equivalent(left, right))
+                    })
+            }
+            (left, right) => left == right,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert2::check;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn unordered_should_ignore_the_order_of_nested_arrays() {
+        let left = json!({"tags": ["a", "b", "a"], "n": [[1, 2]]});
+        let right = json!({"tags": ["a", "a", "b"], "n": [[2, 1]]});
+
+        check!(Comparison::Unordered.equivalent(&left, &right));
+        check!(!Comparison::Unordered.equivalent(&json!(["a", "b"]), &json!(["a", "a"])));
+    }
+
+    #[test]
+    fn numeric_tolerance_should_compare_numbers_within_the_tolerance() {
+        let comparison = Comparison::NumericTolerance(0.01);
+
+        check!(comparison.equivalent(&json!([1.0, 2]), &json!([1.005, 2.0])));
+        check!(!comparison.equivalent(&json!(1.0), &json!(1.02)));
+        check!(!comparison.equivalent(&json!(1), &json!("1")));
+    }
+
+    #[test]
+    fn case_insensitive_should_compare_strings_ignoring_case() {
+        check!(Comparison::CaseInsensitive.equivalent(&json!({"a": "Ada"}), &json!({"a": "ADA"})));
+        check!(!Comparison::CaseInsensitive.equivalent(&json!({"A": "a"}), &json!({"a": "a"})));
+    }
+
+    #[test]
+    fn numeric_type_insensitive_should_compare_large_integers_exactly() {
+        check!(NumericTypeInsensitive.equal(&json!(2), &json!(2.0)));
+        check!(!NumericTypeInsensitive.equal(&json!(u64::MAX), &json!(u64::MAX - 1)));
+        check!(!NumericTypeInsensitive.equal(&json!(1), &json!("1")));
+    }
+
+    #[test]
+    fn string_comparators_should_only_compare_strings() {
+        check!(TrimmedStrings.equal(&json!("\tAda\n"), &json!("Ada")));
+        check!(!TrimmedStrings.equal(&json!("A da"), &json!("Ada")));
+        check!(!UnicodeNormalized.equal(&json!("e"), &json!("\u{e9}")));
+        check!(!UnicodeNormalized.equal(&json!(1), &json!(1.0)));
+    }
+}
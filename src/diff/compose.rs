@@ -0,0 +1,344 @@
+use serde_json::Value;
+
+use crate::{
+    diff::{Patch, PatchOp},
+    patch::PatchError,
+    path::{Segment, Spath},
+};
+
+impl Patch {
+    /// Composes two sequential patches into a single, folded patch.
+    ///
+    /// The result is equivalent to applying `first` and then `second`, with
+    /// operations on the same path folded together:
+    ///
+    /// - `add` followed by `replace` becomes a single `add` with the new value;
+    /// - `add` followed by `remove` disappears entirely;
+    /// - repeated `replace` operations collapse into the last one;
+    /// - operations on children of a path that is later replaced or removed
+    ///   are dropped.
+    ///
+    /// Folding never crosses an operation that could change what a path refers
+    /// to, such as an insert into the same array or a `move` out of the path.
+    /// Because `add` on an existing object member overwrites it, an `add`
+    /// followed by a `remove` is only guaranteed to disappear when the member
+    /// did not exist before. Use [`squash`](Self::squash) when a base document
+    /// is available and the result must be proven equivalent.
+    ///
+    /// ```rust
+    /// use serde_json::json;
+    /// use spatch::diff::{Patch, PatchOp};
+    ///
+    /// let first = Patch::new(vec![PatchOp::add("/a".try_into().unwrap(), json!(1))]);
+    /// let second = Patch::new(vec![PatchOp::replace("/a".try_into().unwrap(), json!(2))]);
+    ///
+    /// let composed = Patch::compose(first, second);
+    ///
+    /// assert_eq!(*composed, vec![PatchOp::add("/a".try_into().unwrap(), json!(2))]);
+    /// ```
+    pub fn compose(first: Patch, second: Patch) -> Patch {
+        Patch::new(fold(first.0.into_iter().chain(second.0)))
+    }
+
+    /// Folds this patch into a minimal equivalent patch for `base`.
+    ///
+    /// The folding rules are the same as for [`compose`](Self::compose). The
+    /// folded patch is applied to `base` alongside the original one, and it is
+    /// only returned when both produce the same document. Otherwise the
+    /// original patch is returned unchanged.
+    ///
+    /// Returns an error when the original patch cannot be applied to `base`.
+    ///
+    /// ```rust
+    /// use serde_json::json;
+    /// use spatch::diff::{Patch, PatchOp};
+    ///
+    /// let base = json!({"a": 1});
+    /// let patch = Patch::new(vec![
+    ///     PatchOp::replace("/a".try_into().unwrap(), json!(2)),
+    ///     PatchOp::replace("/a".try_into().unwrap(), json!(3)),
+    /// ]);
+    ///
+    /// let squashed = patch.squash(&base).unwrap();
+    ///
+    /// assert_eq!(*squashed, vec![PatchOp::replace("/a".try_into().unwrap(), json!(3))]);
+    /// ```
+    pub fn squash(&self, base: &Value) -> Result<Patch, PatchError> {
+        let expected = crate::patch::apply(base, self)?;
+        let squashed = Patch::new(fold(self.0.iter().cloned()));
+
+        match crate::patch::apply(base, &squashed) {
+            Ok(actual) if actual == expected => Ok(squashed),
+            _ => Ok(self.clone()),
+        }
+    }
+}
+
+fn fold(ops: impl IntoIterator<Item = PatchOp>) -> Vec<PatchOp> {
+    let mut folded = Vec::new();
+    for op in ops {
+        if let Some(op) = fold_into(&mut folded, op) {
+            folded.push(op);
+        }
+    }
+    folded
+}
+
+/// Folds `op` into the already folded operations. Returns the operation back
+/// when it still has to be appended.
+fn fold_into(folded: &mut Vec<PatchOp>, op: PatchOp) -> Option<PatchOp> {
+    let path = match &op {
+        PatchOp::Add { path, .. } | PatchOp::Replace { path, .. } | PatchOp::Remove { path } => {
+            path.clone()
+        }
+        _ => return Some(op),
+    };
+    let overwrites = !matches!(op, PatchOp::Add { .. });
+
+    let mut i = folded.len();
+    while i > 0 {
+        i -= 1;
+        let previous = &folded[i];
+        let is_write = matches!(
+            previous,
+            PatchOp::Add { .. } | PatchOp::Replace { .. } | PatchOp::Remove { .. }
+        );
+
+        if is_write && overwrites && path.is_parent_of(previous.path()) {
+            folded.remove(i);
+            continue;
+        }
+
+        if is_write && previous.path() == &path {
+            return merge(folded, i, op);
+        }
+
+        if ops_interfere(previous, &path) {
+            break;
+        }
+    }
+
+    Some(op)
+}
+
+fn merge(folded: &mut Vec<PatchOp>, index: usize, op: PatchOp) -> Option<PatchOp> {
+    if is_append_token(op.path()) {
+        return Some(op);
+    }
+
+    match (&folded[index], op) {
+        (PatchOp::Add { .. }, PatchOp::Replace { path, value }) => {
+            folded[index] = PatchOp::add(path, value);
+            None
+        }
+        (PatchOp::Replace { .. }, PatchOp::Replace { path, value }) => {
+            folded[index] = PatchOp::replace(path, value);
+            None
+        }
+        (PatchOp::Add { .. }, PatchOp::Remove { .. }) => {
+            folded.remove(index);
+            None
+        }
+        (PatchOp::Replace { .. }, op @ PatchOp::Remove { .. }) => {
+            folded.remove(index);
+            Some(op)
+        }
+        (PatchOp::Remove { .. }, PatchOp::Add { path, value }) => {
+            folded[index] = PatchOp::replace(path, value);
+            None
+        }
+        (_, op) => Some(op),
+    }
+}
+
+/// Returns `true` when `op` reads or writes a location that could change what
+/// `path` refers to, so operations cannot be folded or reordered across it.
+pub(super) fn ops_interfere(op: &PatchOp, path: &Spath) -> bool {
+    paths_interfere(op.path(), path) || op.from().is_some_and(|from| paths_interfere(from, path))
+}
+
+pub(super) fn paths_interfere(a: &Spath, b: &Spath) -> bool {
+    a == b || a.is_parent_of(b) || b.is_parent_of(a) || shifts_siblings(a, b)
+}
+
+/// Array inserts and removals shift the indexes of their siblings.
+fn shifts_siblings(a: &Spath, b: &Spath) -> bool {
+    a.parent() == b.parent() && (is_index_token(a) || is_index_token(b))
+}
+
+fn is_index_token(path: &Spath) -> bool {
+    matches!(
+        path.last_segment(),
+        Some(Segment::Field(field)) if field == "-" || field.parse::<usize>().is_ok()
+    )
+}
+
+fn is_append_token(path: &Spath) -> bool {
+    matches!(path.last_segment(), Some(Segment::Field(field)) if field == "-")
+}
+
+#[cfg(test)]
+mod tests {
+    use assert2::{assert, check};
+    use serde_json::json;
+
+    use super::*;
+
+    fn path(raw: &str) -> Spath {
+        raw.try_into().unwrap()
+    }
+
+    #[test]
+    fn compose_should_fold_add_followed_by_replace_into_add() {
+        let first = Patch::new(vec![PatchOp::add(path("/a"), json!(1))]);
+        let second = Patch::new(vec![PatchOp::replace(path("/a"), json!(2))]);
+
+        let composed = Patch::compose(first, second);
+
+        check!(composed == Patch::new(vec![PatchOp::add(path("/a"), json!(2))]));
+    }
+
+    #[test]
+    fn compose_should_drop_add_followed_by_remove() {
+        let first = Patch::new(vec![
+            PatchOp::replace(path("/b"), json!(1)),
+            PatchOp::add(path("/a"), json!(1)),
+        ]);
+        let second = Patch::new(vec![PatchOp::remove(path("/a"))]);
+
+        let composed = Patch::compose(first, second);
+
+        check!(composed == Patch::new(vec![PatchOp::replace(path("/b"), json!(1))]));
+    }
+
+    #[test]
+    fn compose_should_collapse_repeated_replaces() {
+        let first = Patch::new(vec![
+            PatchOp::replace(path("/a"), json!(1)),
+            PatchOp::replace(path("/b"), json!(1)),
+        ]);
+        let second = Patch::new(vec![
+            PatchOp::replace(path("/a"), json!(2)),
+            PatchOp::replace(path("/a"), json!(3)),
+        ]);
+
+        let composed = Patch::compose(first, second);
+
+        check!(
+            composed
+                == Patch::new(vec![
+                    PatchOp::replace(path("/a"), json!(3)),
+                    PatchOp::replace(path("/b"), json!(1)),
+                ])
+        );
+    }
+
+    #[test]
+    fn compose_should_drop_children_of_a_later_replaced_parent() {
+        let first = Patch::new(vec![
+            PatchOp::replace(path("/a/x"), json!(1)),
+            PatchOp::add(path("/a/y"), json!(2)),
+            PatchOp::replace(path("/b"), json!(3)),
+        ]);
+        let second = Patch::new(vec![PatchOp::replace(path("/a"), json!({"z": 1}))]);
+
+        let composed = Patch::compose(first, second);
+
+        check!(
+            composed
+                == Patch::new(vec![
+                    PatchOp::replace(path("/b"), json!(3)),
+                    PatchOp::replace(path("/a"), json!({"z": 1})),
+                ])
+        );
+    }
+
+    #[test]
+    fn compose_should_not_fold_across_shifting_array_operations() {
+        let first = Patch::new(vec![
+            PatchOp::replace(path("/items/1"), json!("a")),
+            PatchOp::add(path("/items/0"), json!("b")),
+        ]);
+        let second = Patch::new(vec![PatchOp::replace(path("/items/1"), json!("c"))]);
+
+        let composed = Patch::compose(first.clone(), second.clone());
+
+        check!(composed == first + second);
+    }
+
+    #[test]
+    fn compose_should_not_fold_across_tests_on_the_same_path() {
+        let first = Patch::new(vec![
+            PatchOp::replace(path("/a"), json!(1)),
+            PatchOp::test(path("/a"), json!(1)),
+        ]);
+        let second = Patch::new(vec![PatchOp::replace(path("/a"), json!(2))]);
+
+        let composed = Patch::compose(first.clone(), second.clone());
+
+        check!(composed == first + second);
+    }
+
+    #[test]
+    fn compose_should_fold_semantic_paths() {
+        let first = Patch::new(vec![PatchOp::replace(
+            path("/users/[id=u-1]/name"),
+            json!("Ada"),
+        )]);
+        let second = Patch::new(vec![PatchOp::remove(path("/users/[id=u-1]"))]);
+
+        let composed = Patch::compose(first, second);
+
+        check!(composed == Patch::new(vec![PatchOp::remove(path("/users/[id=u-1]"))]));
+    }
+
+    #[test]
+    fn squash_should_return_an_equivalent_folded_patch() {
+        let base = json!({"a": {"x": 1}, "items": [1, 2]});
+        let patch = Patch::new(vec![
+            PatchOp::replace(path("/a/x"), json!(2)),
+            PatchOp::add(path("/b"), json!(1)),
+            PatchOp::replace(path("/b"), json!(2)),
+            PatchOp::replace(path("/a"), json!({"y": 1})),
+            PatchOp::add(path("/items/-"), json!(3)),
+        ]);
+
+        assert!(let Ok(squashed) = patch.squash(&base));
+
+        check!(
+            squashed
+                == Patch::new(vec![
+                    PatchOp::add(path("/b"), json!(2)),
+                    PatchOp::replace(path("/a"), json!({"y": 1})),
+                    PatchOp::add(path("/items/-"), json!(3)),
+                ])
+        );
+        check!(
+            crate::patch::apply(&base, &squashed).unwrap()
+                == crate::patch::apply(&base, &patch).unwrap()
+        );
+    }
+
+    #[test]
+    fn squash_should_keep_the_original_patch_when_folding_changes_the_result() {
+        // `add` overwrites the existing member, so dropping `add` + `remove`
+        // would leave `/a` in place.
+        let base = json!({"a": 1});
+        let patch = Patch::new(vec![
+            PatchOp::add(path("/a"), json!(2)),
+            PatchOp::remove(path("/a")),
+        ]);
+
+        assert!(let Ok(squashed) = patch.squash(&base));
+
+        check!(squashed == patch);
+    }
+
+    #[test]
+    fn squash_should_fail_when_the_patch_does_not_apply() {
+        let base = json!({"a": 1});
+        let patch = Patch::new(vec![PatchOp::remove(path("/missing"))]);
+
+        assert!(let Err(PatchError::MultipleErrors(_)) = patch.squash(&base));
+    }
+}
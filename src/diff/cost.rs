@@ -0,0 +1,240 @@
+use std::{fmt, io};
+
+use crate::{diff::OpKind, document::Document, path::Spath};
+
+/// Estimates how expensive an operation is, so compact diffs can decide
+/// whether a parent `replace` is cheaper than the nested operations it
+/// replaces.
+///
+/// Compact mode collapses an object change into a single `replace` when the
+/// cost of that `replace` is strictly lower than the summed cost of the
+/// nested operations. spatch computes serialized value lengths bottom-up while
+/// diffing and passes them in as `value_len`, so cost models never need to
+/// serialize values themselves and compaction stays linear in the size of the
+/// documents.
+///
+/// ```rust
+/// use serde_json::json;
+/// use spatch::diff::{diff, DiffOptions, OpCount};
+///
+/// let before = json!({"settings": {"a": 1, "b": 2}});
+/// let after = json!({"settings": {"a": 10, "b": 20}});
+///
+/// let patch = diff(&before, &after, DiffOptions::new().with_cost_model(&OpCount)).unwrap();
+///
+/// assert_eq!(patch.len(), 1);
+/// ```
+pub trait CostModel: fmt::Debug {
+    /// Returns the cost of a single operation.
+    ///
+    /// `from` is set for `move` and `copy` operations. `value_len` is the
+    /// length of the operation's serialized `value`, or `0` for operations
+    /// without a value.
+    fn op_cost(&self, kind: OpKind, path: &Spath, from: Option<&Spath>, value_len: usize) -> usize;
+}
+
+/// Measures operations by their serialized size in bytes.
+///
+/// This is the default cost model. The cost of an operation is the length of
+/// its compact JSON serialization plus one byte for the separating comma, so
+/// compact mode picks whichever alternative serializes shorter.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ByteSize;
+
+impl CostModel for ByteSize {
+    fn op_cost(&self, kind: OpKind, path: &Spath, from: Option<&Spath>, value_len: usize) -> usize {
+        // {"op":"<kind>","path":<path>}
+        let mut len = 6 + kind.as_str().len() + 2 + 8 + path_len(path) + 1;
+        if let Some(from) = from {
+            // ,"from":<from>
+            len += 8 + path_len(from);
+        }
+        if matches!(kind, OpKind::Add | OpKind::Replace | OpKind::Test) {
+            // ,"value":<value>
+            len += 9 + value_len;
+        }
+        // The comma separating operations in the patch array.
+        len + 1
+    }
+}
+
+/// Counts operations, ignoring their size.
+///
+/// With this model compact mode collapses an object change whenever a single
+/// `replace` replaces more than one nested operation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OpCount;
+
+impl CostModel for OpCount {
+    fn op_cost(&self, _: OpKind, _: &Spath, _: Option<&Spath>, _: usize) -> usize {
+        1
+    }
+}
+
+/// Weighs operations by kind and by the size of their value.
+///
+/// The cost of an operation is the weight of its kind plus
+/// `per_value_byte * value_len`. For example, a model that makes `replace`
+/// operations expensive keeps diffs granular unless many fields change:
+///
+/// ```rust
+/// use spatch::diff::{DiffOptions, WeightedCost};
+///
+/// let model = WeightedCost {
+///     replace: 10,
+///     ..WeightedCost::default()
+/// };
+/// let options = DiffOptions::new().with_cost_model(&model);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WeightedCost {
+    pub add: usize,
+    pub remove: usize,
+    pub replace: usize,
+    pub move_op: usize,
+    pub copy: usize,
+    pub test: usize,
+    pub per_value_byte: usize,
+}
+
+impl Default for WeightedCost {
+    /// Weighs every operation as `1` and ignores value sizes.
+    fn default() -> Self {
+        Self {
+            add: 1,
+            remove: 1,
+            replace: 1,
+            move_op: 1,
+            copy: 1,
+            test: 1,
+            per_value_byte: 0,
+        }
+    }
+}
+
+impl CostModel for WeightedCost {
+    fn op_cost(&self, kind: OpKind, _: &Spath, _: Option<&Spath>, value_len: usize) -> usize {
+        let weight = match kind {
+            OpKind::Add => self.add,
+            OpKind::Remove => self.remove,
+            OpKind::Replace => self.replace,
+            OpKind::Move => self.move_op,
+            OpKind::Copy => self.copy,
+            OpKind::Test => self.test,
+        };
+        weight + self.per_value_byte * value_len
+    }
+}
+
+/// Length of the compact JSON serialization of `value`.
+pub(crate) fn json_len<D: Document>(value: &D) -> usize {
+    if let Some(items) = value.elements() {
+        return container_len(items.len()) + items.iter().map(json_len).sum::<usize>();
+    }
+    if let Some(len) = value.object_len() {
+        // "key":value
+        let members = value
+            .members()
+            .map(|(key, member)| string_len(&key) + 1 + json_len(member));
+        return container_len(len) + members.sum::<usize>();
+    }
+
+    let mut counter = ByteCounter(0);
+    serde_json::to_writer(&mut counter, &*value.to_json())
+        .expect("serializing a JSON value cannot fail");
+    counter.0
+}
+
+/// Serialized length of the brackets and separators of a container with `len`
+/// members.
+pub(crate) fn container_len(len: usize) -> usize {
+    2 + len.saturating_sub(1)
+}
+
+/// Length of `value` serialized as a JSON string, including the quotes.
+pub(crate) fn string_len(value: &str) -> usize {
+    let mut counter = ByteCounter(0);
+    serde_json::to_writer(&mut counter, value).expect("serializing a string cannot fail");
+    counter.0
+}
+
+fn path_len(path: &Spath) -> usize {
+    string_len(&path.to_string())
+}
+
+struct ByteCounter(usize);
+
+impl io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert2::check;
+    use serde_json::json;
+
+    use crate::diff::{Patch, PatchOp};
+
+    use super::*;
+
+    fn path(raw: &str) -> Spath {
+        raw.try_into().unwrap()
+    }
+
+    #[test]
+    fn byte_size_should_match_the_serialized_patch_length() {
+        let value = json!({"a": [1, "two", null], "b\"": true});
+        let ops = vec![
+            PatchOp::add(path("/a/b"), value.clone()),
+            PatchOp::remove(path("/a/[id=x]")),
+            PatchOp::replace(path(""), value.clone()),
+            PatchOp::move_op(path("/a"), path("/b")),
+            PatchOp::copy(path("/a"), path("/b/c")),
+            PatchOp::test(path("/a"), json!("ü")),
+        ];
+
+        for op in ops {
+            let value_len = match &op {
+                PatchOp::Add { value, .. }
+                | PatchOp::Replace { value, .. }
+                | PatchOp::Test { value, .. } => json_len(value),
+                _ => 0,
+            };
+            let serialized = serde_json::to_vec(&Patch::new(vec![op.clone()])).unwrap();
+
+            // Patch arrays add the brackets, a single operation has no comma.
+            check!(
+                ByteSize.op_cost(op.kind(), op.path(), op.from(), value_len)
+                    == serialized.len() - 1,
+                "{op:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn json_len_should_match_serialized_length() {
+        let value = json!({"key": ["a\nb", 1.5, -3, {"nested": "\u{1F600}"}]});
+
+        check!(json_len(&value) == serde_json::to_vec(&value).unwrap().len());
+        check!(string_len("a\"b") == 6);
+    }
+
+    #[test]
+    fn weighted_cost_should_add_per_byte_weight() {
+        let model = WeightedCost {
+            replace: 5,
+            per_value_byte: 2,
+            ..WeightedCost::default()
+        };
+
+        check!(model.op_cost(OpKind::Replace, &path("/a"), None, 3) == 11);
+        check!(model.op_cost(OpKind::Remove, &path("/a"), None, 0) == 1);
+    }
+}
@@ -1,14 +1,17 @@
-use std::collections::{HashMap, HashSet, hash_map::Entry};
+use std::collections::{BTreeMap, btree_map::Entry};
 
 use serde_json::Value;
 
 use crate::{
     diff::{
-        DiffOptions,
+        DiffOptions, OpKind, PatchOpRef, PatchSink,
+        cost::{container_len, json_len, string_len},
         error::{DiffError, DiffErrorSummary},
-        options::DiffGranularity,
+        moves::{DetectedMoves, detect_moves},
+        options::{DiffGranularity, TestMode},
     },
-    path::Spath,
+    document::Document,
+    path::{Segment, Spath},
 };
 
 use super::Patch;
@@ -17,229 +20,576 @@ use super::Patch;
 /// It's used to identify unique items in an array for diffing purposes.
 pub(super) const HASH_KEY_PROP_NAME: &str = "x-spatch-indexKey";
 
-pub(super) fn diff_recursive(
-    left: &serde_json::Value,
-    right: &serde_json::Value,
+pub(super) fn diff_recursive<D: Document>(
+    left: &D,
+    right: &D,
     options: DiffOptions,
     path_pos: &Spath,
-    patch_ops: &Patch,
 ) -> (Patch, DiffErrorSummary) {
-    match (left, right) {
-        (Value::Object(left_map), Value::Object(right_map)) => {
-            diff_object(left_map, right_map, options, path_pos, patch_ops)
+    let mut patch = Patch::default();
+    // Applies the annotations of the schema itself, like the ones of child
+    // schemas
+    let options = options.with_optional_schema(options.schema);
+    let errors = diff_into_sink(left, right, options, path_pos, &mut patch);
+    (patch, errors)
+}
+
+/// Diffs `left` against `right`, emitting operations into `sink` as soon as
+/// they are final.
+///
+/// `options` are used as they are: callers diffing whole documents apply the
+/// annotations of the root schema first, with
+/// [`DiffOptions::with_optional_schema`].
+pub(crate) fn diff_into_sink<'a, D: Document>(
+    left: &'a D,
+    right: &'a D,
+    options: DiffOptions,
+    path_pos: &Spath,
+    sink: &mut dyn PatchSink<'a>,
+) -> DiffErrorSummary {
+    let mut emitter = Emitter::new(sink);
+    diff_node(left, right, options, path_pos, &mut emitter);
+    emitter.errors
+}
+
+/// Serialized lengths of a pair of diffed subtrees.
+///
+/// Lengths are accumulated bottom-up, so compact mode can price a parent
+/// `replace` without serializing anything again.
+struct Diffed {
+    left_len: usize,
+    right_len: usize,
+}
+
+/// Forwards operations to the sink, holding them back only while an enclosing
+/// object may still collapse them into a single `replace`.
+struct Emitter<'a, 's> {
+    sink: &'s mut dyn PatchSink<'a>,
+    errors: DiffErrorSummary,
+    /// Operations of open compaction scopes that are not final yet.
+    pending: Vec<PatchOpRef<'a>>,
+    /// Number of open compaction scopes.
+    scopes: usize,
+    /// Number of outermost open scopes that can no longer be compacted,
+    /// because they contain a pinned operation.
+    settled: usize,
+    /// Total cost of the emitted operations.
+    cost: usize,
+    /// Number of emitted operations compaction must keep: operations with a
+    /// semantic path, and operations of granular subtrees.
+    pinned: usize,
+    /// Number of differences left out of the patch, because of the path
+    /// filter or of schema annotations.
+    skipped: usize,
+    /// Identity guards of the keyed elements being diffed, emitted right
+    /// before the first operation inside the element.
+    guards: Vec<(PatchOpRef<'a>, usize)>,
+    guards_emitted: usize,
+}
+
+/// State of the [`Emitter`] when a compaction scope was opened.
+struct Scope {
+    pending: usize,
+    cost: usize,
+    pinned: usize,
+    skipped: usize,
+}
+
+impl<'a, 's> Emitter<'a, 's> {
+    fn new(sink: &'s mut dyn PatchSink<'a>) -> Self {
+        Emitter {
+            sink,
+            errors: DiffErrorSummary::empty(),
+            pending: Vec::new(),
+            scopes: 0,
+            settled: 0,
+            cost: 0,
+            pinned: 0,
+            skipped: 0,
+            guards: Vec::new(),
+            guards_emitted: 0,
+        }
+    }
+
+    /// Emits an operation. `value_len` is the serialized length of its value.
+    fn emit(&mut self, op: PatchOpRef<'a>, value_len: usize, options: DiffOptions) {
+        if self.skip(&op, options) {
+            return;
+        }
+        while let Some((guard, guard_len)) = self.guards.get(self.guards_emitted).cloned() {
+            self.guards_emitted += 1;
+            self.forward(guard, guard_len, options);
+        }
+        self.forward(op, value_len, options);
+    }
+
+    fn forward(&mut self, op: PatchOpRef<'a>, value_len: usize, options: DiffOptions) {
+        self.cost += options
+            .cost_model
+            .op_cost(op.kind(), op.path(), op.from(), value_len);
+
+        if patch_op_contains_semantic_path(&op) || options.granularity == DiffGranularity::Granular
+        {
+            // Compaction never drops semantic paths, nor the operations of a
+            // subtree that asked for a granular diff, so none of the open
+            // scopes can collapse anymore.
+            self.pinned += 1;
+            self.settled = self.scopes;
+            self.flush();
+        }
+
+        if self.scopes == self.settled {
+            self.sink.push(op);
+        } else {
+            self.pending.push(op);
+        }
+    }
+
+    /// Emits a `replace`, `remove`, or `move` operation, preceded by a `test`
+    /// asserting the `old` value when the configured [`TestMode`] asks for it.
+    /// For `move`, the source value is asserted.
+    fn emit_guarded<D: Document>(
+        &mut self,
+        op: PatchOpRef<'a>,
+        old: &'a D,
+        old_len: usize,
+        value_len: usize,
+        options: DiffOptions,
+    ) {
+        if self.skip(&op, options) {
+            return;
+        }
+        if asserts_old_value(options, old.is_object() || old.is_array()) {
+            let path = op.from().unwrap_or_else(|| op.path()).clone();
+            let test = PatchOpRef::Test {
+                path,
+                value: old.to_json(),
+            };
+            self.emit(test, old_len, options);
+        }
+        self.emit(op, value_len, options);
+    }
+
+    /// Returns `true`, and records the change as skipped, when the path filter
+    /// drops `op`.
+    fn skip(&mut self, op: &PatchOpRef, options: DiffOptions) -> bool {
+        let filter = options.path_filter;
+        if filter.includes(op.path()) && op.from().is_none_or(|from| filter.includes(from)) {
+            return false;
+        }
+        self.skipped += 1;
+        true
+    }
+
+    /// Opens a scope whose operations may later be replaced by a single
+    /// operation.
+    fn open(&mut self) -> Scope {
+        self.scopes += 1;
+        Scope {
+            pending: self.pending.len(),
+            cost: self.cost,
+            pinned: self.pinned,
+            skipped: self.skipped,
+        }
+    }
+
+    /// Returns `true` when the scope can still be compacted. A `replace` of
+    /// the whole scope would bring back the changes the path filter skipped.
+    fn is_compactable(&self, scope: &Scope) -> bool {
+        self.pinned == scope.pinned && self.skipped == scope.skipped
+    }
+
+    /// Drops the operations emitted since the scope was opened. Only valid for
+    /// compactable scopes.
+    fn discard(&mut self, scope: &Scope) {
+        self.pending.truncate(scope.pending);
+        self.cost = scope.cost;
+    }
+
+    fn close(&mut self) {
+        if self.settled == self.scopes {
+            self.settled -= 1;
+        }
+        self.scopes -= 1;
+        if self.scopes == self.settled {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        for op in self.pending.drain(..) {
+            self.sink.push(op);
+        }
+    }
+
+    /// Registers a `test` of the identity key of a keyed array element in
+    /// [`TestMode::IdentityOnly`]. The `test` is only emitted once an
+    /// operation inside the element is. Returns `true` when a guard was
+    /// registered and has to be released with [`release_guard`](Self::release_guard).
+    ///
+    /// Identity guards always carry a semantic path, so a scope that emitted
+    /// one is never compacted and the guard is never discarded.
+    fn guard_identity<D: Document>(
+        &mut self,
+        item: &'a D,
+        index_key: &str,
+        item_path: &Spath,
+        options: DiffOptions,
+    ) -> bool {
+        match (options.test_mode, item.member(index_key)) {
+            (TestMode::IdentityOnly, Some(identity)) => {
+                let test = PatchOpRef::Test {
+                    path: item_path.push(Segment::Field(index_key.to_owned())),
+                    value: identity.to_json(),
+                };
+                self.guards.push((test, json_len(identity)));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn release_guard(&mut self) {
+        self.guards.pop();
+        self.guards_emitted = self.guards_emitted.min(self.guards.len());
+    }
+}
+
+fn diff_node<'a, D: Document>(
+    left: &'a D,
+    right: &'a D,
+    options: DiffOptions,
+    path_pos: &Spath,
+    emitter: &mut Emitter<'a, '_>,
+) -> Diffed {
+    let options = options.with_schema_comparison();
+
+    // Subtrees the path filter or the schema excludes are not diffed at all,
+    // and neither are values the schema's comparison deems equal
+    let skip = !options.path_filter.includes_below(path_pos)
+        || options.is_ignored()
+        || options
+            .comparison
+            .is_some_and(|comparison| comparison.equivalent(&left.to_json(), &right.to_json()));
+    if skip {
+        if left != right {
+            emitter.skipped += 1;
         }
-        (Value::Array(left_array), Value::Array(right_array)) => {
-            diff_array(left_array, right_array, options, path_pos, patch_ops)
+        return Diffed {
+            left_len: json_len(left),
+            right_len: json_len(right),
+        };
+    }
+
+    // Atomic subtrees, and subtrees at the maximum depth, are replaced as a
+    // whole, whatever the granularity
+    let atomic = options.is_atomic_at(path_pos);
+    if !atomic && left.is_object() && right.is_object() {
+        return diff_object(left, right, options, path_pos, emitter);
+    }
+
+    match (left.elements(), right.elements()) {
+        // Atomic arrays are only replaced as a whole when they have no identity
+        (Some(left_array), Some(right_array))
+            if !atomic && (!options.atomic_arrays || options.index_key().is_some()) =>
+        {
+            diff_array(left_array, right_array, options, path_pos, emitter)
         }
-        (left, right) if left == right => (Patch::default(), DiffErrorSummary::empty()), // Values are equal, no diff needed
-        (_, right) => {
-            let patch = super::PatchOp::replace(path_pos.clone(), right.clone());
-            // patch_ops.push(patch.clone());
+        _ => {
+            let diffed = Diffed {
+                left_len: json_len(left),
+                right_len: json_len(right),
+            };
+            // Values are equal, no diff needed
+            if left == right {
+                return diffed;
+            }
+            let comparator = options.comparator_at(path_pos);
+            if comparator
+                .is_some_and(|comparator| comparator.equal(&left.to_json(), &right.to_json()))
+            {
+                emitter.skipped += 1;
+            } else {
+                let patch = PatchOpRef::Replace {
+                    path: path_pos.clone(),
+                    value: right.to_json(),
+                };
 
-            (Patch::new(vec![patch]), DiffErrorSummary::empty())
+                emitter.emit_guarded(patch, left, diffed.left_len, diffed.right_len, options);
+            }
+            diffed
         }
     }
 }
 
-fn diff_object(
-    left_map: &serde_json::Map<String, Value>,
-    right_map: &serde_json::Map<String, Value>,
+fn diff_object<'a, D: Document>(
+    left: &'a D,
+    right: &'a D,
     options: DiffOptions,
     path_pointer: &Spath,
-    patch_ops: &Patch,
-) -> (Patch, DiffErrorSummary) {
-    let inner_patch = right_map
-        .iter()
-        .map(|(key, right_value)| {
-            let sub_schema = options.property_schema(key);
-            match left_map.get(key) {
-                // If the key exists in both maps, recurse into the values
-                Some(left_value) => {
-                    let child_options = options.with_optional_schema(sub_schema);
-
-                    let child_path = path_pointer.push(crate::path::Segment::Field(key.clone()));
-                    diff_recursive(
-                        left_value,
-                        right_value,
-                        child_options,
-                        &child_path,
-                        patch_ops,
-                    )
+    emitter: &mut Emitter<'a, '_>,
+) -> Diffed {
+    let moves = match options.move_detection {
+        Some(min_size) => detect_moves(left, right, path_pointer, min_size, options),
+        None => DetectedMoves::default(),
+    };
+
+    let scope = match options.granularity {
+        DiffGranularity::Compact => Some(emitter.open()),
+        DiffGranularity::Granular => None,
+    };
+
+    let mut diffed = Diffed {
+        left_len: container_len(left.object_len().unwrap_or_default()),
+        right_len: container_len(right.object_len().unwrap_or_default()),
+    };
+
+    let mut removed_sources = Vec::new();
+    for (key, right_value) in right.members() {
+        let child_path = path_pointer.push(Segment::Field(key.clone().into_owned()));
+        // "key":
+        let member_len = string_len(&key) + 1;
+        diffed.right_len += member_len;
+
+        match left.member(&key) {
+            // If the key exists in both maps, recurse into the values
+            Some(left_value) => {
+                let sub_schema = options.property_schema(&key);
+                let child_options = options.with_optional_schema(sub_schema);
+
+                let child = diff_node(left_value, right_value, child_options, &child_path, emitter);
+                diffed.left_len += member_len + child.left_len;
+                diffed.right_len += child.right_len;
+            }
+            None => {
+                let value_len = json_len(right_value);
+                diffed.right_len += value_len;
+
+                let excluded = options.resolver().is_ignored(options.property_schema(&key))
+                    || !options.path_filter.includes(&child_path);
+                if excluded {
+                    emitter.skipped += 1;
+                    // The source of a move that is left out is still removed
+                    if let Some(super::PatchOp::Move { from, .. }) = moves.ops.get(key.as_ref()) {
+                        removed_sources.extend(from.field());
+                    }
+                    continue;
                 }
-                // Otherwise, it's an addition
-                None => {
-                    let child_path = path_pointer.push(crate::path::Segment::Field(key.clone()));
-                    let patch_op = super::PatchOp::add(child_path.clone(), right_value.clone());
 
-                    (Patch::new(vec![patch_op]), DiffErrorSummary::empty())
+                // A move or copy whose source is left out would be skipped
+                // along with it, so the member is added instead
+                let detected = moves.ops.get(key.as_ref()).filter(|op| {
+                    let included = op
+                        .from()
+                        .is_none_or(|from| options.path_filter.includes(from));
+                    if !included && let super::PatchOp::Move { from, .. } = op {
+                        removed_sources.extend(from.field());
+                    }
+                    included
+                });
+                match detected {
+                    // A member moved from another key. The moved value is
+                    // identical to the right-hand value.
+                    Some(op @ super::PatchOp::Move { .. }) => {
+                        emitter.emit_guarded(op.clone().into(), right_value, value_len, 0, options);
+                    }
+                    // A member copied from another key
+                    Some(op) => emitter.emit(op.clone().into(), 0, options),
+                    // Otherwise, it's an addition
+                    None => {
+                        let patch_op = PatchOpRef::Add {
+                            path: child_path,
+                            value: right_value.to_json(),
+                        };
+                        emitter.emit(patch_op, value_len, options);
+                    }
                 }
             }
-        })
-        .fold((Patch::default(), DiffErrorSummary::empty()), |acc, p| {
-            (acc.0 + p.0, acc.1 + p.1)
-        });
-
-    let mut removals = vec![];
-    for key in left_map.keys() {
-        // If the key is missing in the right map, it's a removal
-        if !right_map.contains_key(key) {
-            let child_path = path_pointer.push(crate::path::Segment::Field(key.clone()));
-            let child_op = super::PatchOp::remove(child_path.clone());
-            removals.push(child_op);
         }
     }
 
-    let replace_patch = {
-        let patch_op =
-            super::PatchOp::replace(path_pointer.clone(), Value::Object(right_map.clone()));
-        Patch::new(vec![patch_op])
-    };
+    for (key, left_value) in left.members() {
+        // If the key is missing in the right map, it's a removal, unless the
+        // member was moved to a new key
+        if right.member(&key).is_some() {
+            continue;
+        }
 
-    let computed_patch = (inner_patch.0 + Patch::new(removals), inner_patch.1.clone());
+        let value_len = json_len(left_value);
+        diffed.left_len += string_len(&key) + 1 + value_len;
+
+        if options.resolver().is_ignored(options.property_schema(&key)) {
+            emitter.skipped += 1;
+        } else if !moves.moved_from.contains(key.as_ref())
+            || removed_sources.iter().any(|source| *source == key)
+        {
+            let child_path = path_pointer.push(Segment::Field(key.into_owned()));
+            let child_op = PatchOpRef::Remove { path: child_path };
+            emitter.emit_guarded(child_op, left_value, value_len, 0, options);
+        }
+    }
 
-    let inner_patch_size_bytes = serde_json::to_vec(&computed_patch.0).unwrap().len();
-    let replace_patch_size_bytes = serde_json::to_vec(&replace_patch).unwrap().len();
+    if let Some(scope) = scope {
+        if emitter.is_compactable(&scope) && options.path_filter.includes(path_pointer) {
+            let guard = asserts_old_value(options, true);
+            let cost_model = options.cost_model;
+            let mut replace_cost =
+                cost_model.op_cost(OpKind::Replace, path_pointer, None, diffed.right_len);
+            if guard {
+                replace_cost +=
+                    cost_model.op_cost(OpKind::Test, path_pointer, None, diffed.left_len);
+            }
 
-    match options.granularity {
-        DiffGranularity::Compact => {
-            if !patch_contains_semantic_path(&computed_patch.0)
-                && replace_patch_size_bytes < inner_patch_size_bytes
-            {
-                (replace_patch, inner_patch.1)
-            } else {
-                computed_patch
+            if replace_cost < emitter.cost - scope.cost {
+                emitter.discard(&scope);
+                let patch_op = PatchOpRef::Replace {
+                    path: path_pointer.clone(),
+                    value: right.to_json(),
+                };
+                emitter.emit_guarded(patch_op, left, diffed.left_len, diffed.right_len, options);
             }
         }
-        DiffGranularity::Granular => computed_patch,
+        emitter.close();
     }
+
+    diffed
 }
 
-fn patch_contains_semantic_path(patch: &Patch) -> bool {
-    patch.iter().any(patch_op_contains_semantic_path)
+fn array_len<D: Document>(items: &[D]) -> usize {
+    container_len(items.len()) + items.iter().map(json_len).sum::<usize>()
 }
 
-fn patch_op_contains_semantic_path(op: &super::PatchOp) -> bool {
-    match op {
-        super::PatchOp::Add { path, .. }
-        | super::PatchOp::Remove { path }
-        | super::PatchOp::Replace { path, .. }
-        | super::PatchOp::Test { path, .. } => path_contains_semantic_segment(path),
-        super::PatchOp::Move { from, path } | super::PatchOp::Copy { from, path } => {
-            path_contains_semantic_segment(from) || path_contains_semantic_segment(path)
-        }
+pub(crate) fn asserts_old_value(options: DiffOptions, is_container: bool) -> bool {
+    match options.test_mode {
+        TestMode::All => true,
+        TestMode::LeafValuesOnly => !is_container,
+        TestMode::Disabled | TestMode::IdentityOnly => false,
     }
 }
 
+fn patch_op_contains_semantic_path(op: &PatchOpRef) -> bool {
+    path_contains_semantic_segment(op.path())
+        || op.from().is_some_and(path_contains_semantic_segment)
+}
+
 fn path_contains_semantic_segment(path: &Spath) -> bool {
     path.into_iter()
-        .any(|segment| matches!(segment, crate::path::Segment::Filter(_)))
+        .any(|segment| matches!(segment, Segment::Filter(_)))
 }
 
-fn diff_array(
-    left: &[Value],
-    right: &[Value],
+fn diff_array<'a, D: Document>(
+    left: &'a [D],
+    right: &'a [D],
     options: DiffOptions<'_>,
     path_pointer: &Spath,
-    patch_ops: &Patch,
-) -> (Patch, DiffErrorSummary) {
+    emitter: &mut Emitter<'a, '_>,
+) -> Diffed {
     // TODO: emit warning if the schema is missing an index key when the schema is provided
     let index_key = options.index_key().map(str::to_owned);
 
     match index_key {
         // If the schema specifies an index key, use keyed diffing
         Some(ref key) if options.schema.is_some() => {
-            diff_array_keyed(left, right, key, options, path_pointer, patch_ops)
+            diff_array_keyed(left, right, key, options, path_pointer, emitter)
         }
         // Otherwise, use index based diffing
-        _ => diff_array_indexed(left, right, options, path_pointer, patch_ops),
+        _ => diff_array_indexed(left, right, options, path_pointer, emitter),
     }
 }
 
-fn diff_array_keyed(
-    left: &[Value],
-    right: &[Value],
+fn diff_array_keyed<'a, D: Document>(
+    left: &'a [D],
+    right: &'a [D],
     index_key: &str,
     options: DiffOptions,
     path_pointer: &Spath,
-    patch_ops: &Patch,
-) -> (Patch, DiffErrorSummary) {
-    // Build maps: key -> element
+    emitter: &mut Emitter<'a, '_>,
+) -> Diffed {
+    // Build maps: key -> element. Ordered maps keep the emitted operations
+    // deterministic for identical inputs.
     let (map_left, errors_left) = build_key_map(left, index_key, path_pointer);
     let (map_right, errors_right) = build_key_map(right, index_key, path_pointer);
+    emitter.errors.left.extend(errors_left);
+    emitter.errors.right.extend(errors_right);
 
-    let keys_a: HashSet<_> = map_left.keys().cloned().collect();
-    let keys_b: HashSet<_> = map_right.keys().cloned().collect();
-
-    let mut removed_keys: Vec<_> = keys_a.difference(&keys_b).collect();
-    removed_keys.sort_unstable();
-
-    let mut added_keys: Vec<_> = keys_b.difference(&keys_a).collect();
-    added_keys.sort_unstable();
-
-    let mut modified_keys: Vec<_> = keys_a.intersection(&keys_b).collect();
-    modified_keys.sort_unstable();
+    let mut diffed = Diffed {
+        left_len: container_len(left.len()),
+        right_len: container_len(right.len()),
+    };
 
     // Removed elements
-    let removed = removed_keys
-        .into_iter()
-        .map(|key| {
-            let child_path = path_pointer.push_filter(index_key, key);
-            Patch::new_with_op(super::PatchOp::remove(child_path.clone()))
-        })
-        .fold(Patch::default(), |acc, p| acc + p);
+    for (key, &value_left) in &map_left {
+        if map_right.contains_key(key) {
+            continue;
+        }
+
+        let value_len = json_len(value_left);
+        diffed.left_len += value_len;
+
+        let child_path = path_pointer.push_filter(index_key, key);
+        let guarded = emitter.guard_identity(value_left, index_key, &child_path, options);
+        emitter.emit_guarded(
+            PatchOpRef::Remove { path: child_path },
+            value_left,
+            value_len,
+            0,
+            options,
+        );
+        if guarded {
+            emitter.release_guard();
+        }
+    }
 
     // Added elements
-    let added = added_keys
-        .into_iter()
-        .map(|key| {
-            let child_path = path_pointer.push(crate::path::Segment::Field("-".to_string()));
-            let val = &map_right[key];
+    for (key, &value_right) in &map_right {
+        if map_left.contains_key(key) {
+            continue;
+        }
+
+        let value_len = json_len(value_right);
+        diffed.right_len += value_len;
 
-            Patch::new_with_op(super::PatchOp::add(child_path.clone(), val.clone()))
-        })
-        .fold(Patch::default(), |acc, p| acc + p);
+        let child_path = path_pointer.push(Segment::Field("-".to_string()));
+        let patch_op = PatchOpRef::Add {
+            path: child_path,
+            value: value_right.to_json(),
+        };
+        emitter.emit(patch_op, value_len, options);
+    }
 
     let sub_schema = options.items_schema();
     let child_options = options.with_optional_schema(sub_schema);
 
     // Modified elements (same key in both)
-    let modified = modified_keys
-        .into_iter()
-        .map(|key| {
-            let child_path = path_pointer.push_filter(index_key, key);
-            let value_left = &map_left[key];
-            let value_right = &map_right[key];
-
-            diff_recursive(
-                value_left,
-                value_right,
-                child_options,
-                &child_path,
-                patch_ops,
-            )
-        })
-        .fold((Patch::default(), DiffErrorSummary::empty()), |acc, p| {
-            (acc.0 + p.0, acc.1 + p.1)
-        });
+    for (key, &value_left) in &map_left {
+        let Some(&value_right) = map_right.get(key) else {
+            continue;
+        };
+
+        let child_path = path_pointer.push_filter(index_key, key);
+        let guarded = emitter.guard_identity(value_left, index_key, &child_path, options);
+        let child = diff_node(value_left, value_right, child_options, &child_path, emitter);
+        if guarded {
+            emitter.release_guard();
+        }
+        diffed.left_len += child.left_len;
+        diffed.right_len += child.right_len;
+    }
 
-    let patch = removed + added + modified.0;
-    (
-        patch,
-        DiffErrorSummary::new(errors_left, errors_right) + modified.1,
-    )
+    diffed
 }
 
-fn build_key_map(
-    arr: &[Value],
+fn build_key_map<'v, D: Document>(
+    arr: &'v [D],
     index_key: &str,
     path_pointer: &Spath,
-) -> (HashMap<String, Value>, Vec<DiffError>) {
-    let mut map = HashMap::new();
+) -> (BTreeMap<String, &'v D>, Vec<DiffError>) {
+    let mut map = BTreeMap::new();
     let mut errors = Vec::new();
     for (i, item) in arr.iter().enumerate() {
-        let current_path = path_pointer.push(crate::path::Segment::Field(format!("{}", i)));
-        match item {
-            Value::Object(obj) => match obj.get(index_key) {
+        let current_path = path_pointer.push(Segment::Field(format!("{}", i)));
+        match item.object_len() {
+            Some(_) => match item.member(index_key) {
                 Some(value) => match index_key_value_to_filter(value) {
                     Some(key) => match map.entry(key) {
                         Entry::Occupied(entry) => {
@@ -250,25 +600,35 @@ fn build_key_map(
                             ));
                         }
                         Entry::Vacant(entry) => {
-                            entry.insert(item.clone());
+                            entry.insert(item);
                         }
                     },
                     None => {
-                        errors.push(DiffError::non_string_index_key(&current_path, value));
+                        errors.push(DiffError::non_string_index_key(
+                            &current_path,
+                            &value.to_json(),
+                        ));
                     }
                 },
                 None => {
                     errors.push(DiffError::missing_index_key(&current_path, index_key));
                 }
             },
-            _ => errors.push(DiffError::non_object_array_item(path_pointer, item)),
+            None => errors.push(DiffError::non_object_array_item(
+                path_pointer,
+                &item.to_json(),
+            )),
         }
     }
     (map, errors)
 }
 
-fn index_key_value_to_filter(value: &Value) -> Option<String> {
-    match value {
+pub(crate) fn index_key_value_to_filter<D: Document>(value: &D) -> Option<String> {
+    if value.is_array() || value.is_object() {
+        return None;
+    }
+
+    match &*value.to_json() {
         Value::String(s) => Some(s.clone()),
         Value::Number(n) => Some(n.to_string()),
         Value::Bool(b) => Some(b.to_string()),
@@ -276,13 +636,13 @@ fn index_key_value_to_filter(value: &Value) -> Option<String> {
     }
 }
 
-fn diff_array_indexed(
-    left_array: &[Value],
-    right_array: &[Value],
+fn diff_array_indexed<'a, D: Document>(
+    left_array: &'a [D],
+    right_array: &'a [D],
     options: DiffOptions,
     path_pointer: &Spath,
-    patch_ops: &Patch,
-) -> (Patch, DiffErrorSummary) {
+    emitter: &mut Emitter<'a, '_>,
+) -> Diffed {
     let len_left = left_array.len();
     let len_right = right_array.len();
 
@@ -301,12 +661,16 @@ fn diff_array_indexed(
     // right: [a,b]
     if len_right <= len_left && left_array[..len_right] == *right_array {
         // remove from the end, descending indexes
-        let mut patch = Patch::default();
         for i in (len_right..len_left).rev() {
-            let child_path = path_pointer.push(crate::path::Segment::Field(i.to_string()));
-            patch = patch + Patch::new_with_op(super::PatchOp::remove(child_path));
+            let child_path = path_pointer.push(Segment::Field(i.to_string()));
+            let removed = &left_array[i];
+            let op = PatchOpRef::Remove { path: child_path };
+            emitter.emit_guarded(op, removed, json_len(removed), 0, options);
         }
-        return (patch, DiffErrorSummary::empty());
+        return Diffed {
+            left_len: array_len(left_array),
+            right_len: array_len(right_array),
+        };
     }
 
     // 2) Pure remove-from-front: right is a suffix of left
@@ -314,24 +678,33 @@ fn diff_array_indexed(
     // right: [b,c]
     if len_right <= len_left && left_array[len_left - len_right..] == *right_array {
         // remove index 0 repeatedly
-        let mut patch = Patch::default();
-        for _ in 0..(len_left - len_right) {
-            let child_path = path_pointer.push(crate::path::Segment::Field("0".to_owned()));
-            patch = patch + Patch::new_with_op(super::PatchOp::remove(child_path));
+        for removed in &left_array[..(len_left - len_right)] {
+            let child_path = path_pointer.push(Segment::Field("0".to_owned()));
+            let op = PatchOpRef::Remove { path: child_path };
+            emitter.emit_guarded(op, removed, json_len(removed), 0, options);
         }
-        return (patch, DiffErrorSummary::empty());
+        return Diffed {
+            left_len: array_len(left_array),
+            right_len: array_len(right_array),
+        };
     }
 
     // 3) Pure append: left is a prefix of right
     // left:  [a,b]
     // right: [a,b,c]
     if len_left <= len_right && right_array[..len_left] == *left_array {
-        let mut patch = Patch::default();
         for el in &right_array[len_left..] {
-            let child_path = path_pointer.push(crate::path::Segment::Field("-".to_owned()));
-            patch = patch + Patch::new_with_op(super::PatchOp::add(child_path, el.clone()));
+            let child_path = path_pointer.push(Segment::Field("-".to_owned()));
+            let op = PatchOpRef::Add {
+                path: child_path,
+                value: el.to_json(),
+            };
+            emitter.emit(op, json_len(el), options);
         }
-        return (patch, DiffErrorSummary::empty());
+        return Diffed {
+            left_len: array_len(left_array),
+            right_len: array_len(right_array),
+        };
     }
 
     // 4) Pure add-to-front: left is a suffix of right
@@ -340,14 +713,20 @@ fn diff_array_indexed(
     //
     // JSON Patch has no "insert at front" primitive; it’s still `add /arr/0`.
     if len_left <= len_right && right_array[len_right - len_left..] == *left_array {
-        let mut patch = Patch::default();
         // add to front in increasing order so final order matches `right`
         for el in right_array[..(len_right - len_left)].iter().rev() {
             // inserting multiple at index 0: do it in reverse so final order is correct
-            let child_path = path_pointer.push(crate::path::Segment::Field("0".to_owned()));
-            patch = patch + Patch::new_with_op(super::PatchOp::add(child_path, el.clone()));
+            let child_path = path_pointer.push(Segment::Field("0".to_owned()));
+            let op = PatchOpRef::Add {
+                path: child_path,
+                value: el.to_json(),
+            };
+            emitter.emit(op, json_len(el), options);
         }
-        return (patch, DiffErrorSummary::empty());
+        return Diffed {
+            left_len: array_len(left_array),
+            right_len: array_len(right_array),
+        };
     }
 
     // --------
@@ -355,48 +734,59 @@ fn diff_array_indexed(
     // --------
 
     let min_len = len_left.min(len_right);
+    let mut diffed = Diffed {
+        left_len: container_len(len_left),
+        right_len: container_len(len_right),
+    };
 
-    let recursed = (0..min_len)
-        .map(|i| {
-            let child_path = path_pointer.push(crate::path::Segment::Field(i.to_string()));
-            diff_recursive(
-                &left_array[i],
-                &right_array[i],
-                item_options,
-                &child_path,
-                patch_ops,
-            )
-        })
-        .fold((Patch::default(), DiffErrorSummary::empty()), |acc, p| {
-            (acc.0 + p.0, acc.1 + p.1)
-        });
+    for i in 0..min_len {
+        let child_path = path_pointer.push(Segment::Field(i.to_string()));
+        let child = diff_node(
+            &left_array[i],
+            &right_array[i],
+            item_options,
+            &child_path,
+            emitter,
+        );
+        diffed.left_len += child.left_len;
+        diffed.right_len += child.right_len;
+    }
 
     // Extra elements in left_array (removals)
     // IMPORTANT: remove from end to avoid index shifting
-    let removals = (min_len..len_left)
-        .rev()
-        .map(|i| {
-            let child_path = path_pointer.push(crate::path::Segment::Field(i.to_string()));
-            Patch::new_with_op(super::PatchOp::remove(child_path))
-        })
-        .fold(Patch::default(), |acc, p| acc + p);
+    for i in (min_len..len_left).rev() {
+        let child_path = path_pointer.push(Segment::Field(i.to_string()));
+        let removed = &left_array[i];
+        let value_len = json_len(removed);
+        diffed.left_len += value_len;
+        emitter.emit_guarded(
+            PatchOpRef::Remove { path: child_path },
+            removed,
+            value_len,
+            0,
+            options,
+        );
+    }
 
     // Extra elements in right_array (additions)
-    let additions = right_array[min_len..]
-        .iter()
-        .map(|element| {
-            let child_path = path_pointer.push(crate::path::Segment::Field("-".to_owned()));
-            Patch::new_with_op(super::PatchOp::add(child_path, element.clone()))
-        })
-        .fold(Patch::default(), |acc, p| acc + p);
-
-    let patch = recursed.0 + removals + additions;
-    let diff_errors = recursed.1;
-    (patch, diff_errors)
+    for element in &right_array[min_len..] {
+        let child_path = path_pointer.push(Segment::Field("-".to_owned()));
+        let value_len = json_len(element);
+        diffed.right_len += value_len;
+        let op = PatchOpRef::Add {
+            path: child_path,
+            value: element.to_json(),
+        };
+        emitter.emit(op, value_len, options);
+    }
+
+    diffed
 }
 
 #[cfg(test)]
 mod tests {
+    use std::borrow::Cow;
+
     use assert2::{assert, check};
 
     use crate::diff::test_util::SIMPLE_SCHEMA;
@@ -414,13 +804,8 @@ mod tests {
         let left = serde_json::json!("foo");
         let right = serde_json::json!("foo");
 
-        let (patch_ops, diff_errors) = diff_recursive(
-            &left,
-            &right,
-            DiffOptions::new(),
-            &Spath::default(),
-            &Patch::default(),
-        );
+        let (patch_ops, diff_errors) =
+            diff_recursive(&left, &right, DiffOptions::new(), &Spath::default());
 
         // No patch operations should be generated for equal values
         check!(diff_errors.is_empty() == true);
@@ -432,13 +817,8 @@ mod tests {
         let left = serde_json::json!("foo");
         let right = serde_json::json!("bar");
 
-        let (patch_ops, diff_errors) = diff_recursive(
-            &left,
-            &right,
-            DiffOptions::new(),
-            &Spath::default(),
-            &Patch::default(),
-        );
+        let (patch_ops, diff_errors) =
+            diff_recursive(&left, &right, DiffOptions::new(), &Spath::default());
 
         let expected_patch = Patch::new(vec![PatchOp::replace(Spath::default(), right.clone())]);
 
@@ -451,13 +831,8 @@ mod tests {
         let left = serde_json::json!("foo");
         let right = serde_json::json!({"baz": 42});
 
-        let (patch_ops, diff_errors) = diff_recursive(
-            &left,
-            &right,
-            DiffOptions::new(),
-            &Spath::default(),
-            &Patch::default(),
-        );
+        let (patch_ops, diff_errors) =
+            diff_recursive(&left, &right, DiffOptions::new(), &Spath::default());
 
         let expected_patch = Patch::new(vec![PatchOp::replace(Spath::default(), right.clone())]);
 
@@ -470,13 +845,8 @@ mod tests {
         let left = serde_json::json!({"foo": 43});
         let right = serde_json::json!({"foo": 42});
 
-        let (patch_ops, diff_errors) = diff_recursive(
-            &left,
-            &right,
-            DiffOptions::new(),
-            &Spath::default(),
-            &Patch::default(),
-        );
+        let (patch_ops, diff_errors) =
+            diff_recursive(&left, &right, DiffOptions::new(), &Spath::default());
 
         let expected_patch = Patch::new(vec![PatchOp::replace(
             path("/foo"),
@@ -492,13 +862,8 @@ mod tests {
         let left = serde_json::json!({"foo": 43, "bar": 1});
         let right = serde_json::json!({"foo": 43});
 
-        let (patch_ops, diff_errors) = diff_recursive(
-            &left,
-            &right,
-            DiffOptions::new(),
-            &Spath::default(),
-            &Patch::default(),
-        );
+        let (patch_ops, diff_errors) =
+            diff_recursive(&left, &right, DiffOptions::new(), &Spath::default());
 
         let expected_patch = Patch::new(vec![PatchOp::remove(path("/bar"))]);
 
@@ -511,13 +876,8 @@ mod tests {
         let left = serde_json::json!({"foo": 43});
         let right = serde_json::json!({"foo": 43, "bar": 1});
 
-        let (patch_ops, diff_errors) = diff_recursive(
-            &left,
-            &right,
-            DiffOptions::new(),
-            &Spath::default(),
-            &Patch::default(),
-        );
+        let (patch_ops, diff_errors) =
+            diff_recursive(&left, &right, DiffOptions::new(), &Spath::default());
 
         let expected_patch = Patch::new(vec![PatchOp::add(path("/bar"), Value::Number(1.into()))]);
 
@@ -543,7 +903,6 @@ mod tests {
             &right,
             DiffOptions::new().with_granularity(DiffGranularity::Compact),
             &Spath::default(),
-            &Patch::default(),
         );
 
         let expected_patch = Patch::new(vec![PatchOp::replace(Spath::default(), right.clone())]);
@@ -570,7 +929,6 @@ mod tests {
             &right,
             DiffOptions::new().granular(),
             &Spath::default(),
-            &Patch::default(),
         );
 
         let expected_patch = Patch::new(vec![
@@ -591,13 +949,21 @@ mod tests {
             path("/tracks/[id=free]/levels/[id=1]"),
             destination_path.clone(),
         )]);
-        check!(patch_contains_semantic_path(&move_patch));
+        check!(
+            move_patch
+                .iter()
+                .any(|op| patch_op_contains_semantic_path(&op.borrowed()))
+        );
 
         let copy_patch = Patch::new(vec![PatchOp::copy(
             path("/tracks/[id=free]/levels/[id=1]"),
             destination_path,
         )]);
-        check!(patch_contains_semantic_path(&copy_patch));
+        check!(
+            copy_patch
+                .iter()
+                .any(|op| patch_op_contains_semantic_path(&op.borrowed()))
+        );
     }
 
     #[test]
@@ -653,7 +1019,6 @@ mod tests {
             &right,
             DiffOptions::new().with_schema(&schema).compact(),
             &Spath::default(),
-            &Patch::default(),
         );
 
         let expected_patch = Patch::new(vec![
@@ -693,7 +1058,6 @@ mod tests {
             &right,
             DiffOptions::new().with_schema(&schema).granular(),
             &Spath::default(),
-            &Patch::default(),
         );
 
         let expected_patch = Patch::new(vec![PatchOp::replace(
@@ -724,7 +1088,6 @@ mod tests {
             &right,
             DiffOptions::new().with_schema(&schema).granular(),
             &Spath::default(),
-            &Patch::default(),
         );
 
         let expected_patch = Patch::new(vec![PatchOp::replace(
@@ -785,7 +1148,6 @@ mod tests {
             &right,
             DiffOptions::new().with_schema(&schema).granular(),
             &Spath::default(),
-            &Patch::default(),
         );
 
         let expected_patch = Patch::new(vec![PatchOp::replace(
@@ -863,7 +1225,6 @@ mod tests {
             &right,
             DiffOptions::new().with_schema(&schema).granular(),
             &Spath::default(),
-            &Patch::default(),
         );
 
         let expected_patch = Patch::new(vec![PatchOp::replace(
@@ -904,7 +1265,6 @@ mod tests {
             &right,
             DiffOptions::new().with_schema(&schema).granular(),
             &Spath::default(),
-            &Patch::default(),
         );
 
         let expected_patch = Patch::new(vec![PatchOp::replace(
@@ -922,7 +1282,7 @@ mod tests {
     //     let left = serde_json::json!({"foo": {"bar": 1}});
     //     let right = serde_json::json!({"baz": {"bar": 1}});
     //
-    //     let (patch_ops, diff_errors) = diff_recursive(&left, &right, None, &Spath::default(), &Patch::default());
+    //     let (patch_ops, diff_errors) = diff_recursive(&left, &right, None, &Spath::default());
     //
     //     let expected_patch = Patch::new(vec![PatchOp::move_op(
     //         path("/foo"),
@@ -944,8 +1304,7 @@ mod tests {
             {"id": "abc", "count": 2},
         ]});
 
-        let (patch_ops, diff_errors) =
-            diff_recursive(&left, &right, options, &Spath::default(), &Patch::default());
+        let (patch_ops, diff_errors) = diff_recursive(&left, &right, options, &Spath::default());
 
         let expected_patch = Patch::new(vec![PatchOp::remove(path("/foo/[id=bla]"))]);
 
@@ -966,8 +1325,7 @@ mod tests {
             {"id": "bla", "count": 3},
         ]});
 
-        let (patch_ops, diff_errors) =
-            diff_recursive(&left, &right, options, &Spath::default(), &Patch::default());
+        let (patch_ops, diff_errors) = diff_recursive(&left, &right, options, &Spath::default());
 
         let expected_patch = Patch::new(vec![PatchOp::add(
             path("/foo/-"),
@@ -992,8 +1350,7 @@ mod tests {
             {"id": "bla", "count": 3},
         ]});
 
-        let (patch_ops, diff_errors) =
-            diff_recursive(&left, &right, options, &Spath::default(), &Patch::default());
+        let (patch_ops, diff_errors) = diff_recursive(&left, &right, options, &Spath::default());
 
         let expected_patch = Patch::new(vec![PatchOp::replace(
             path("/foo/[id=bla]/count"),
@@ -1018,8 +1375,7 @@ mod tests {
             {"key": "bla", "count": 3},
         ]});
 
-        let (_patch_ops, diff_errors) =
-            diff_recursive(&left, &right, options, &Spath::default(), &Patch::default());
+        let (_patch_ops, diff_errors) = diff_recursive(&left, &right, options, &Spath::default());
 
         check!(diff_errors.left.len() == 2);
         check!(diff_errors.right.len() == 2);
@@ -1043,8 +1399,7 @@ mod tests {
             {"id": "abc", "count": 3},
         ]});
 
-        let (_patch_ops, diff_errors) =
-            diff_recursive(&left, &right, options, &Spath::default(), &Patch::default());
+        let (_patch_ops, diff_errors) = diff_recursive(&left, &right, options, &Spath::default());
 
         check!(diff_errors.left.len() == 1);
         check!(diff_errors.right.len() == 1);
@@ -1066,8 +1421,7 @@ mod tests {
         ]});
         let right = left.clone();
 
-        let (_patch_ops, diff_errors) =
-            diff_recursive(&left, &right, options, &Spath::default(), &Patch::default());
+        let (_patch_ops, diff_errors) = diff_recursive(&left, &right, options, &Spath::default());
 
         let expected_errors = vec![
             DiffError::non_string_index_key(
@@ -1111,8 +1465,7 @@ mod tests {
             {"id": "bla", "count": 10},
         ]});
 
-        let (patch_ops, diff_errors) =
-            diff_recursive(&left, &right, options, &Spath::default(), &Patch::default());
+        let (patch_ops, diff_errors) = diff_recursive(&left, &right, options, &Spath::default());
 
         let expected_patch = Patch::new(vec![
             PatchOp::replace(path("/foo/[id=bla]/count"), Value::Number(10.into())),
@@ -1135,13 +1488,8 @@ mod tests {
             {"id": "abc", "count": 2},
         ]});
 
-        let (patch_ops, diff_errors) = diff_recursive(
-            &left,
-            &right,
-            DiffOptions::new(),
-            &Spath::default(),
-            &Patch::default(),
-        );
+        let (patch_ops, diff_errors) =
+            diff_recursive(&left, &right, DiffOptions::new(), &Spath::default());
 
         let expected_patch = Patch::new(vec![PatchOp::remove(path("/foo/1"))]);
 
@@ -1160,13 +1508,8 @@ mod tests {
             {"id": "bla", "count": 3},
         ]});
 
-        let (patch_ops, diff_errors) = diff_recursive(
-            &left,
-            &right,
-            DiffOptions::new(),
-            &Spath::default(),
-            &Patch::default(),
-        );
+        let (patch_ops, diff_errors) =
+            diff_recursive(&left, &right, DiffOptions::new(), &Spath::default());
 
         // TODO: The result should be a just removal of the object at index 0
         // Currently the emitted patch is not optimal, but valid
@@ -1189,13 +1532,8 @@ mod tests {
             {"id": "lol", "count": 10},
         ]});
 
-        let (patch_ops, diff_errors) = diff_recursive(
-            &left,
-            &right,
-            DiffOptions::new(),
-            &Spath::default(),
-            &Patch::default(),
-        );
+        let (patch_ops, diff_errors) =
+            diff_recursive(&left, &right, DiffOptions::new(), &Spath::default());
 
         let expected_patch = Patch::new(vec![PatchOp::replace(
             path("/foo/1"),
@@ -1217,13 +1555,8 @@ mod tests {
             {"id": "lol", "count": 10},
         ]});
 
-        let (patch_ops, diff_errors) = diff_recursive(
-            &left,
-            &right,
-            DiffOptions::new(),
-            &Spath::default(),
-            &Patch::default(),
-        );
+        let (patch_ops, diff_errors) =
+            diff_recursive(&left, &right, DiffOptions::new(), &Spath::default());
 
         let expected_patch = Patch::new(vec![PatchOp::add(
             path("/foo/-"),
@@ -1252,13 +1585,8 @@ mod tests {
                 } => {
                     let comment = comment.unwrap_or_default();
 
-                    let (patch_ops, _diff_errors) = diff_recursive(
-                        &doc,
-                        &expected,
-                        DiffOptions::new(),
-                        &Spath::default(),
-                        &Patch::default(),
-                    );
+                    let (patch_ops, _diff_errors) =
+                        diff_recursive(&doc, &expected, DiffOptions::new(), &Spath::default());
 
                     let mut generated_patch_json = Vec::new();
                     for op in patch_ops.iter() {
@@ -1309,13 +1637,8 @@ mod tests {
             "d": 4,
         });
 
-        let (patch_ops, diff_errors) = diff_recursive(
-            &left,
-            &right,
-            DiffOptions::new(),
-            &Spath::default(),
-            &Patch::default(),
-        );
+        let (patch_ops, diff_errors) =
+            diff_recursive(&left, &right, DiffOptions::new(), &Spath::default());
 
         let expected_patch = Patch::new(vec![PatchOp::replace(Spath::default(), right.clone())]);
 
@@ -1345,7 +1668,6 @@ mod tests {
             &right,
             DiffOptions::new().granular(),
             &Spath::default(),
-            &Patch::default(),
         );
 
         let expected_patch = Patch::new(vec![
@@ -1364,13 +1686,8 @@ mod tests {
         let left = serde_json::json!(["a", "b", "c", "d"]);
         let right = serde_json::json!(["a", "b"]);
 
-        let (patch_ops, diff_errors) = diff_recursive(
-            &left,
-            &right,
-            DiffOptions::new(),
-            &Spath::default(),
-            &Patch::default(),
-        );
+        let (patch_ops, diff_errors) =
+            diff_recursive(&left, &right, DiffOptions::new(), &Spath::default());
 
         let expected_patch = Patch::new(vec![
             PatchOp::remove(path("/3")),
@@ -1387,13 +1704,8 @@ mod tests {
         let left = serde_json::json!(["a", "b"]);
         let right = serde_json::json!(["a", "b", "c", "d"]);
 
-        let (patch_ops, diff_errors) = diff_recursive(
-            &left,
-            &right,
-            DiffOptions::new(),
-            &Spath::default(),
-            &Patch::default(),
-        );
+        let (patch_ops, diff_errors) =
+            diff_recursive(&left, &right, DiffOptions::new(), &Spath::default());
 
         let expected_patch = Patch::new(vec![
             PatchOp::add(path("/-"), serde_json::json!("c")),
@@ -1410,13 +1722,8 @@ mod tests {
         let left = serde_json::json!(["a", "b", "c", "d"]);
         let right = serde_json::json!(["c", "d"]);
 
-        let (patch_ops, diff_errors) = diff_recursive(
-            &left,
-            &right,
-            DiffOptions::new(),
-            &Spath::default(),
-            &Patch::default(),
-        );
+        let (patch_ops, diff_errors) =
+            diff_recursive(&left, &right, DiffOptions::new(), &Spath::default());
 
         let expected_patch = Patch::new(vec![
             PatchOp::remove(path("/0")),
@@ -1433,13 +1740,8 @@ mod tests {
         let left = serde_json::json!(["c", "d"]);
         let right = serde_json::json!(["a", "b", "c", "d"]);
 
-        let (patch_ops, diff_errors) = diff_recursive(
-            &left,
-            &right,
-            DiffOptions::new(),
-            &Spath::default(),
-            &Patch::default(),
-        );
+        let (patch_ops, diff_errors) =
+            diff_recursive(&left, &right, DiffOptions::new(), &Spath::default());
 
         let expected_patch = Patch::new(vec![
             PatchOp::add(path("/0"), serde_json::json!("b")),
@@ -1456,21 +1758,788 @@ mod tests {
         let left = serde_json::json!(["a", "b", "c"]);
         let right = serde_json::json!(["a", "x", "c", "d"]);
 
+        let (patch_ops, diff_errors) =
+            diff_recursive(&left, &right, DiffOptions::new(), &Spath::default());
+
+        let expected_patch = Patch::new(vec![
+            PatchOp::replace(path("/1"), serde_json::json!("x")),
+            PatchOp::add(path("/-"), serde_json::json!("d")),
+        ]);
+
+        check!(diff_errors.is_empty() == true);
+        check!(patch_ops.len() == 2);
+        check!(patch_ops == expected_patch);
+    }
+
+    #[test]
+    fn test_mode_all_should_assert_old_values_before_replace_and_remove() {
+        let left = serde_json::json!({"a": 1, "b": {"c": 2}, "d": [1, 2, 3]});
+        let right = serde_json::json!({"a": 2, "d": [1, 2]});
+
         let (patch_ops, diff_errors) = diff_recursive(
             &left,
             &right,
-            DiffOptions::new(),
+            DiffOptions::new().granular().with_tests(TestMode::All),
             &Spath::default(),
-            &Patch::default(),
         );
 
         let expected_patch = Patch::new(vec![
-            PatchOp::replace(path("/1"), serde_json::json!("x")),
-            PatchOp::add(path("/-"), serde_json::json!("d")),
+            PatchOp::test(path("/a"), serde_json::json!(1)),
+            PatchOp::replace(path("/a"), serde_json::json!(2)),
+            PatchOp::test(path("/d/2"), serde_json::json!(3)),
+            PatchOp::remove(path("/d/2")),
+            PatchOp::test(path("/b"), serde_json::json!({"c": 2})),
+            PatchOp::remove(path("/b")),
+        ]);
+
+        check!(diff_errors.is_empty() == true);
+        check!(patch_ops == expected_patch);
+    }
+
+    #[test]
+    fn test_mode_all_should_assert_each_element_removed_from_the_front() {
+        let left = serde_json::json!(["a", "b", "c"]);
+        let right = serde_json::json!(["c"]);
+
+        let (patch_ops, diff_errors) = diff_recursive(
+            &left,
+            &right,
+            DiffOptions::new().with_tests(TestMode::All),
+            &Spath::default(),
+        );
+
+        let expected_patch = Patch::new(vec![
+            PatchOp::test(path("/0"), serde_json::json!("a")),
+            PatchOp::remove(path("/0")),
+            PatchOp::test(path("/0"), serde_json::json!("b")),
+            PatchOp::remove(path("/0")),
         ]);
 
         check!(diff_errors.is_empty() == true);
-        check!(patch_ops.len() == 2);
         check!(patch_ops == expected_patch);
+        check!(crate::patch::apply(&left, &patch_ops).unwrap() == right);
+    }
+
+    #[test]
+    fn test_mode_leaf_values_only_should_not_assert_containers() {
+        let left = serde_json::json!({"a": 1, "b": {"c": 2}});
+        let right = serde_json::json!({"a": 2});
+
+        let (patch_ops, diff_errors) = diff_recursive(
+            &left,
+            &right,
+            DiffOptions::new()
+                .granular()
+                .with_tests(TestMode::LeafValuesOnly),
+            &Spath::default(),
+        );
+
+        let expected_patch = Patch::new(vec![
+            PatchOp::test(path("/a"), serde_json::json!(1)),
+            PatchOp::replace(path("/a"), serde_json::json!(2)),
+            PatchOp::remove(path("/b")),
+        ]);
+
+        check!(diff_errors.is_empty() == true);
+        check!(patch_ops == expected_patch);
+    }
+
+    #[test]
+    fn test_mode_identity_only_should_assert_keyed_element_identities() {
+        let schema = serde_json::json!({
+            "properties": {
+                "levels": { "x-spatch-indexKey": "id" }
+            }
+        });
+        let left = serde_json::json!({
+            "name": "old",
+            "levels": [
+                {"id": 1, "xp": 100},
+                {"id": 2, "xp": 200},
+                {"id": 3, "xp": 300}
+            ]
+        });
+        let right = serde_json::json!({
+            "name": "new",
+            "levels": [
+                {"id": 1, "xp": 150},
+                {"id": 3, "xp": 300}
+            ]
+        });
+
+        let (patch_ops, diff_errors) = diff_recursive(
+            &left,
+            &right,
+            DiffOptions::new()
+                .with_schema(&schema)
+                .granular()
+                .with_tests(TestMode::IdentityOnly),
+            &Spath::default(),
+        );
+
+        let expected_patch = Patch::new(vec![
+            PatchOp::test(path("/levels/[id=2]/id"), serde_json::json!(2)),
+            PatchOp::remove(path("/levels/[id=2]")),
+            PatchOp::test(path("/levels/[id=1]/id"), serde_json::json!(1)),
+            PatchOp::replace(path("/levels/[id=1]/xp"), serde_json::json!(150)),
+            PatchOp::replace(path("/name"), serde_json::json!("new")),
+        ]);
+
+        check!(diff_errors.is_empty() == true);
+        check!(patch_ops == expected_patch);
+    }
+
+    #[test]
+    fn test_mode_should_make_patch_fail_when_target_changed() {
+        let left = serde_json::json!({"a": 1, "b": 2});
+        let right = serde_json::json!({"a": 10, "b": 2});
+        let changed = serde_json::json!({"a": 5, "b": 2});
+
+        let (patch_ops, _) = diff_recursive(
+            &left,
+            &right,
+            DiffOptions::new().with_tests(TestMode::All),
+            &Spath::default(),
+        );
+
+        check!(crate::patch::apply(&left, &patch_ops).unwrap() == right);
+        assert!(let Err(_) = crate::patch::apply(&changed, &patch_ops));
+    }
+
+    #[test]
+    fn move_detection_should_emit_move_for_renamed_member() {
+        let left = serde_json::json!({"old": {"a": 1, "b": [1, 2]}, "keep": 1});
+        let right = serde_json::json!({"new": {"a": 1, "b": [1, 2]}, "keep": 1});
+
+        let (patch_ops, diff_errors) = diff_recursive(
+            &left,
+            &right,
+            DiffOptions::new().granular().with_move_detection(2),
+            &Spath::default(),
+        );
+
+        let expected_patch = Patch::new(vec![PatchOp::move_op(path("/old"), path("/new"))]);
+
+        check!(diff_errors.is_empty() == true);
+        check!(patch_ops == expected_patch);
+        check!(crate::patch::apply(&left, &patch_ops).unwrap() == right);
+    }
+
+    #[test]
+    fn move_detection_should_emit_copy_when_source_remains() {
+        let left = serde_json::json!({"a": {"x": [1, 2, 3]}, "b": {"x": [4]}});
+        let right = serde_json::json!({
+            "a": {"x": [1, 2, 3]},
+            "c": {"x": [1, 2, 3]},
+            "d": {"x": [4]},
+            "e": {"x": [4]}
+        });
+
+        let (patch_ops, diff_errors) = diff_recursive(
+            &left,
+            &right,
+            DiffOptions::new().granular().with_move_detection(2),
+            &Spath::default(),
+        );
+
+        let expected_patch = Patch::new(vec![
+            PatchOp::copy(path("/a"), path("/c")),
+            PatchOp::move_op(path("/b"), path("/d")),
+            PatchOp::copy(path("/d"), path("/e")),
+        ]);
+
+        check!(diff_errors.is_empty() == true);
+        check!(patch_ops == expected_patch);
+        check!(crate::patch::apply(&left, &patch_ops).unwrap() == right);
+    }
+
+    #[test]
+    fn move_detection_should_ignore_subtrees_below_the_minimum_size() {
+        let left = serde_json::json!({"old": {"a": 1}});
+        let right = serde_json::json!({"new": {"a": 1}});
+
+        let (patch_ops, diff_errors) = diff_recursive(
+            &left,
+            &right,
+            DiffOptions::new().granular().with_move_detection(3),
+            &Spath::default(),
+        );
+
+        let expected_patch = Patch::new(vec![
+            PatchOp::add(path("/new"), serde_json::json!({"a": 1})),
+            PatchOp::remove(path("/old")),
+        ]);
+
+        check!(diff_errors.is_empty() == true);
+        check!(patch_ops == expected_patch);
+    }
+
+    #[test]
+    fn move_detection_should_work_in_nested_objects() {
+        let left = serde_json::json!({"config": {"db": {"host": "a", "port": 1}}});
+        let right = serde_json::json!({"config": {"database": {"host": "a", "port": 1}}});
+
+        let (patch_ops, diff_errors) = diff_recursive(
+            &left,
+            &right,
+            DiffOptions::new().with_move_detection(3),
+            &Spath::default(),
+        );
+
+        let expected_patch = Patch::new(vec![PatchOp::move_op(
+            path("/config/db"),
+            path("/config/database"),
+        )]);
+
+        check!(diff_errors.is_empty() == true);
+        check!(patch_ops == expected_patch);
+    }
+
+    #[test]
+    fn move_detection_should_assert_the_moved_value_in_test_mode() {
+        let left = serde_json::json!({"old": [1, 2]});
+        let right = serde_json::json!({"new": [1, 2]});
+
+        let (patch_ops, diff_errors) = diff_recursive(
+            &left,
+            &right,
+            DiffOptions::new()
+                .with_move_detection(1)
+                .with_tests(TestMode::All),
+            &Spath::default(),
+        );
+
+        let expected_patch = Patch::new(vec![
+            PatchOp::test(path("/old"), serde_json::json!([1, 2])),
+            PatchOp::move_op(path("/old"), path("/new")),
+        ]);
+
+        check!(diff_errors.is_empty() == true);
+        check!(patch_ops == expected_patch);
+    }
+
+    #[test]
+    fn op_count_cost_model_should_compact_multiple_nested_changes() {
+        let left = serde_json::json!({"settings": {"a": 1, "b": 2, "c": 3}, "name": "x"});
+        let right = serde_json::json!({"settings": {"a": 10, "b": 20, "c": 3}, "name": "x"});
+
+        let (patch_ops, diff_errors) = diff_recursive(
+            &left,
+            &right,
+            DiffOptions::new().with_cost_model(&crate::diff::OpCount),
+            &Spath::default(),
+        );
+
+        let expected_patch = Patch::new(vec![PatchOp::replace(
+            path("/settings"),
+            serde_json::json!({"a": 10, "b": 20, "c": 3}),
+        )]);
+
+        check!(diff_errors.is_empty() == true);
+        check!(patch_ops == expected_patch);
+    }
+
+    #[test]
+    fn weighted_cost_model_should_keep_granular_ops_when_values_are_expensive() {
+        let left = serde_json::json!({"a": 1, "b": 2});
+        let right = serde_json::json!({"a": 10, "b": 20});
+        let model = crate::diff::WeightedCost {
+            per_value_byte: 1,
+            ..crate::diff::WeightedCost::default()
+        };
+
+        let (patch_ops, diff_errors) = diff_recursive(
+            &left,
+            &right,
+            DiffOptions::new().with_cost_model(&model),
+            &Spath::default(),
+        );
+
+        let expected_patch = Patch::new(vec![
+            PatchOp::replace(path("/a"), serde_json::json!(10)),
+            PatchOp::replace(path("/b"), serde_json::json!(20)),
+        ]);
+
+        check!(diff_errors.is_empty() == true);
+        check!(patch_ops == expected_patch);
+    }
+
+    #[test]
+    fn diff_into_sink_should_borrow_values_from_the_documents() {
+        let left = serde_json::json!({"a": {"x": 1}, "b": [1]});
+        let right = serde_json::json!({"a": {"x": 2}, "b": [1, {"big": true}]});
+
+        let mut ops: Vec<PatchOpRef> = Vec::new();
+        let diff_errors = diff_into_sink(
+            &left,
+            &right,
+            DiffOptions::new().granular(),
+            &Spath::default(),
+            &mut ops,
+        );
+
+        check!(diff_errors.is_empty() == true);
+        check!(ops.len() == 2);
+        check!(let PatchOpRef::Replace { value: Cow::Borrowed(_), .. } = &ops[0]);
+        assert!(let PatchOpRef::Add { value: Cow::Borrowed(value), .. } = &ops[1]);
+        check!(std::ptr::eq(*value, &right["b"][1]));
+    }
+
+    #[test]
+    fn compact_diff_should_match_granular_diff_outside_compacted_objects() {
+        let schema = serde_json::json!({
+            "properties": {
+                "users": { "x-spatch-indexKey": "id" }
+            }
+        });
+        let left = serde_json::json!({
+            "settings": {"a": 1, "b": 2},
+            "users": [{"id": "u-1", "name": "Ada"}]
+        });
+        let right = serde_json::json!({
+            "settings": {"a": 10, "b": 20},
+            "users": [{"id": "u-1", "name": "Ada Lovelace"}]
+        });
+
+        let (patch_ops, diff_errors) = diff_recursive(
+            &left,
+            &right,
+            DiffOptions::new().with_schema(&schema),
+            &Spath::default(),
+        );
+
+        let expected_patch = Patch::new(vec![
+            PatchOp::replace(path("/settings"), serde_json::json!({"a": 10, "b": 20})),
+            PatchOp::replace(
+                path("/users/[id=u-1]/name"),
+                serde_json::json!("Ada Lovelace"),
+            ),
+        ]);
+
+        check!(diff_errors.is_empty() == true);
+        check!(patch_ops == expected_patch);
+    }
+
+    #[test]
+    fn ignored_subtrees_should_not_be_diffed() {
+        let schema = serde_json::json!({"properties": {"items": {"x-spatch-indexKey": "id"}}});
+        let left = serde_json::json!({
+            "meta": {"updatedAt": 1, "name": "a"},
+            "items": [{"id": "i-1", "etag": "x", "n": 1}, {"id": "i-2", "etag": "y"}]
+        });
+        let right = serde_json::json!({
+            "meta": {"updatedAt": 2, "name": "a"},
+            "items": [{"id": "i-1", "etag": "z", "n": 2}, {"id": "i-2", "etag": "w"}]
+        });
+        let ignore = [
+            "/meta/updatedAt".try_into().unwrap(),
+            "/items/*/etag".try_into().unwrap(),
+        ];
+
+        let (patch_ops, diff_errors) = diff_recursive(
+            &left,
+            &right,
+            DiffOptions::new().with_schema(&schema).ignore(&ignore),
+            &Spath::default(),
+        );
+
+        check!(diff_errors.is_empty());
+        check!(
+            patch_ops
+                == Patch::new(vec![PatchOp::replace(
+                    path("/items/[id=i-1]/n"),
+                    serde_json::json!(2)
+                )])
+        );
+    }
+
+    #[test]
+    fn compact_diff_should_not_replace_objects_with_ignored_changes() {
+        let left = serde_json::json!({"meta": {"updatedAt": 1, "a": 1, "b": 2}});
+        let right = serde_json::json!({"meta": {"updatedAt": 2, "a": 10, "b": 20}});
+        let ignore = ["/meta/updatedAt".try_into().unwrap()];
+
+        let (patch_ops, _) = diff_recursive(
+            &left,
+            &right,
+            DiffOptions::new().compact().ignore(&ignore),
+            &Spath::default(),
+        );
+
+        check!(
+            patch_ops
+                == Patch::new(vec![
+                    PatchOp::replace(path("/meta/a"), serde_json::json!(10)),
+                    PatchOp::replace(path("/meta/b"), serde_json::json!(20)),
+                ])
+        );
+    }
+
+    #[test]
+    fn only_should_restrict_the_diff_to_matching_subtrees() {
+        let left = serde_json::json!({"spec": {"a": 1, "b": 2}, "status": {"c": 3}, "x": 1});
+        let right = serde_json::json!({"spec": {"a": 10, "b": 20}, "status": {"c": 4}, "y": 1});
+        let only = ["/spec".try_into().unwrap(), "/status/c".try_into().unwrap()];
+
+        let (patch_ops, _) = diff_recursive(
+            &left,
+            &right,
+            DiffOptions::new().only(&only),
+            &Spath::default(),
+        );
+
+        check!(
+            patch_ops
+                == Patch::new(vec![
+                    PatchOp::replace(path("/spec"), serde_json::json!({"a": 10, "b": 20})),
+                    PatchOp::replace(path("/status/c"), serde_json::json!(4)),
+                ])
+        );
+    }
+
+    #[test]
+    fn schema_ignored_members_should_not_be_diffed() {
+        let schema = serde_json::json!({"properties": {
+            "updatedAt": {"x-spatch-ignore": true},
+            "etag": {"x-spatch-ignore": true},
+            "old": {"x-spatch-ignore": true}
+        }});
+        let left = serde_json::json!({"updatedAt": 1, "old": "x", "name": "a", "b": 1});
+        let right = serde_json::json!({"updatedAt": 2, "etag": "y", "name": "b", "b": 2});
+
+        let (patch_ops, _) = diff_recursive(
+            &left,
+            &right,
+            DiffOptions::new().with_schema(&schema),
+            &Spath::default(),
+        );
+
+        check!(
+            patch_ops
+                == Patch::new(vec![
+                    PatchOp::replace(path("/b"), serde_json::json!(2)),
+                    PatchOp::replace(path("/name"), serde_json::json!("b")),
+                ])
+        );
+    }
+
+    #[test]
+    fn ignored_move_targets_should_still_remove_the_source() {
+        let ignore = ["/new".try_into().unwrap()];
+        let left = serde_json::json!({"old": {"a": [1, 2, 3]}});
+        let right = serde_json::json!({"new": {"a": [1, 2, 3]}});
+
+        let (patch_ops, _) = diff_recursive(
+            &left,
+            &right,
+            DiffOptions::new()
+                .granular()
+                .with_move_detection(1)
+                .ignore(&ignore),
+            &Spath::default(),
+        );
+
+        check!(patch_ops == Patch::new(vec![PatchOp::remove(path("/old"))]));
+    }
+
+    #[test]
+    fn moves_from_filtered_sources_should_add_the_target() {
+        let left = serde_json::json!({"a": {"x": 1, "y": 2}});
+        let right = serde_json::json!({"b": {"x": 1, "y": 2}});
+        let ignore = ["/a".try_into().unwrap()];
+        let only = ["/b".try_into().unwrap()];
+
+        for options in [
+            DiffOptions::new().ignore(&ignore),
+            DiffOptions::new().only(&only),
+        ] {
+            let (patch_ops, _) = diff_recursive(
+                &left,
+                &right,
+                options.granular().with_move_detection(1),
+                &Spath::default(),
+            );
+
+            check!(
+                patch_ops
+                    == Patch::new(vec![PatchOp::add(
+                        path("/b"),
+                        serde_json::json!({"x": 1, "y": 2})
+                    )])
+            );
+        }
+    }
+
+    #[test]
+    fn schema_ignored_members_should_not_be_moved_or_copied() {
+        let schema = serde_json::json!({"properties": {"cache": {"x-spatch-ignore": true}}});
+        let options = DiffOptions::new()
+            .with_schema(&schema)
+            .granular()
+            .with_move_detection(1);
+        let left = serde_json::json!({"cache": {"x": 1, "y": 2}});
+
+        let moved = serde_json::json!({"data": {"x": 1, "y": 2}});
+        let (patch_ops, _) = diff_recursive(&left, &moved, options, &Spath::default());
+        check!(
+            patch_ops
+                == Patch::new(vec![PatchOp::add(
+                    path("/data"),
+                    serde_json::json!({"x": 1, "y": 2})
+                )])
+        );
+
+        let copied = serde_json::json!({"cache": {"x": 1, "y": 2}, "data": {"x": 1, "y": 2}});
+        let (patch_ops, _) = diff_recursive(&left, &copied, options, &Spath::default());
+        check!(
+            patch_ops
+                == Patch::new(vec![PatchOp::add(
+                    path("/data"),
+                    serde_json::json!({"x": 1, "y": 2})
+                )])
+        );
+    }
+
+    #[test]
+    fn schema_comparison_should_hold_for_the_whole_subtree() {
+        let schema = serde_json::json!({"properties": {
+            "geo": {"x-spatch-compare": "numeric-tolerance", "x-spatch-tolerance": 0.001},
+            "tags": {"x-spatch-compare": "unordered"},
+            "names": {"x-spatch-compare": "case-insensitive"}
+        }});
+        let left = serde_json::json!({
+            "geo": {"lat": 51.5, "lng": [0.1, -0.12]},
+            "tags": ["a", "b"],
+            "names": {"first": "Ada", "last": "Lovelace"}
+        });
+        let right = serde_json::json!({
+            "geo": {"lat": 51.5004, "lng": [0.1, -0.2]},
+            "tags": ["b", "a"],
+            "names": {"first": "ADA", "last": "Byron"}
+        });
+
+        let (patch_ops, _) = diff_recursive(
+            &left,
+            &right,
+            DiffOptions::new().with_schema(&schema),
+            &Spath::default(),
+        );
+
+        check!(
+            patch_ops
+                == Patch::new(vec![
+                    PatchOp::replace(path("/geo/lng/1"), serde_json::json!(-0.2)),
+                    PatchOp::replace(path("/names/last"), serde_json::json!("Byron")),
+                ])
+        );
+    }
+
+    #[test]
+    fn path_comparators_should_take_precedence_over_the_global_comparator() {
+        let exact = crate::diff::NumericEpsilon(0.0);
+        let comparators: [crate::diff::PathComparator; 1] =
+            [("/prices/*".try_into().unwrap(), &exact)];
+        let left = serde_json::json!({"prices": [1.0], "ratio": 0.5, "name": "a "});
+        let right = serde_json::json!({"prices": [1.001], "ratio": 0.501, "name": "a"});
+
+        let (patch_ops, _) = diff_recursive(
+            &left,
+            &right,
+            DiffOptions::new()
+                .with_comparator(&crate::diff::NumericEpsilon(0.01))
+                .with_path_comparators(&comparators),
+            &Spath::default(),
+        );
+
+        // The equal ratio also keeps the root from being replaced
+        check!(
+            patch_ops
+                == Patch::new(vec![
+                    PatchOp::replace(path("/name"), serde_json::json!("a")),
+                    PatchOp::replace(path("/prices/0"), serde_json::json!(1.001)),
+                ])
+        );
+    }
+
+    #[test]
+    fn atomic_subtrees_should_be_replaced_whole_in_granular_mode() {
+        let schema = serde_json::json!({"properties": {
+            "shapes": {
+                "x-spatch-indexKey": "id",
+                "items": {"properties": {"geometry": {"x-spatch-atomic": true}}}
+            },
+            "payload": {"x-spatch-atomic": true}
+        }});
+        let left = serde_json::json!({
+            "shapes": [{"id": "s-1", "geometry": {"points": [[0, 0], [1, 1]]}, "name": "a"}],
+            "payload": ["x", "y"],
+            "same": {"a": 1}
+        });
+        let right = serde_json::json!({
+            "shapes": [{"id": "s-1", "geometry": {"points": [[0, 0], [1, 2]]}, "name": "b"}],
+            "payload": ["x", "z"],
+            "same": {"a": 1}
+        });
+
+        let (patch_ops, _) = diff_recursive(
+            &left,
+            &right,
+            DiffOptions::new().with_schema(&schema).granular(),
+            &Spath::default(),
+        );
+
+        check!(
+            patch_ops
+                == Patch::new(vec![
+                    PatchOp::replace(path("/payload"), serde_json::json!(["x", "z"])),
+                    PatchOp::replace(
+                        path("/shapes/[id=s-1]/geometry"),
+                        serde_json::json!({"points": [[0, 0], [1, 2]]})
+                    ),
+                    PatchOp::replace(path("/shapes/[id=s-1]/name"), serde_json::json!("b")),
+                ])
+        );
+    }
+
+    #[test]
+    fn atomic_paths_should_match_array_elements() {
+        let atomic = ["/items/*".try_into().unwrap()];
+        let left = serde_json::json!({"items": [{"a": 1, "b": 2}, {"a": 1}]});
+        let right = serde_json::json!({"items": [{"a": 1, "b": 3}, {"a": 1}]});
+
+        let (patch_ops, _) = diff_recursive(
+            &left,
+            &right,
+            DiffOptions::new().granular().atomic(&atomic),
+            &Spath::default(),
+        );
+
+        check!(
+            patch_ops
+                == Patch::new(vec![PatchOp::replace(
+                    path("/items/0"),
+                    serde_json::json!({"a": 1, "b": 3})
+                )])
+        );
+    }
+
+    #[test]
+    fn schema_granularity_should_hold_for_the_subtree() {
+        let schema = serde_json::json!({
+            "x-spatch-granularity": "granular",
+            "properties": {
+                "settings": {
+                    "properties": {"bulk": {"x-spatch-granularity": "compact"}}
+                },
+                "data": {"x-spatch-granularity": "compact"}
+            }
+        });
+        let left = serde_json::json!({
+            "settings": {"a": 1, "b": 2, "bulk": {"x": 1, "y": 2}},
+            "data": {"a": 1, "b": 2}
+        });
+        let right = serde_json::json!({
+            "settings": {"a": 10, "b": 20, "bulk": {"x": 10, "y": 20}},
+            "data": {"a": 10, "b": 20}
+        });
+
+        // The root annotation wins over the compact default
+        let (patch_ops, _) = diff_recursive(
+            &left,
+            &right,
+            DiffOptions::new().with_schema(&schema).compact(),
+            &Spath::default(),
+        );
+
+        check!(
+            patch_ops
+                == Patch::new(vec![
+                    PatchOp::replace(path("/data"), serde_json::json!({"a": 10, "b": 20})),
+                    PatchOp::replace(path("/settings/a"), serde_json::json!(10)),
+                    PatchOp::replace(path("/settings/b"), serde_json::json!(20)),
+                    PatchOp::replace(
+                        path("/settings/bulk"),
+                        serde_json::json!({"x": 10, "y": 20})
+                    ),
+                ])
+        );
+    }
+
+    #[test]
+    fn compact_parents_should_keep_the_operations_of_granular_subtrees() {
+        let schema = serde_json::json!({"properties": {
+            "settings": {"x-spatch-granularity": "granular"}
+        }});
+        let left = serde_json::json!({"settings": {"a": 1, "b": 2}, "c": 3});
+        let right = serde_json::json!({"settings": {"a": 10, "b": 20}, "c": 30});
+
+        let (patch_ops, _) = diff_recursive(
+            &left,
+            &right,
+            DiffOptions::new()
+                .with_schema(&schema)
+                .with_cost_model(&crate::diff::OpCount),
+            &Spath::default(),
+        );
+
+        check!(
+            patch_ops
+                == Patch::new(vec![
+                    PatchOp::replace(path("/c"), serde_json::json!(30)),
+                    PatchOp::replace(path("/settings/a"), serde_json::json!(10)),
+                    PatchOp::replace(path("/settings/b"), serde_json::json!(20)),
+                ])
+        );
+    }
+
+    #[test]
+    fn max_depth_should_count_keyed_identities_as_one_level() {
+        let schema = serde_json::json!({"properties": {"users": {"x-spatch-indexKey": "id"}}});
+        let left = serde_json::json!({
+            "users": [{"id": "u-1", "name": "Ada", "tags": ["a"]}, {"id": "u-2"}],
+            "meta": {"a": {"b": 1}}
+        });
+        let right = serde_json::json!({
+            "users": [{"id": "u-1", "name": "Ada", "tags": ["b"]}, {"id": "u-3"}],
+            "meta": {"a": {"b": 2}}
+        });
+
+        let (patch_ops, _) = diff_recursive(
+            &left,
+            &right,
+            DiffOptions::new()
+                .with_schema(&schema)
+                .granular()
+                .max_depth(2),
+            &Spath::default(),
+        );
+
+        check!(
+            patch_ops
+                == Patch::new(vec![
+                    PatchOp::replace(path("/meta/a"), serde_json::json!({"b": 2})),
+                    PatchOp::remove(path("/users/[id=u-2]")),
+                    PatchOp::add(path("/users/-"), serde_json::json!({"id": "u-3"})),
+                    PatchOp::replace(
+                        path("/users/[id=u-1]"),
+                        serde_json::json!({"id": "u-1", "name": "Ada", "tags": ["b"]})
+                    ),
+                ])
+        );
+    }
+
+    #[test]
+    fn max_depth_zero_should_replace_the_root() {
+        let left = serde_json::json!({"a": 1});
+        let right = serde_json::json!({"a": 2});
+
+        let (patch_ops, _) = diff_recursive(
+            &left,
+            &right,
+            DiffOptions::new().granular().max_depth(0),
+            &Spath::default(),
+        );
+
+        check!(patch_ops == Patch::new(vec![PatchOp::replace(Spath::default(), right.clone())]));
     }
 }
@@ -52,22 +52,42 @@
 //!
 //! assert_eq!(patch_json[0]["path"], "/users/[id=u-2]/name");
 //! ```
+mod compare;
+mod compose;
+mod cost;
 mod engine;
 mod error;
+mod moves;
+mod normalize;
 mod options;
 mod patch_operations;
 mod schema;
+mod sink;
+mod stats;
 #[cfg(test)]
 pub mod test_util;
 
 use std::ops::{Add, Deref};
 
-pub use options::{DiffGranularity, DiffOptions};
-pub use patch_operations::PatchOp;
+pub use compare::{
+    NumericEpsilon, NumericTypeInsensitive, PathComparator, TrimmedStrings, UnicodeNormalized,
+    ValueComparator,
+};
+pub(crate) use cost::json_len;
+pub use cost::{ByteSize, CostModel, OpCount, WeightedCost};
+pub(crate) use engine::{asserts_old_value, diff_into_sink, index_key_value_to_filter};
+pub use error::{DiffError, DiffErrorSummary};
+pub use options::{DiffGranularity, DiffOptions, TestMode};
+pub use patch_operations::{OpKind, PatchOp, PatchOpRef};
 pub use schema::SchemaResolver;
 use serde::Serialize;
+pub use sink::PatchSink;
+pub use stats::PatchStats;
 
-use crate::{diff::error::DiffErrorSummary, path::Spath};
+use crate::{
+    document::Document,
+    path::{PathFilter, Spath},
+};
 
 /// A sequence of JSON Patch operations produced by [`diff`].
 ///
@@ -89,6 +109,38 @@ impl Patch {
     pub fn push(&mut self, op: PatchOp) {
         self.0.push(op);
     }
+
+    /// Returns the operations whose paths `filter` includes, dropping the
+    /// others. Operations with a `from` path are kept only when both paths are
+    /// included.
+    ///
+    /// This applies the filter of [`DiffOptions::ignore`] and
+    /// [`DiffOptions::only`] to a patch computed or written elsewhere.
+    ///
+    /// ```rust
+    /// use serde_json::json;
+    /// use spatch::{diff::{Patch, PatchOp}, path::{PathFilter, PathPattern}};
+    ///
+    /// let patch = Patch::new(vec![
+    ///     PatchOp::replace("/meta/updatedAt".try_into().unwrap(), json!(2)),
+    ///     PatchOp::replace("/name".try_into().unwrap(), json!("Ada")),
+    /// ]);
+    /// let ignore = [PathPattern::try_from("/meta/*").unwrap()];
+    ///
+    /// let filtered = patch.filter_paths(PathFilter::new().ignore(&ignore));
+    ///
+    /// assert_eq!(*filtered, [PatchOp::replace("/name".try_into().unwrap(), json!("Ada"))]);
+    /// ```
+    pub fn filter_paths(&self, filter: PathFilter) -> Patch {
+        let ops = self
+            .iter()
+            .filter(|op| {
+                filter.includes(op.path()) && op.from().is_none_or(|from| filter.includes(from))
+            })
+            .cloned()
+            .collect();
+        Patch::new(ops)
+    }
 }
 
 impl Deref for Patch {
@@ -109,15 +161,21 @@ impl Add for Patch {
     }
 }
 
-impl Iterator for Patch {
+impl IntoIterator for Patch {
     type Item = PatchOp;
+    type IntoIter = std::vec::IntoIter<PatchOp>;
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.0.is_empty() {
-            None
-        } else {
-            Some(self.0.remove(0))
-        }
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'p> IntoIterator for &'p Patch {
+    type Item = &'p PatchOp;
+    type IntoIter = std::slice::Iter<'p, PatchOp>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
     }
 }
 
@@ -136,6 +194,12 @@ impl Iterator for Patch {
 /// `null` identity values are rejected because they cannot be emitted as semantic
 /// path filters.
 ///
+/// The output is deterministic: diffing identical inputs with identical options
+/// always produces the same operations in the same order, so the serialized
+/// patch is byte-for-byte stable. Object members and keyed array items are
+/// visited in sorted key order. Use [`Patch::normalize`] to compare patches
+/// that were produced or edited elsewhere.
+///
 /// # Compact by default
 ///
 /// ```rust
@@ -207,13 +271,12 @@ impl Iterator for Patch {
 ///
 /// assert_eq!(patch_json[0]["path"], "/levels/[id=1]/xp");
 /// ```
-pub fn diff(
-    left: &serde_json::Value,
-    right: &serde_json::Value,
+pub fn diff<D: Document>(
+    left: &D,
+    right: &D,
     options: DiffOptions<'_>,
 ) -> Result<Patch, DiffErrorSummary> {
-    let (patch, error_summary) =
-        engine::diff_recursive(left, right, options, &Spath::default(), &Patch::default());
+    let (patch, error_summary) = engine::diff_recursive(left, right, options, &Spath::default());
 
     if error_summary.is_empty() {
         Ok(patch)
@@ -222,6 +285,48 @@ pub fn diff(
     }
 }
 
+/// Computes the diff of `left` and `right` like [`diff`], pushing operations
+/// into `sink` instead of collecting them.
+///
+/// Operations are pushed as soon as they are final, with values borrowed from
+/// `left` and `right` when they are `serde_json` values. Granular diffs and subtrees addressed by semantic paths
+/// are forwarded immediately. In compact mode, the operations of an object are
+/// held back until it is known whether a single `replace` of the object is
+/// cheaper.
+///
+/// Errors are reported once the whole diff is computed, so `sink` may already
+/// have received operations when an error is returned.
+///
+/// ```rust
+/// use serde_json::json;
+/// use spatch::diff::{diff_into, DiffOptions, PatchOpRef};
+///
+/// let before = json!({"name": "Ada"});
+/// let after = json!({"name": "Ada Lovelace"});
+///
+/// let mut ops: Vec<PatchOpRef> = Vec::new();
+/// diff_into(&before, &after, DiffOptions::new(), &mut ops).unwrap();
+///
+/// assert_eq!(ops[0].path().to_string(), "/name");
+/// ```
+pub fn diff_into<'a, D: Document>(
+    left: &'a D,
+    right: &'a D,
+    options: DiffOptions<'_>,
+    sink: &mut impl PatchSink<'a>,
+) -> Result<(), DiffErrorSummary> {
+    // Applies the annotations of the schema itself, like the ones of child
+    // schemas
+    let options = options.with_optional_schema(options.schema);
+    let error_summary = engine::diff_into_sink(left, right, options, &Spath::default(), sink);
+
+    if error_summary.is_empty() {
+        Ok(())
+    } else {
+        Err(error_summary)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use assert2::{assert, check};
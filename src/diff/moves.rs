@@ -0,0 +1,162 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
+use serde_json::Value;
+
+use crate::{
+    diff::{DiffOptions, PatchOp},
+    document::Document,
+    path::{Segment, Spath},
+};
+
+/// `move` and `copy` operations detected between the members of two objects.
+#[derive(Debug, Default)]
+pub(super) struct DetectedMoves<'v> {
+    /// Operations replacing the `add` of the keyed right-hand member.
+    pub(super) ops: BTreeMap<Cow<'v, str>, PatchOp>,
+
+    /// Left-hand members that were moved away and must not be removed again.
+    pub(super) moved_from: BTreeSet<Cow<'v, str>>,
+}
+
+/// Finds members that disappear from `left_map` or stay unchanged in it and
+/// appear, with identical content, under a new key in `right_map`.
+///
+/// Only subtrees with at least `min_size` JSON values are considered, so small
+/// scalars keep being emitted as plain `add` operations. Candidates are matched
+/// by content hash and confirmed by comparing the values. Members the schema
+/// ignores or the path filter leaves out are never sources, since moving them
+/// would remove them.
+pub(super) fn detect_moves<'v, D: Document>(
+    left_map: &'v D,
+    right_map: &'v D,
+    path_pointer: &Spath,
+    min_size: usize,
+    options: DiffOptions,
+) -> DetectedMoves<'v> {
+    let mut detected = DetectedMoves::default();
+
+    let added: Vec<(Cow<'v, str>, &D)> = right_map
+        .members()
+        .filter(|(key, value)| left_map.member(key).is_none() && subtree_size(*value) >= min_size)
+        .collect();
+    if added.is_empty() {
+        return detected;
+    }
+
+    // Sources in key order: removed members can be moved, members present on
+    // both sides with the same value can be copied.
+    let mut sources: HashMap<u64, Vec<(Cow<'v, str>, bool)>> = HashMap::new();
+    for (key, value) in left_map.members() {
+        let removed = match right_map.member(&key) {
+            None => true,
+            Some(right_value) if right_value == value => false,
+            Some(_) => continue,
+        };
+        let excluded = options.resolver().is_ignored(options.property_schema(&key))
+            || !options
+                .path_filter
+                .includes(&path_pointer.push(Segment::Field(key.clone().into_owned())));
+        if !excluded && subtree_size(value) >= min_size {
+            sources
+                .entry(content_hash(value))
+                .or_default()
+                .push((key, removed));
+        }
+    }
+
+    // Destinations of earlier moves, usable as copy sources afterwards.
+    let mut moved_to: HashMap<u64, Vec<Cow<'v, str>>> = HashMap::new();
+
+    for (key, value) in added {
+        let hash = content_hash(value);
+        let path = path_pointer.push(Segment::Field(key.clone().into_owned()));
+        let candidates = sources.get(&hash).map(Vec::as_slice).unwrap_or_default();
+
+        let movable = candidates.iter().find(|(source, removed)| {
+            *removed
+                && !detected.moved_from.contains(source.as_ref())
+                && left_map.member(source) == Some(value)
+        });
+        if let Some((source, _)) = movable {
+            let from = path_pointer.push(Segment::Field(source.clone().into_owned()));
+            detected
+                .ops
+                .insert(key.clone(), PatchOp::move_op(from, path));
+            detected.moved_from.insert(source.clone());
+            moved_to.entry(hash).or_default().push(key);
+            continue;
+        }
+
+        let copyable = candidates
+            .iter()
+            .filter(|(_, removed)| !removed)
+            .map(|(source, _)| source)
+            .chain(moved_to.get(&hash).into_iter().flatten())
+            .find(|source| {
+                left_map
+                    .member(source)
+                    .or_else(|| right_map.member(source))
+                    .is_some_and(|candidate| candidate == value)
+            });
+        if let Some(source) = copyable {
+            let from = path_pointer.push(Segment::Field(source.clone().into_owned()));
+            detected.ops.insert(key, PatchOp::copy(from, path));
+        }
+    }
+
+    detected
+}
+
+/// Number of JSON values in the subtree, including the root.
+pub(super) fn subtree_size<D: Document>(value: &D) -> usize {
+    match value.elements() {
+        Some(items) => 1 + items.iter().map(subtree_size).sum::<usize>(),
+        None => {
+            1 + value
+                .members()
+                .map(|(_, member)| subtree_size(member))
+                .sum::<usize>()
+        }
+    }
+}
+
+fn content_hash<D: Document>(value: &D) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hash_value(value, &mut hasher);
+    hasher.finish()
+}
+
+fn hash_value<D: Document>(value: &D, hasher: &mut DefaultHasher) {
+    if let Some(items) = value.elements() {
+        (4u8, items.len()).hash(hasher);
+        items.iter().for_each(|item| hash_value(item, hasher));
+        return;
+    }
+    if let Some(len) = value.object_len() {
+        // Members are combined independently of their order, because some
+        // formats compare objects without regard to member order.
+        let members = value
+            .members()
+            .map(|(key, member)| {
+                let mut member_hasher = DefaultHasher::new();
+                key.hash(&mut member_hasher);
+                hash_value(member, &mut member_hasher);
+                member_hasher.finish()
+            })
+            .fold(0u64, u64::wrapping_add);
+        (5u8, len, members).hash(hasher);
+        return;
+    }
+
+    match &*value.to_json() {
+        Value::Null => 0u8.hash(hasher),
+        Value::Bool(b) => (1u8, b).hash(hasher),
+        Value::Number(n) => (2u8, n.to_string()).hash(hasher),
+        Value::String(s) => (3u8, s).hash(hasher),
+        Value::Array(_) | Value::Object(_) => unreachable!("containers are hashed above"),
+    }
+}
@@ -0,0 +1,270 @@
+use std::collections::BTreeSet;
+
+use crate::diff::{Patch, PatchOp, compose::ops_interfere};
+
+impl Patch {
+    /// Returns the patch in canonical form.
+    ///
+    /// Normalization makes semantically equal patches compare, hash, and
+    /// serialize the same way:
+    ///
+    /// - exact duplicates of idempotent operations (`replace`, `test`, and
+    ///   `add` on an object member) are dropped when nothing in between touches
+    ///   their path;
+    /// - independent operations are sorted by path, operation, and value;
+    /// - operations that depend on each other keep their relative order, for
+    ///   example a `replace` of `/a` stays after an `add` of `/a`, and inserts
+    ///   into the same array are never reordered.
+    ///
+    /// The normalized patch produces the same document as the original one.
+    ///
+    /// ```rust
+    /// use serde_json::json;
+    /// use spatch::diff::{Patch, PatchOp};
+    ///
+    /// let a = Patch::new(vec![
+    ///     PatchOp::replace("/b".try_into().unwrap(), json!(2)),
+    ///     PatchOp::replace("/a".try_into().unwrap(), json!(1)),
+    /// ]);
+    /// let b = Patch::new(vec![
+    ///     PatchOp::replace("/a".try_into().unwrap(), json!(1)),
+    ///     PatchOp::replace("/b".try_into().unwrap(), json!(2)),
+    /// ]);
+    ///
+    /// assert_eq!(a.normalize(), b.normalize());
+    /// ```
+    pub fn normalize(&self) -> Patch {
+        let ops = dedup(&self.0);
+        let keys: Vec<String> = ops.iter().map(|op| sort_key(op)).collect();
+
+        // `dependencies[j]` counts the earlier operations `j` has to stay after.
+        let mut dependencies = vec![0usize; ops.len()];
+        let mut dependents = vec![Vec::new(); ops.len()];
+        for j in 0..ops.len() {
+            for i in 0..j {
+                if depends_on(ops[j], ops[i]) {
+                    dependencies[j] += 1;
+                    dependents[i].push(j);
+                }
+            }
+        }
+
+        let mut ready: BTreeSet<(&str, usize)> = (0..ops.len())
+            .filter(|&i| dependencies[i] == 0)
+            .map(|i| (keys[i].as_str(), i))
+            .collect();
+
+        let mut normalized = Vec::with_capacity(ops.len());
+        while let Some((_, i)) = ready.pop_first() {
+            normalized.push(ops[i].clone());
+            for &j in &dependents[i] {
+                dependencies[j] -= 1;
+                if dependencies[j] == 0 {
+                    ready.insert((keys[j].as_str(), j));
+                }
+            }
+        }
+
+        Patch::new(normalized)
+    }
+}
+
+fn dedup(ops: &[PatchOp]) -> Vec<&PatchOp> {
+    let mut kept: Vec<&PatchOp> = Vec::with_capacity(ops.len());
+    for op in ops {
+        let duplicate = is_idempotent(op)
+            && kept
+                .iter()
+                .rev()
+                .take_while(|previous| *previous == &op || !writes_to(previous, op))
+                .any(|previous| *previous == op);
+
+        if !duplicate {
+            kept.push(op);
+        }
+    }
+    kept
+}
+
+fn is_idempotent(op: &PatchOp) -> bool {
+    match op {
+        PatchOp::Replace { .. } | PatchOp::Test { .. } => true,
+        PatchOp::Add { path, .. } => !path
+            .field()
+            .is_some_and(|field| field == "-" || field.parse::<usize>().is_ok()),
+        PatchOp::Remove { .. } | PatchOp::Move { .. } | PatchOp::Copy { .. } => false,
+    }
+}
+
+fn writes_to(previous: &PatchOp, op: &PatchOp) -> bool {
+    !matches!(previous, PatchOp::Test { .. }) && ops_interfere(previous, op.path())
+}
+
+fn depends_on(op: &PatchOp, earlier: &PatchOp) -> bool {
+    if matches!((op, earlier), (PatchOp::Test { .. }, PatchOp::Test { .. })) {
+        return false;
+    }
+
+    ops_interfere(earlier, op.path()) || op.from().is_some_and(|from| ops_interfere(earlier, from))
+}
+
+fn sort_key(op: &PatchOp) -> String {
+    let rank = match op {
+        PatchOp::Test { .. } => 0,
+        PatchOp::Remove { .. } => 1,
+        PatchOp::Replace { .. } => 2,
+        PatchOp::Add { .. } => 3,
+        PatchOp::Move { .. } => 4,
+        PatchOp::Copy { .. } => 5,
+    };
+    let value = match op {
+        PatchOp::Add { value, .. }
+        | PatchOp::Replace { value, .. }
+        | PatchOp::Test { value, .. } => value.to_string(),
+        PatchOp::Remove { .. } => String::new(),
+        PatchOp::Move { from, .. } | PatchOp::Copy { from, .. } => from.to_string(),
+    };
+
+    format!("{}\u{0}{rank}\u{0}{value}", op.path())
+}
+
+#[cfg(test)]
+mod tests {
+    use assert2::check;
+    use serde_json::json;
+
+    use crate::{
+        diff::{DiffOptions, diff},
+        path::Spath,
+    };
+
+    use super::*;
+
+    fn path(raw: &str) -> Spath {
+        raw.try_into().unwrap()
+    }
+
+    #[test]
+    fn normalize_should_sort_independent_operations() {
+        let patch = Patch::new(vec![
+            PatchOp::add(path("/c"), json!(3)),
+            PatchOp::remove(path("/b")),
+            PatchOp::replace(path("/a"), json!(1)),
+        ]);
+
+        check!(
+            patch.normalize()
+                == Patch::new(vec![
+                    PatchOp::replace(path("/a"), json!(1)),
+                    PatchOp::remove(path("/b")),
+                    PatchOp::add(path("/c"), json!(3)),
+                ])
+        );
+    }
+
+    #[test]
+    fn normalize_should_keep_dependent_operations_in_order() {
+        let patch = Patch::new(vec![
+            PatchOp::add(path("/z"), json!({})),
+            PatchOp::add(path("/z/a"), json!(1)),
+            PatchOp::add(path("/items/0"), json!("b")),
+            PatchOp::add(path("/items/0"), json!("a")),
+            PatchOp::move_op(path("/z"), path("/b")),
+        ]);
+
+        check!(
+            patch.normalize()
+                == Patch::new(vec![
+                    PatchOp::add(path("/items/0"), json!("b")),
+                    PatchOp::add(path("/items/0"), json!("a")),
+                    PatchOp::add(path("/z"), json!({})),
+                    PatchOp::add(path("/z/a"), json!(1)),
+                    PatchOp::move_op(path("/z"), path("/b")),
+                ])
+        );
+    }
+
+    #[test]
+    fn normalize_should_drop_duplicate_idempotent_operations() {
+        let patch = Patch::new(vec![
+            PatchOp::replace(path("/a"), json!(1)),
+            PatchOp::test(path("/b"), json!(2)),
+            PatchOp::replace(path("/a"), json!(1)),
+            PatchOp::test(path("/b"), json!(2)),
+            PatchOp::add(path("/items/-"), json!(1)),
+            PatchOp::add(path("/items/-"), json!(1)),
+        ]);
+
+        check!(
+            patch.normalize()
+                == Patch::new(vec![
+                    PatchOp::replace(path("/a"), json!(1)),
+                    PatchOp::test(path("/b"), json!(2)),
+                    PatchOp::add(path("/items/-"), json!(1)),
+                    PatchOp::add(path("/items/-"), json!(1)),
+                ])
+        );
+    }
+
+    #[test]
+    fn normalize_should_keep_duplicates_separated_by_a_write() {
+        let patch = Patch::new(vec![
+            PatchOp::replace(path("/a"), json!(1)),
+            PatchOp::replace(path("/a"), json!(2)),
+            PatchOp::replace(path("/a"), json!(1)),
+        ]);
+
+        check!(patch.normalize() == patch);
+    }
+
+    #[test]
+    fn normalize_should_produce_the_same_document() {
+        let doc = json!({"a": {"x": 1}, "items": [1, 2, 3], "z": 0});
+        let patch = Patch::new(vec![
+            PatchOp::replace(path("/z"), json!(1)),
+            PatchOp::remove(path("/items/0")),
+            PatchOp::add(path("/a/y"), json!(2)),
+            PatchOp::copy(path("/a"), path("/b")),
+            PatchOp::add(path("/items/-"), json!(4)),
+            PatchOp::test(path("/z"), json!(1)),
+        ]);
+
+        check!(
+            crate::patch::apply(&doc, &patch.normalize()).unwrap()
+                == crate::patch::apply(&doc, &patch).unwrap()
+        );
+    }
+
+    #[test]
+    fn diff_should_be_deterministic_for_identical_inputs() {
+        let schema = json!({
+            "properties": {
+                "users": { "x-spatch-indexKey": "id" }
+            }
+        });
+        let left = json!({
+            "users": [
+                {"id": "u-3", "name": "Edsger"},
+                {"id": "u-1", "name": "Ada"},
+                {"id": "u-2", "name": "Grace"}
+            ],
+            "b": 1,
+            "a": 2
+        });
+        let right = json!({
+            "a": 3,
+            "users": [
+                {"id": "u-4", "name": "Barbara"},
+                {"id": "u-2", "name": "Grace Hopper"},
+                {"id": "u-5", "name": "Alan"}
+            ]
+        });
+        let options = DiffOptions::new().with_schema(&schema).granular();
+
+        let expected = serde_json::to_vec(&diff(&left, &right, options).unwrap()).unwrap();
+        for _ in 0..32 {
+            let actual = serde_json::to_vec(&diff(&left, &right, options).unwrap()).unwrap();
+            check!(actual == expected);
+        }
+    }
+}
@@ -1,3 +1,8 @@
+use crate::{
+    diff::{ByteSize, CostModel, PathComparator, ValueComparator, compare::Comparison},
+    path::{PathFilter, PathPattern, Spath},
+};
+
 /// Configuration for [`diff`](crate::diff::diff).
 ///
 /// `DiffOptions` lets you choose how patches should read to humans without
@@ -110,6 +115,48 @@ pub struct DiffOptions<'a> {
 
     /// Controls whether object diffs prefer smaller patches or nested patches.
     pub granularity: DiffGranularity,
+
+    /// Controls which `test` operations are emitted as preconditions.
+    pub test_mode: TestMode,
+
+    /// Minimum subtree size, in JSON values, for `move` and `copy` detection.
+    ///
+    /// `None` disables detection, so renamed object members are emitted as a
+    /// `remove` plus an `add` carrying the full value.
+    pub move_detection: Option<usize>,
+
+    /// Decides whether compact mode collapses object changes into a parent
+    /// `replace`. Defaults to [`ByteSize`].
+    pub cost_model: &'a dyn CostModel,
+
+    /// Subtrees to diff, see [`ignore`](Self::ignore) and [`only`](Self::only).
+    pub path_filter: PathFilter<'a>,
+
+    /// Decides whether differing values are equal anyway, see
+    /// [`ValueComparator`]. `None` compares values exactly.
+    pub comparator: Option<&'a dyn ValueComparator>,
+
+    /// Comparators for the subtrees matched by their patterns, taking
+    /// precedence over [`comparator`](Self::comparator). The first matching
+    /// pattern wins.
+    pub path_comparators: &'a [PathComparator<'a>],
+
+    /// Subtrees replaced as a whole when they differ, see
+    /// [`atomic`](Self::atomic).
+    pub atomic_paths: &'a [PathPattern],
+
+    /// Depth below which subtrees aren't diffed, see
+    /// [`max_depth`](Self::max_depth). `None` diffs documents to the leaves.
+    pub max_depth: Option<usize>,
+
+    /// Replaces arrays that differ as a whole instead of diffing their
+    /// elements, unless the schema gives them an index key. Used for merge
+    /// patches.
+    pub(crate) atomic_arrays: bool,
+
+    /// Comparison of the subtree being diffed, from the nearest
+    /// `x-spatch-compare` annotation.
+    pub(crate) comparison: Option<Comparison>,
 }
 
 /// Controls how aggressively spatch collapses object changes.
@@ -144,6 +191,53 @@ pub enum DiffGranularity {
     Granular,
 }
 
+/// Controls which `test` operations spatch emits as preconditions.
+///
+/// Preconditions make a patch safe for optimistic concurrency: when the
+/// document changed after the diff was computed, the `test` operations fail
+/// and [`apply`](crate::patch::apply) rejects the whole patch instead of
+/// silently overwriting someone else's change.
+///
+/// ```rust
+/// use serde_json::json;
+/// use spatch::diff::{diff, DiffOptions, TestMode};
+///
+/// let before = json!({"name": "Ada"});
+/// let after = json!({"name": "Ada Lovelace"});
+///
+/// let patch = diff(&before, &after, DiffOptions::new().with_tests(TestMode::All)).unwrap();
+/// let patch_json = serde_json::to_value(&patch).unwrap();
+///
+/// assert_eq!(patch_json[0], json!({"op": "test", "path": "/name", "value": "Ada"}));
+/// assert_eq!(patch_json[1]["op"], "replace");
+///
+/// let changed_meanwhile = json!({"name": "Grace"});
+/// assert!(spatch::patch::apply(&changed_meanwhile, &patch).is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TestMode {
+    /// Do not emit `test` operations.
+    Disabled,
+
+    /// Emit a `test` asserting the old value before every `replace` and
+    /// `remove`.
+    All,
+
+    /// Emit a `test` asserting only the identity key of keyed array elements
+    /// that are modified or removed, for example
+    /// `{"op": "test", "path": "/users/[id=u-1]/id", "value": "u-1"}`.
+    ///
+    /// This guards against the element disappearing without pinning the rest
+    /// of its content.
+    IdentityOnly,
+
+    /// Like [`All`](Self::All), but only for old values that are strings,
+    /// numbers, booleans, or `null`. Replacing or removing a whole object or
+    /// array is not guarded.
+    LeafValuesOnly,
+}
+
 impl<'a> DiffOptions<'a> {
     /// Creates default diff options.
     ///
@@ -151,7 +245,13 @@ impl<'a> DiffOptions<'a> {
     ///
     /// - no schema, so arrays use standard index-based JSON Patch paths;
     /// - [`DiffGranularity::Compact`], so large object changes can collapse to
-    ///   a smaller parent `replace` operation.
+    ///   a smaller parent `replace` operation;
+    /// - [`TestMode::Disabled`], so no `test` preconditions are emitted;
+    /// - no `move` or `copy` detection;
+    /// - every subtree is diffed;
+    /// - values are compared exactly;
+    /// - documents are diffed to their leaves;
+    /// - the [`ByteSize`] cost model.
     pub fn new() -> Self {
         Self::default()
     }
@@ -282,6 +382,8 @@ impl<'a> DiffOptions<'a> {
     ///
     /// Compact mode is the default. When a parent object replacement serializes
     /// smaller than many child operations, spatch emits the parent `replace`.
+    /// Use [`with_cost_model`](Self::with_cost_model) to compare something
+    /// other than serialized size.
     /// Schema-aware diffs are the exception: if nested operations contain
     /// semantic path filters like `[id=item-42]`, compact mode keeps those
     /// operations so semantic identity is not lost.
@@ -290,14 +392,214 @@ impl<'a> DiffOptions<'a> {
         self
     }
 
+    /// Emits `test` operations as preconditions, see [`TestMode`].
+    pub fn with_tests(mut self, test_mode: TestMode) -> Self {
+        self.test_mode = test_mode;
+        self
+    }
+
+    /// Detects renamed and duplicated object members.
+    ///
+    /// When a member disappears from an object and an identical value appears
+    /// under a new key of the same object, spatch emits a `move` instead of a
+    /// `remove` plus an `add` carrying the full value. When the value also
+    /// stays at its old key, spatch emits a `copy`. Candidates are matched by
+    /// content hash, and only subtrees with at least `min_subtree_size` JSON
+    /// values are considered: `{"a": [1, 2]}` has a size of 4.
+    ///
+    /// ```rust
+    /// use serde_json::json;
+    /// use spatch::diff::{diff, DiffOptions};
+    ///
+    /// let before = json!({"draft": {"title": "Notes", "tags": ["a", "b"]}});
+    /// let after = json!({"published": {"title": "Notes", "tags": ["a", "b"]}});
+    ///
+    /// let patch = diff(&before, &after, DiffOptions::new().with_move_detection(3)).unwrap();
+    /// let patch_json = serde_json::to_value(&patch).unwrap();
+    ///
+    /// assert_eq!(
+    ///     patch_json,
+    ///     json!([{"op": "move", "from": "/draft", "path": "/published"}])
+    /// );
+    /// ```
+    pub fn with_move_detection(mut self, min_subtree_size: usize) -> Self {
+        self.move_detection = Some(min_subtree_size);
+        self
+    }
+
+    /// Sets the cost model used by compact mode, see [`CostModel`].
+    pub fn with_cost_model(mut self, cost_model: &'a dyn CostModel) -> Self {
+        self.cost_model = cost_model;
+        self
+    }
+
+    /// Skips the subtrees matched by `patterns`, like timestamps or generated
+    /// fields that make diffs noisy.
+    ///
+    /// Patterns are [`Spath`](crate::path::Spath)s where a `*` segment matches
+    /// any member, array index, or semantic filter, see [`PathPattern`].
+    ///
+    /// ```rust
+    /// use serde_json::json;
+    /// use spatch::{diff::{diff, DiffOptions}, path::PathPattern};
+    ///
+    /// let ignore = [
+    ///     PathPattern::try_from("/meta/updatedAt").unwrap(),
+    ///     PathPattern::try_from("/items/*/etag").unwrap(),
+    /// ];
+    /// let before = json!({"meta": {"updatedAt": 1}, "items": [{"etag": "a", "n": 1}]});
+    /// let after = json!({"meta": {"updatedAt": 2}, "items": [{"etag": "b", "n": 2}]});
+    ///
+    /// let patch = diff(&before, &after, DiffOptions::new().granular().ignore(&ignore)).unwrap();
+    /// let patch_json = serde_json::to_value(&patch).unwrap();
+    ///
+    /// assert_eq!(patch_json, json!([{"op": "replace", "path": "/items/0/n", "value": 2}]));
+    /// ```
+    pub fn ignore(mut self, patterns: &'a [PathPattern]) -> Self {
+        self.path_filter = self.path_filter.ignore(patterns);
+        self
+    }
+
+    /// Diffs only the subtrees matched by `patterns`, see [`PathPattern`].
+    ///
+    /// Changes outside of them are skipped. [`ignore`](Self::ignore) patterns
+    /// still apply inside them.
+    pub fn only(mut self, patterns: &'a [PathPattern]) -> Self {
+        self.path_filter = self.path_filter.only(patterns);
+        self
+    }
+
+    /// Compares the values of the whole diff with `comparator`, see
+    /// [`ValueComparator`].
+    ///
+    /// ```rust
+    /// use serde_json::json;
+    /// use spatch::diff::{diff, DiffOptions, NumericTypeInsensitive};
+    ///
+    /// let before = json!({"ratio": 1, "name": "a"});
+    /// let after = json!({"ratio": 1.0, "name": "a"});
+    ///
+    /// let patch = diff(&before, &after, DiffOptions::new().with_comparator(&NumericTypeInsensitive))
+    ///     .unwrap();
+    ///
+    /// assert!(patch.is_empty());
+    /// ```
+    pub fn with_comparator(mut self, comparator: &'a dyn ValueComparator) -> Self {
+        self.comparator = Some(comparator);
+        self
+    }
+
+    /// Compares the values inside the subtrees matched by each pattern with
+    /// its comparator, see [`PathPattern`].
+    ///
+    /// ```rust
+    /// use serde_json::json;
+    /// use spatch::{
+    ///     diff::{diff, DiffOptions, NumericEpsilon, PathComparator},
+    ///     path::PathPattern,
+    /// };
+    ///
+    /// let geometry = NumericEpsilon(1e-6);
+    /// let comparators: [PathComparator; 1] =
+    ///     [(PathPattern::try_from("/shapes/*/points").unwrap(), &geometry)];
+    /// let before = json!({"shapes": [{"points": [0.1, 0.2], "scale": 1.0}]});
+    /// let after = json!({"shapes": [{"points": [0.1000000001, 0.2], "scale": 1.0000000001}]});
+    ///
+    /// let patch = diff(&before, &after, DiffOptions::new().granular().with_path_comparators(&comparators))
+    ///     .unwrap();
+    /// let patch_json = serde_json::to_value(&patch).unwrap();
+    ///
+    /// assert_eq!(patch_json[0]["path"], "/shapes/0/scale");
+    /// ```
+    pub fn with_path_comparators(mut self, comparators: &'a [PathComparator<'a>]) -> Self {
+        self.path_comparators = comparators;
+        self
+    }
+
+    /// Treats the subtrees matched by `patterns` as opaque values: when they
+    /// differ, they are replaced with a single `replace`, whatever the
+    /// [`DiffGranularity`]. Schemas mark such subtrees with
+    /// `x-spatch-atomic: true`.
+    ///
+    /// ```rust
+    /// use serde_json::json;
+    /// use spatch::{diff::{diff, DiffOptions}, path::PathPattern};
+    ///
+    /// let atomic = [PathPattern::try_from("/shapes/*/geometry").unwrap()];
+    /// let before = json!({"shapes": [{"geometry": {"type": "Point", "coordinates": [1, 2]}}]});
+    /// let after = json!({"shapes": [{"geometry": {"type": "Point", "coordinates": [1, 3]}}]});
+    ///
+    /// let patch = diff(&before, &after, DiffOptions::new().granular().atomic(&atomic)).unwrap();
+    /// let patch_json = serde_json::to_value(&patch).unwrap();
+    ///
+    /// assert_eq!(patch_json[0]["path"], "/shapes/0/geometry");
+    /// ```
+    pub fn atomic(mut self, patterns: &'a [PathPattern]) -> Self {
+        self.atomic_paths = patterns;
+        self
+    }
+
+    /// Stops diffing `depth` levels below the root: differing subtrees at
+    /// that depth are replaced as a whole. Each member name, array index, or
+    /// keyed array identity like `[id=u-1]` is one level.
+    ///
+    /// This gives a quick overview of which areas of a document changed.
+    ///
+    /// ```rust
+    /// use serde_json::json;
+    /// use spatch::diff::{diff, DiffOptions};
+    ///
+    /// let before = json!({"spec": {"replicas": 1, "image": {"tag": "1.0"}}, "status": "ok"});
+    /// let after = json!({"spec": {"replicas": 2, "image": {"tag": "1.1"}}, "status": "ok"});
+    ///
+    /// let patch = diff(&before, &after, DiffOptions::new().granular().max_depth(2)).unwrap();
+    /// let patch_json = serde_json::to_value(&patch).unwrap();
+    ///
+    /// assert_eq!(patch_json[0]["path"], "/spec/image");
+    /// assert_eq!(patch_json[0]["value"], json!({"tag": "1.1"}));
+    /// assert_eq!(patch_json[1]["path"], "/spec/replicas");
+    /// ```
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Returns `true` when the subtree at `path` is replaced as a whole,
+    /// because it's atomic or at the maximum depth.
+    pub(crate) fn is_atomic_at(&self, path: &Spath) -> bool {
+        self.max_depth
+            .is_some_and(|depth| path.segments.len() >= depth)
+            || self.resolver().is_atomic(self.schema)
+            || self
+                .atomic_paths
+                .iter()
+                .any(|pattern| pattern.matches(path))
+    }
+
+    /// Returns the comparator for the values at `path`.
+    pub(crate) fn comparator_at(&self, path: &Spath) -> Option<&'a dyn ValueComparator> {
+        self.path_comparators
+            .iter()
+            .find(|(pattern, _)| pattern.contains(path))
+            .map(|(_, comparator)| *comparator)
+            .or(self.comparator)
+    }
+
     /// Sets or clears the active schema in one call.
     ///
     /// Passing `Some(schema)` behaves like [`with_schema`](Self::with_schema).
     /// Passing `None` behaves like [`without_schema`](Self::without_schema) and
     /// intentionally prevents a parent schema from leaking into child values
     /// that do not have their own schema entry.
+    ///
+    /// An `x-spatch-granularity` annotation on `schema` overrides the
+    /// granularity for its subtree. Otherwise the current granularity is
+    /// inherited.
     pub fn with_optional_schema(mut self, schema: Option<&'a serde_json::Value>) -> Self {
         self.schema = schema;
+        if let Some(granularity) = self.resolver().granularity(schema) {
+            self.granularity = granularity;
+        }
         self
     }
 
@@ -313,6 +615,19 @@ impl<'a> DiffOptions<'a> {
         self.resolver().items_schema(self.schema)
     }
 
+    pub(crate) fn is_ignored(&self) -> bool {
+        self.resolver().is_ignored(self.schema)
+    }
+
+    /// Applies the `x-spatch-compare` annotation of the current schema, which
+    /// holds for the whole subtree.
+    pub(crate) fn with_schema_comparison(mut self) -> Self {
+        if let Some(comparison) = self.resolver().comparison(self.schema) {
+            self.comparison = Some(comparison);
+        }
+        self
+    }
+
     pub(crate) fn index_key(&self) -> Option<&'a str> {
         self.resolver().index_key(self.schema)
     }
@@ -324,6 +639,16 @@ impl<'a> Default for DiffOptions<'a> {
             schema: None,
             root_schema: None,
             granularity: DiffGranularity::Compact,
+            test_mode: TestMode::Disabled,
+            move_detection: None,
+            cost_model: &ByteSize,
+            path_filter: PathFilter::default(),
+            comparator: None,
+            path_comparators: &[],
+            atomic_paths: &[],
+            max_depth: None,
+            atomic_arrays: false,
+            comparison: None,
         }
     }
 }
@@ -1,4 +1,7 @@
-use serde::Deserialize;
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 use crate::path::Spath;
 
@@ -30,6 +33,32 @@ pub enum PatchOp {
     },
 }
 
+/// The kind of a [`PatchOp`], without its paths and value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OpKind {
+    Add,
+    Remove,
+    Replace,
+    Move,
+    Copy,
+    Test,
+}
+
+impl OpKind {
+    /// Returns the `op` member value used in JSON Patch documents.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OpKind::Add => "add",
+            OpKind::Remove => "remove",
+            OpKind::Replace => "replace",
+            OpKind::Move => "move",
+            OpKind::Copy => "copy",
+            OpKind::Test => "test",
+        }
+    }
+}
+
 impl PatchOp {
     pub fn replace(path: Spath, value: serde_json::Value) -> Self {
         PatchOp::Replace { path, value }
@@ -54,9 +83,164 @@ impl PatchOp {
     pub fn test(path: Spath, value: serde_json::Value) -> Self {
         PatchOp::Test { path, value }
     }
+
+    /// Returns the kind of the operation.
+    pub fn kind(&self) -> OpKind {
+        match self {
+            PatchOp::Add { .. } => OpKind::Add,
+            PatchOp::Remove { .. } => OpKind::Remove,
+            PatchOp::Replace { .. } => OpKind::Replace,
+            PatchOp::Move { .. } => OpKind::Move,
+            PatchOp::Copy { .. } => OpKind::Copy,
+            PatchOp::Test { .. } => OpKind::Test,
+        }
+    }
+
+    /// Returns the target path of the operation.
+    pub fn path(&self) -> &Spath {
+        match self {
+            PatchOp::Add { path, .. }
+            | PatchOp::Remove { path }
+            | PatchOp::Replace { path, .. }
+            | PatchOp::Move { path, .. }
+            | PatchOp::Copy { path, .. }
+            | PatchOp::Test { path, .. } => path,
+        }
+    }
+
+    /// Returns the source path of `move` and `copy` operations.
+    pub fn from(&self) -> Option<&Spath> {
+        match self {
+            PatchOp::Move { from, .. } | PatchOp::Copy { from, .. } => Some(from),
+            _ => None,
+        }
+    }
 }
 
 impl serde::Serialize for PatchOp {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.borrowed().serialize(serializer)
+    }
+}
+
+/// A [`PatchOp`] whose value may be borrowed from the diffed documents.
+///
+/// The diff engine emits `PatchOpRef`s into a [`PatchSink`](super::PatchSink),
+/// so values are only cloned when a sink keeps them, for example when it
+/// collects a [`Patch`](super::Patch). Sinks that write operations out as they
+/// arrive never clone document values at all.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum PatchOpRef<'a> {
+    Add { path: Spath, value: Cow<'a, Value> },
+    Remove { path: Spath },
+    Replace { path: Spath, value: Cow<'a, Value> },
+    Move { from: Spath, path: Spath },
+    Copy { from: Spath, path: Spath },
+    Test { path: Spath, value: Cow<'a, Value> },
+}
+
+impl<'a> PatchOpRef<'a> {
+    /// Returns the kind of the operation.
+    pub fn kind(&self) -> OpKind {
+        match self {
+            PatchOpRef::Add { .. } => OpKind::Add,
+            PatchOpRef::Remove { .. } => OpKind::Remove,
+            PatchOpRef::Replace { .. } => OpKind::Replace,
+            PatchOpRef::Move { .. } => OpKind::Move,
+            PatchOpRef::Copy { .. } => OpKind::Copy,
+            PatchOpRef::Test { .. } => OpKind::Test,
+        }
+    }
+
+    /// Returns the target path of the operation.
+    pub fn path(&self) -> &Spath {
+        match self {
+            PatchOpRef::Add { path, .. }
+            | PatchOpRef::Remove { path }
+            | PatchOpRef::Replace { path, .. }
+            | PatchOpRef::Move { path, .. }
+            | PatchOpRef::Copy { path, .. }
+            | PatchOpRef::Test { path, .. } => path,
+        }
+    }
+
+    /// Returns the source path of `move` and `copy` operations.
+    pub fn from(&self) -> Option<&Spath> {
+        match self {
+            PatchOpRef::Move { from, .. } | PatchOpRef::Copy { from, .. } => Some(from),
+            _ => None,
+        }
+    }
+
+    /// Converts the operation into an owned [`PatchOp`], cloning a borrowed
+    /// value.
+    pub fn into_owned(self) -> PatchOp {
+        match self {
+            PatchOpRef::Add { path, value } => PatchOp::add(path, value.into_owned()),
+            PatchOpRef::Remove { path } => PatchOp::remove(path),
+            PatchOpRef::Replace { path, value } => PatchOp::replace(path, value.into_owned()),
+            PatchOpRef::Move { from, path } => PatchOp::move_op(from, path),
+            PatchOpRef::Copy { from, path } => PatchOp::copy(from, path),
+            PatchOpRef::Test { path, value } => PatchOp::test(path, value.into_owned()),
+        }
+    }
+}
+
+impl PatchOp {
+    /// Borrows the operation as a [`PatchOpRef`].
+    pub fn borrowed(&self) -> PatchOpRef<'_> {
+        match self {
+            PatchOp::Add { path, value } => PatchOpRef::Add {
+                path: path.clone(),
+                value: Cow::Borrowed(value),
+            },
+            PatchOp::Remove { path } => PatchOpRef::Remove { path: path.clone() },
+            PatchOp::Replace { path, value } => PatchOpRef::Replace {
+                path: path.clone(),
+                value: Cow::Borrowed(value),
+            },
+            PatchOp::Move { from, path } => PatchOpRef::Move {
+                from: from.clone(),
+                path: path.clone(),
+            },
+            PatchOp::Copy { from, path } => PatchOpRef::Copy {
+                from: from.clone(),
+                path: path.clone(),
+            },
+            PatchOp::Test { path, value } => PatchOpRef::Test {
+                path: path.clone(),
+                value: Cow::Borrowed(value),
+            },
+        }
+    }
+}
+
+impl From<PatchOp> for PatchOpRef<'_> {
+    fn from(op: PatchOp) -> Self {
+        match op {
+            PatchOp::Add { path, value } => PatchOpRef::Add {
+                path,
+                value: Cow::Owned(value),
+            },
+            PatchOp::Remove { path } => PatchOpRef::Remove { path },
+            PatchOp::Replace { path, value } => PatchOpRef::Replace {
+                path,
+                value: Cow::Owned(value),
+            },
+            PatchOp::Move { from, path } => PatchOpRef::Move { from, path },
+            PatchOp::Copy { from, path } => PatchOpRef::Copy { from, path },
+            PatchOp::Test { path, value } => PatchOpRef::Test {
+                path,
+                value: Cow::Owned(value),
+            },
+        }
+    }
+}
+
+impl serde::Serialize for PatchOpRef<'_> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
@@ -66,31 +250,31 @@ impl serde::Serialize for PatchOp {
         let mut map = serializer.serialize_map(None)?;
 
         match self {
-            PatchOp::Add { path, value } => {
+            PatchOpRef::Add { path, value } => {
                 map.serialize_entry("op", "add")?;
                 map.serialize_entry("path", &path)?;
                 map.serialize_entry("value", value)?;
             }
-            PatchOp::Remove { path } => {
+            PatchOpRef::Remove { path } => {
                 map.serialize_entry("op", "remove")?;
                 map.serialize_entry("path", &path)?;
             }
-            PatchOp::Replace { path, value } => {
+            PatchOpRef::Replace { path, value } => {
                 map.serialize_entry("op", "replace")?;
                 map.serialize_entry("path", &path)?;
                 map.serialize_entry("value", value)?;
             }
-            PatchOp::Test { path, value } => {
+            PatchOpRef::Test { path, value } => {
                 map.serialize_entry("op", "test")?;
                 map.serialize_entry("path", &path)?;
                 map.serialize_entry("value", value)?;
             }
-            PatchOp::Move { from, path } => {
+            PatchOpRef::Move { from, path } => {
                 map.serialize_entry("op", "move")?;
                 map.serialize_entry("from", &from)?;
                 map.serialize_entry("path", &path)?;
             }
-            PatchOp::Copy { from, path } => {
+            PatchOpRef::Copy { from, path } => {
                 map.serialize_entry("op", "copy")?;
                 map.serialize_entry("from", &from)?;
                 map.serialize_entry("path", &path)?;
@@ -2,8 +2,29 @@ use std::collections::HashSet;
 
 use serde_json::Value;
 
+use crate::diff::{
+    DiffGranularity,
+    compare::{COMPARE_PROP_NAME, Comparison, TOLERANCE_PROP_NAME},
+};
+
 const MAX_REF_DEPTH: usize = 64;
 
+/// Name of the schema property excluding a subtree from diffs.
+const IGNORE_PROP_NAME: &str = "x-spatch-ignore";
+
+/// Name of the schema property making a subtree opaque to diffs.
+const ATOMIC_PROP_NAME: &str = "x-spatch-atomic";
+
+/// Name of the schema property overriding the granularity of a subtree.
+const GRANULARITY_PROP_NAME: &str = "x-spatch-granularity";
+
+/// Kubernetes' merge key of lists in strategic merge patches.
+const KUBERNETES_MERGE_KEY: &str = "x-kubernetes-patch-merge-key";
+
+/// Kubernetes' keys of `x-kubernetes-list-type: map` lists. Only lists keyed by
+/// a single property have an identity spatch can express.
+const KUBERNETES_LIST_MAP_KEYS: &str = "x-kubernetes-list-map-keys";
+
 #[derive(Debug, Clone, Copy)]
 pub struct SchemaResolver<'a> {
     root: Option<&'a Value>,
@@ -30,9 +51,61 @@ impl<'a> SchemaResolver<'a> {
         self.resolve(items)
     }
 
+    /// Returns the property identifying the elements of an array schema.
+    ///
+    /// `x-spatch-indexKey` takes precedence over the Kubernetes annotations
+    /// `x-kubernetes-patch-merge-key` and `x-kubernetes-list-map-keys`, so CRD
+    /// schemas can be used as they are.
     pub fn index_key(&self, schema: Option<&'a Value>) -> Option<&'a str> {
         let schema = self.resolve(schema?)?;
-        schema.get(super::engine::HASH_KEY_PROP_NAME)?.as_str()
+        let annotation = |name| schema.get(name).and_then(Value::as_str);
+
+        annotation(super::engine::HASH_KEY_PROP_NAME)
+            .or_else(|| annotation(KUBERNETES_MERGE_KEY))
+            .or_else(
+                || match schema.get(KUBERNETES_LIST_MAP_KEYS)?.as_array()?.as_slice() {
+                    [key] => key.as_str(),
+                    _ => None,
+                },
+            )
+    }
+
+    /// Returns `true` when the schema excludes its values from diffs with
+    /// `x-spatch-ignore: true`.
+    pub fn is_ignored(&self, schema: Option<&'a Value>) -> bool {
+        self.flag(schema, IGNORE_PROP_NAME)
+    }
+
+    /// Returns `true` when the schema makes its values opaque with
+    /// `x-spatch-atomic: true`, so they are replaced as a whole.
+    pub fn is_atomic(&self, schema: Option<&'a Value>) -> bool {
+        self.flag(schema, ATOMIC_PROP_NAME)
+    }
+
+    /// Returns the granularity the schema sets for its subtree with
+    /// `x-spatch-granularity: "compact"` or `"granular"`.
+    pub fn granularity(&self, schema: Option<&'a Value>) -> Option<DiffGranularity> {
+        let schema = self.resolve(schema?)?;
+        match schema.get(GRANULARITY_PROP_NAME)?.as_str()? {
+            "compact" => Some(DiffGranularity::Compact),
+            "granular" => Some(DiffGranularity::Granular),
+            _ => None,
+        }
+    }
+
+    fn flag(&self, schema: Option<&'a Value>, name: &str) -> bool {
+        schema
+            .and_then(|schema| self.resolve(schema))
+            .and_then(|schema| schema.get(name))
+            .and_then(Value::as_bool)
+            .unwrap_or(false)
+    }
+
+    /// Returns how the schema's `x-spatch-compare` annotation compares values.
+    pub(crate) fn comparison(&self, schema: Option<&'a Value>) -> Option<Comparison> {
+        let schema = self.resolve(schema?)?;
+        let tolerance = schema.get(TOLERANCE_PROP_NAME).and_then(Value::as_f64);
+        Comparison::from_annotation(schema.get(COMPARE_PROP_NAME)?.as_str()?, tolerance)
     }
 
     fn resolve_inner(
@@ -62,3 +135,49 @@ impl<'a> SchemaResolver<'a> {
         self.root?.pointer(pointer)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use assert2::check;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn index_key_should_recognize_kubernetes_annotations() {
+        let resolver = SchemaResolver::new(None);
+
+        let merge_key = json!({"x-kubernetes-patch-merge-key": "name"});
+        let list_map_keys = json!({"x-kubernetes-list-map-keys": ["containerPort"]});
+        let both = json!({"x-spatch-indexKey": "id", "x-kubernetes-patch-merge-key": "name"});
+
+        check!(resolver.index_key(Some(&merge_key)) == Some("name"));
+        check!(resolver.index_key(Some(&list_map_keys)) == Some("containerPort"));
+        check!(resolver.index_key(Some(&both)) == Some("id"));
+    }
+
+    #[test]
+    fn index_key_should_ignore_composite_list_map_keys() {
+        let resolver = SchemaResolver::new(None);
+        let schema = json!({"x-kubernetes-list-map-keys": ["containerPort", "protocol"]});
+
+        check!(resolver.index_key(Some(&schema)) == None);
+    }
+
+    #[test]
+    fn annotations_should_be_read_through_refs() {
+        let root = json!({
+            "$defs": {"stamp": {"x-spatch-ignore": true, "x-spatch-compare": "unordered"}},
+            "properties": {"updatedAt": {"$ref": "#/$defs/stamp"}}
+        });
+        let resolver = SchemaResolver::new(Some(&root));
+        let property = resolver.property_schema(Some(&root), "updatedAt");
+
+        check!(resolver.is_ignored(property));
+        check!(!resolver.is_atomic(property));
+        check!(resolver.is_atomic(Some(&json!({"x-spatch-atomic": true}))));
+        check!(resolver.comparison(property) == Some(Comparison::Unordered));
+        check!(!resolver.is_ignored(Some(&root)));
+        check!(resolver.comparison(Some(&json!({"x-spatch-compare": "fuzzy"}))) == None);
+    }
+}
@@ -0,0 +1,52 @@
+use crate::diff::{Patch, PatchOp, PatchOpRef};
+
+/// Receives the operations of a diff as soon as they are final.
+///
+/// [`diff_into`](super::diff_into) pushes operations in patch order. Values
+/// are borrowed from the diffed documents, so a sink that serializes or
+/// inspects operations can process documents of any size without cloning
+/// them. [`Patch`] and `Vec<PatchOp>` collect owned operations, while
+/// `Vec<PatchOpRef>` keeps the borrowed ones.
+///
+/// ```rust
+/// use serde_json::json;
+/// use spatch::diff::{diff_into, DiffOptions, PatchOpRef, PatchSink};
+///
+/// struct Paths(Vec<String>);
+///
+/// impl<'a> PatchSink<'a> for Paths {
+///     fn push(&mut self, op: PatchOpRef<'a>) {
+///         self.0.push(op.path().to_string());
+///     }
+/// }
+///
+/// let before = json!({"a": 1, "b": 2});
+/// let after = json!({"a": 2, "b": 3});
+///
+/// let mut paths = Paths(Vec::new());
+/// diff_into(&before, &after, DiffOptions::new().granular(), &mut paths).unwrap();
+///
+/// assert_eq!(paths.0, ["/a", "/b"]);
+/// ```
+pub trait PatchSink<'a> {
+    /// Receives the next operation.
+    fn push(&mut self, op: PatchOpRef<'a>);
+}
+
+impl<'a> PatchSink<'a> for Patch {
+    fn push(&mut self, op: PatchOpRef<'a>) {
+        self.0.push(op.into_owned());
+    }
+}
+
+impl<'a> PatchSink<'a> for Vec<PatchOp> {
+    fn push(&mut self, op: PatchOpRef<'a>) {
+        Vec::push(self, op.into_owned());
+    }
+}
+
+impl<'a> PatchSink<'a> for Vec<PatchOpRef<'a>> {
+    fn push(&mut self, op: PatchOpRef<'a>) {
+        Vec::push(self, op);
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+
+use crate::{
+    diff::{OpKind, Patch, PatchOp, cost::json_len, index_key_value_to_filter},
+    document::Document,
+    patch::apply_in_place,
+    path::{Segment, Spath},
+    resolve::resolve_ref,
+};
+
+/// A summary of what a [`Patch`] changes, computed by [`Patch::stats`].
+///
+/// Serializes as a JSON object, so CI jobs can gate on it, e.g. on the number
+/// of services a configuration change touches.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct PatchStats {
+    /// Number of operations of each kind.
+    pub operations: BTreeMap<OpKind, usize>,
+
+    /// Number of operations under each top-level key, e.g. `services`.
+    /// Operations on the root itself are counted under an empty key.
+    pub top_level_keys: BTreeMap<String, usize>,
+
+    /// Number of distinct elements touched in each keyed array.
+    ///
+    /// Arrays are named by their path without filters, so
+    /// `/services/[name=api]/ports/[port=80]` touches one element of
+    /// `/services` and one of `/services/ports`. Elements appended with `-`
+    /// count as touched too.
+    pub identities: BTreeMap<String, usize>,
+
+    /// Serialized size, in bytes, of the values added, replaced in, or copied.
+    pub bytes_added: usize,
+
+    /// Serialized size, in bytes, of the values removed or replaced.
+    pub bytes_removed: usize,
+}
+
+impl Patch {
+    /// Summarizes the patch: operations by kind and by top-level key, touched
+    /// array identities, and bytes added and removed.
+    ///
+    /// Old values are resolved from `left`, the document the patch applies
+    /// to, with the preceding operations applied, so index-shifting removes
+    /// count the element each one actually removes. Values that can't be
+    /// resolved count as zero bytes.
+    ///
+    /// ```rust
+    /// use serde_json::json;
+    /// use spatch::diff::{diff, DiffOptions, OpKind};
+    ///
+    /// let schema = json!({"properties": {"services": {"x-spatch-indexKey": "name"}}});
+    /// let before = json!({"services": [
+    ///     {"name": "api", "replicas": 2},
+    ///     {"name": "web", "replicas": 1}
+    /// ]});
+    /// let after = json!({"services": [
+    ///     {"name": "api", "replicas": 3},
+    ///     {"name": "web", "replicas": 2}
+    /// ]});
+    ///
+    /// let patch = diff(&before, &after, DiffOptions::new().with_schema(&schema)).unwrap();
+    /// let stats = patch.stats(&before);
+    ///
+    /// assert_eq!(stats.operations[&OpKind::Replace], 2);
+    /// assert_eq!(stats.top_level_keys["services"], 2);
+    /// assert_eq!(stats.identities["/services"], 2);
+    /// ```
+    pub fn stats<D: Document>(&self, left: &D) -> PatchStats {
+        let mut stats = PatchStats::default();
+
+        // Identity keys of keyed arrays, to name the elements appended to them
+        let mut keys: BTreeMap<String, String> = BTreeMap::new();
+        for op in self.iter() {
+            for (array, segment) in filters(op.path()) {
+                if let Some((key, _)) = segment.first() {
+                    keys.entry(array).or_insert_with(|| key.clone());
+                }
+            }
+        }
+
+        let mut identities: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        let mut doc = left.clone();
+        for (index, op) in self.iter().enumerate() {
+            *stats.operations.entry(op.kind()).or_default() += 1;
+            if let PatchOp::Test { .. } = op {
+                continue;
+            }
+
+            let top_level_key = match op.path().into_iter().next() {
+                Some(segment) => segment_name(segment),
+                None => String::new(),
+            };
+            *stats.top_level_keys.entry(top_level_key).or_default() += 1;
+
+            for path in [Some(op.path()), op.from()].into_iter().flatten() {
+                let mut element = String::new();
+                for (array, conditions) in filters(path) {
+                    element.push_str(&segment_name(&Segment::Filter(conditions.clone())));
+                    identities.entry(array).or_default().insert(element.clone());
+                }
+            }
+            if let PatchOp::Add { path, value } = op
+                && let Some((Segment::Field(last), parents)) = split_last(path)
+                && last == "-"
+                && value.is_object()
+            {
+                let array = collapsed(&parents);
+                // Named like the elements of filter paths, after the elements
+                // of the parent arrays
+                let parent_elements: String = parents
+                    .iter()
+                    .filter(|segment| matches!(segment, Segment::Filter(_)))
+                    .map(|segment| segment_name(segment))
+                    .collect();
+                let identity = keys
+                    .get(&array)
+                    .and_then(|key| Some((key, index_key_value_to_filter(value.get(key)?)?)))
+                    .map_or_else(
+                        || format!("-{index}"),
+                        |(key, value)| format!("{parent_elements}[{key}={value}]"),
+                    );
+                identities.entry(array).or_default().insert(identity);
+            }
+
+            let old_len = || resolve_ref(&doc, op.path()).map_or(0, json_len);
+            match op {
+                PatchOp::Add { value, .. } => stats.bytes_added += json_len(value),
+                PatchOp::Replace { value, .. } => {
+                    stats.bytes_added += json_len(value);
+                    stats.bytes_removed += old_len();
+                }
+                PatchOp::Remove { .. } => stats.bytes_removed += old_len(),
+                PatchOp::Copy { from, .. } => {
+                    stats.bytes_added += resolve_ref(&doc, from).map_or(0, json_len);
+                }
+                PatchOp::Move { .. } | PatchOp::Test { .. } => {}
+            }
+            // Later operations see the document as this one left it
+            let _ = apply_in_place(&mut doc, std::slice::from_ref(op));
+        }
+
+        stats.identities = identities
+            .into_iter()
+            .map(|(array, elements)| (array, elements.len()))
+            .collect();
+        stats
+    }
+}
+
+/// The filters of `path`, with the path of the array each one selects from,
+/// without filters.
+fn filters(path: &Spath) -> Vec<(String, &Vec<(String, String)>)> {
+    let segments: Vec<&Segment> = path.into_iter().collect();
+    segments
+        .iter()
+        .enumerate()
+        .filter_map(|(index, segment)| match segment {
+            Segment::Filter(conditions) => Some((collapsed(&segments[..index]), conditions)),
+            Segment::Field(_) => None,
+        })
+        .collect()
+}
+
+/// The path of `segments` without their filters, e.g. `/services/ports`.
+fn collapsed(segments: &[&Segment]) -> String {
+    segments
+        .iter()
+        .filter(|segment| matches!(segment, Segment::Field(_)))
+        .fold(Spath::default(), |path, segment| {
+            path.push((*segment).clone())
+        })
+        .to_string()
+}
+
+fn split_last(path: &Spath) -> Option<(&Segment, Vec<&Segment>)> {
+    let mut segments: Vec<&Segment> = path.into_iter().collect();
+    let last = segments.pop()?;
+    Some((last, segments))
+}
+
+/// A segment as written in paths, without its leading slash.
+fn segment_name(segment: &Segment) -> String {
+    Spath::default().push(segment.clone()).to_string()[1..].to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use assert2::check;
+    use serde_json::json;
+
+    use super::*;
+
+    fn path(raw: &str) -> Spath {
+        raw.try_into().unwrap()
+    }
+
+    #[test]
+    fn stats_should_count_operations_by_kind_and_top_level_key() {
+        let left = json!({"a": {"b": 1}, "c": "text", "d": [1, 2]});
+        let patch = Patch::new(vec![
+            PatchOp::test(path("/a/b"), json!(1)),
+            PatchOp::replace(path("/a/b"), json!(10)),
+            PatchOp::add(path("/a/e"), json!("new")),
+            PatchOp::remove(path("/c")),
+            PatchOp::move_op(path("/d/0"), path("/f")),
+        ]);
+
+        let stats = patch.stats(&left);
+
+        check!(
+            stats.operations
+                == BTreeMap::from([
+                    (OpKind::Add, 1),
+                    (OpKind::Remove, 1),
+                    (OpKind::Replace, 1),
+                    (OpKind::Move, 1),
+                    (OpKind::Test, 1),
+                ])
+        );
+        check!(
+            stats.top_level_keys
+                == BTreeMap::from([
+                    ("a".to_owned(), 2),
+                    ("c".to_owned(), 1),
+                    ("f".to_owned(), 1)
+                ])
+        );
+        check!(stats.identities.is_empty());
+        // 10 and "new", then 1 and "text"
+        check!(stats.bytes_added == 2 + 5);
+        check!(stats.bytes_removed == 1 + 6);
+    }
+
+    #[test]
+    fn stats_should_count_touched_identities_per_array() {
+        let left = json!({"services": [
+            {"name": "api", "ports": [{"port": 80}, {"port": 443}]},
+            {"name": "web", "ports": [{"port": 80}]}
+        ]});
+        let patch = Patch::new(vec![
+            PatchOp::replace(
+                path("/services/[name=api]/ports/[port=80]/port"),
+                json!(8080),
+            ),
+            PatchOp::remove(path("/services/[name=api]/ports/[port=443]")),
+            PatchOp::remove(path("/services/[name=web]/ports/[port=80]")),
+            PatchOp::add(path("/services/-"), json!({"name": "db", "ports": []})),
+            PatchOp::add(path("/services/-"), json!({"name": "db", "ports": []})),
+            PatchOp::add(path("/services/[name=api]/ports/-"), json!({"port": 81})),
+            PatchOp::add(path("/services/[name=web]/ports/-"), json!({"port": 81})),
+        ]);
+
+        let stats = patch.stats(&left);
+
+        check!(
+            stats.identities
+                == BTreeMap::from([
+                    ("/services".to_owned(), 3),
+                    ("/services/ports".to_owned(), 5)
+                ])
+        );
+    }
+
+    #[test]
+    fn stats_should_resolve_old_values_after_the_preceding_operations() {
+        let left = json!({"items": ["alpha", "b", "c"]});
+        let patch = Patch::new(vec![
+            PatchOp::remove(path("/items/0")),
+            PatchOp::remove(path("/items/0")),
+            PatchOp::replace(path("/items/0"), json!("z")),
+        ]);
+
+        let stats = patch.stats(&left);
+
+        // "alpha", "b", then "c"
+        check!(stats.bytes_removed == 7 + 3 + 3);
+        check!(stats.bytes_added == 3);
+    }
+
+    #[test]
+    fn stats_should_count_operations_on_the_root_under_an_empty_key() {
+        let patch = Patch::new(vec![PatchOp::replace(path(""), json!([]))]);
+
+        let stats = patch.stats(&json!({}));
+
+        check!(stats.top_level_keys == BTreeMap::from([(String::new(), 1)]));
+        check!(stats.bytes_removed == 2);
+    }
+}
@@ -0,0 +1,56 @@
+use std::{borrow::Cow, iter};
+
+use serde_json::{Map, Value};
+
+use crate::document::{Document, DocumentError, Members};
+
+impl Document for Value {
+    fn from_json(value: Value) -> Result<Self, DocumentError> {
+        Ok(value)
+    }
+
+    fn to_json(&self) -> Cow<'_, Value> {
+        Cow::Borrowed(self)
+    }
+
+    fn elements(&self) -> Option<&[Self]> {
+        self.as_array().map(Vec::as_slice)
+    }
+
+    fn elements_mut(&mut self) -> Option<&mut Vec<Self>> {
+        self.as_array_mut()
+    }
+
+    fn object_len(&self) -> Option<usize> {
+        self.as_object().map(Map::len)
+    }
+
+    fn member(&self, key: &str) -> Option<&Self> {
+        self.as_object()?.get(key)
+    }
+
+    fn member_mut(&mut self, key: &str) -> Option<&mut Self> {
+        self.as_object_mut()?.get_mut(key)
+    }
+
+    fn members(&self) -> Members<'_, Self> {
+        match self {
+            Value::Object(map) => Box::new(
+                map.iter()
+                    .map(|(key, value)| (Cow::Borrowed(key.as_str()), value)),
+            ),
+            _ => Box::new(iter::empty()),
+        }
+    }
+
+    fn insert_member(&mut self, key: String, value: Self) -> Result<Option<Self>, Self> {
+        match self.as_object_mut() {
+            Some(map) => Ok(map.insert(key, value)),
+            None => Err(value),
+        }
+    }
+
+    fn remove_member(&mut self, key: &str) -> Option<Self> {
+        self.as_object_mut()?.remove(key)
+    }
+}
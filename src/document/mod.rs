@@ -0,0 +1,151 @@
+//! Document trees that spatch can resolve, patch, and diff.
+//!
+//! [`resolve`](crate::resolve), [`patch`](crate::patch), and
+//! [`diff`](crate::diff) work on any type implementing [`Document`], so
+//! native YAML or TOML trees can be patched without converting the whole
+//! document to JSON and back. Only the values carried by patch operations are
+//! converted, through [`Document::from_json`] and [`Document::to_json`].
+//!
+//! `serde_json::Value` is always supported. The other backends are behind
+//! cargo features:
+//!
+//! | Feature     | Type                        |
+//! |-------------|-----------------------------|
+//! | `yaml`      | `serde_yaml::Value`         |
+//! | `toml`      | `toml::Value`               |
+//! | `simd-json` | `simd_json::OwnedValue`     |
+//!
+//! ```rust
+//! use serde_json::json;
+//! use spatch::{diff::{diff, DiffOptions}, patch::apply};
+//!
+//! let before = json!({"name": "spatch"});
+//! let after = json!({"name": "spatch", "version": 1});
+//!
+//! let patch = diff(&before, &after, DiffOptions::new()).unwrap();
+//! assert_eq!(apply(&before, &patch).unwrap(), after);
+//! ```
+mod json;
+#[cfg(feature = "simd-json")]
+mod simd_json;
+#[cfg(feature = "toml")]
+mod toml;
+#[cfg(feature = "yaml")]
+mod yaml;
+
+use std::{borrow::Cow, fmt};
+
+use serde_json::Value;
+
+/// The members of an object, as returned by [`Document::members`].
+///
+/// Keys are borrowed when the format stores them as strings, and formatted
+/// when it doesn't, e.g. integer keys in YAML.
+pub type Members<'a, D> = Box<dyn Iterator<Item = (Cow<'a, str>, &'a D)> + 'a>;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq, Clone)]
+pub enum DocumentError {
+    /// A JSON value from a patch has no equivalent in the document format.
+    #[error("{format} documents can't represent {value}")]
+    Unrepresentable { format: &'static str, value: String },
+}
+
+impl DocumentError {
+    pub fn unrepresentable(format: &'static str, value: &Value) -> Self {
+        DocumentError::Unrepresentable {
+            format,
+            value: value.to_string(),
+        }
+    }
+}
+
+/// A tree of JSON-like values: scalars, arrays, and objects with string keys.
+///
+/// Containers are accessed natively. Scalars are only inspected through
+/// [`to_json`](Document::to_json), which is cheap for them.
+///
+/// Object members must be iterated in a deterministic order, so diffs stay
+/// reproducible. Implementations backed by hash maps sort them by key.
+pub trait Document: Clone + PartialEq + fmt::Debug {
+    /// Converts a JSON value, such as the value of a patch operation, into a
+    /// document value.
+    fn from_json(value: Value) -> Result<Self, DocumentError>;
+
+    /// Converts the value to JSON, borrowing it when it already is JSON.
+    ///
+    /// Values without a JSON equivalent are converted the way the format's
+    /// serde serializer would, e.g. dates become strings.
+    fn to_json(&self) -> Cow<'_, Value>;
+
+    /// Returns the elements, when the value is an array.
+    fn elements(&self) -> Option<&[Self]>;
+
+    /// Returns the elements for modification, when the value is an array.
+    fn elements_mut(&mut self) -> Option<&mut Vec<Self>>;
+
+    /// Returns the number of members, when the value is an object.
+    fn object_len(&self) -> Option<usize>;
+
+    /// Returns the member named `key`, when the value is an object that has
+    /// one.
+    fn member(&self, key: &str) -> Option<&Self>;
+
+    /// Returns the member named `key` for modification.
+    fn member_mut(&mut self, key: &str) -> Option<&mut Self>;
+
+    /// Iterates over the members of an object. Other values have no members.
+    fn members(&self) -> Members<'_, Self>;
+
+    /// Inserts a member into an object and returns the value it replaced.
+    /// Returns `value` back as an error when this is not an object.
+    fn insert_member(&mut self, key: String, value: Self) -> Result<Option<Self>, Self>;
+
+    /// Removes the member named `key` from an object and returns it.
+    fn remove_member(&mut self, key: &str) -> Option<Self>;
+
+    /// Removes the member named `key` from an object and returns it, along
+    /// with its position among the members, so that
+    /// [`shift_insert_member`](Document::shift_insert_member) can put it back.
+    fn shift_remove_member(&mut self, key: &str) -> Option<(usize, Self)> {
+        let position = self.members().position(|(name, _)| name == key)?;
+        Some((position, self.remove_member(key)?))
+    }
+
+    /// Inserts a member into an object at `position` among its members, and
+    /// returns the value it replaced. A replaced member keeps its position.
+    /// Returns `value` back as an error when this is not an object.
+    ///
+    /// Members after `position` are shifted, so objects that keep their
+    /// members in insertion order are restored exactly. Objects that order
+    /// their members by key are unaffected.
+    fn shift_insert_member(
+        &mut self,
+        position: usize,
+        key: String,
+        value: Self,
+    ) -> Result<Option<Self>, Self> {
+        if let Some(old) = self.insert_member(key.clone(), value)? {
+            return Ok(Some(old));
+        }
+        let following: Vec<String> = self
+            .members()
+            .skip(position)
+            .map(|(name, _)| name.into_owned())
+            .filter(|name| *name != key)
+            .collect();
+        for name in following {
+            if let Some(member) = self.remove_member(&name) {
+                let _ = self.insert_member(name, member);
+            }
+        }
+        Ok(None)
+    }
+
+    fn is_array(&self) -> bool {
+        self.elements().is_some()
+    }
+
+    fn is_object(&self) -> bool {
+        self.object_len().is_some()
+    }
+}
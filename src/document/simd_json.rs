@@ -0,0 +1,173 @@
+use std::{borrow::Cow, iter};
+
+use serde_json::Value as Json;
+use simd_json::{
+    OwnedValue as Value,
+    owned::Object,
+    prelude::{ValueAsScalar, ValueObjectAccess},
+};
+
+use crate::document::{Document, DocumentError, Members};
+
+fn simd_to_json(value: &Value) -> Json {
+    match value {
+        Value::Static(_) => {
+            if let Some(number) = value.as_i64() {
+                Json::from(number)
+            } else if let Some(number) = value.as_u64() {
+                Json::from(number)
+            } else if let Some(number) = value.as_f64() {
+                Json::from(number)
+            } else if let Some(flag) = value.as_bool() {
+                Json::Bool(flag)
+            } else {
+                Json::Null
+            }
+        }
+        Value::String(string) => Json::String(string.clone()),
+        Value::Array(items) => Json::Array(items.iter().map(simd_to_json).collect()),
+        Value::Object(object) => Json::Object(
+            object
+                .iter()
+                .map(|(key, value)| (key.clone(), simd_to_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+fn json_to_simd(value: Json) -> Value {
+    match value {
+        Json::Null => Value::from(()),
+        Json::Bool(flag) => Value::from(flag),
+        Json::Number(number) => match (number.as_i64(), number.as_u64()) {
+            (Some(number), _) => Value::from(number),
+            (None, Some(number)) => Value::from(number),
+            _ => Value::from(number.as_f64().unwrap_or_default()),
+        },
+        Json::String(string) => Value::String(string),
+        Json::Array(items) => Value::Array(Box::new(items.into_iter().map(json_to_simd).collect())),
+        Json::Object(map) => Value::Object(Box::new(
+            map.into_iter()
+                .map(|(key, value)| (key, json_to_simd(value)))
+                .collect::<Object>(),
+        )),
+    }
+}
+
+/// simd-json's owned values. Objects are hash maps, so their members are
+/// iterated in key order.
+impl Document for Value {
+    fn from_json(value: Json) -> Result<Self, DocumentError> {
+        Ok(json_to_simd(value))
+    }
+
+    fn to_json(&self) -> Cow<'_, Json> {
+        Cow::Owned(simd_to_json(self))
+    }
+
+    fn elements(&self) -> Option<&[Self]> {
+        match self {
+            Value::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    fn elements_mut(&mut self) -> Option<&mut Vec<Self>> {
+        match self {
+            Value::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    fn object_len(&self) -> Option<usize> {
+        match self {
+            Value::Object(object) => Some(object.len()),
+            _ => None,
+        }
+    }
+
+    fn member(&self, key: &str) -> Option<&Self> {
+        self.get(key)
+    }
+
+    fn member_mut(&mut self, key: &str) -> Option<&mut Self> {
+        match self {
+            Value::Object(object) => object.get_mut(key),
+            _ => None,
+        }
+    }
+
+    fn members(&self) -> Members<'_, Self> {
+        match self {
+            Value::Object(object) => {
+                let mut members: Vec<_> = object.iter().collect();
+                members.sort_unstable_by_key(|(key, _)| *key);
+                Box::new(
+                    members
+                        .into_iter()
+                        .map(|(key, value)| (Cow::Borrowed(key.as_str()), value)),
+                )
+            }
+            _ => Box::new(iter::empty()),
+        }
+    }
+
+    fn insert_member(&mut self, key: String, value: Self) -> Result<Option<Self>, Self> {
+        match self {
+            Value::Object(object) => Ok(object.insert(key, value)),
+            _ => Err(value),
+        }
+    }
+
+    fn remove_member(&mut self, key: &str) -> Option<Self> {
+        match self {
+            Value::Object(object) => object.remove(key),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert2::check;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        diff::{DiffOptions, PatchOp, diff},
+        patch::apply,
+        path::Spath,
+    };
+
+    fn path(raw: &str) -> Spath {
+        raw.try_into().unwrap()
+    }
+
+    fn simd(value: Json) -> Value {
+        Value::from_json(value).unwrap()
+    }
+
+    #[test]
+    fn diff_should_emit_members_in_key_order() {
+        let left = simd(json!({}));
+        let right = simd(json!({"c": 3, "a": 1, "b": 2}));
+
+        let patch = diff(&left, &right, DiffOptions::new().granular()).unwrap();
+
+        let paths: Vec<_> = patch.iter().map(|op| op.path().to_string()).collect();
+        check!(paths == ["/a", "/b", "/c"]);
+    }
+
+    #[test]
+    fn apply_should_match_serde_json() {
+        let doc = json!({"items": [{"id": 1, "qty": 2}], "total": 2.5});
+        let patch = vec![
+            PatchOp::replace(path("/items/[id=1]/qty"), json!(3)),
+            PatchOp::add(path("/note"), json!(null)),
+        ];
+
+        let result = apply(&simd(doc.clone()), &patch).unwrap();
+
+        check!(result.to_json().into_owned() == apply(&doc, &patch).unwrap());
+    }
+}
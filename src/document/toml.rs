@@ -0,0 +1,178 @@
+use std::{borrow::Cow, iter};
+
+use serde_json::Value as Json;
+use toml::{Table, Value};
+
+use crate::document::{Document, DocumentError, Members};
+
+const FORMAT: &str = "TOML";
+
+fn toml_to_json(value: &Value) -> Json {
+    match value {
+        Value::String(string) => Json::String(string.clone()),
+        Value::Integer(number) => Json::from(*number),
+        // NaN and infinities have no JSON equivalent, like in serde_json.
+        Value::Float(number) => Json::from(*number),
+        Value::Boolean(flag) => Json::Bool(*flag),
+        Value::Datetime(datetime) => Json::String(datetime.to_string()),
+        Value::Array(items) => Json::Array(items.iter().map(toml_to_json).collect()),
+        Value::Table(table) => Json::Object(
+            table
+                .iter()
+                .map(|(key, value)| (key.clone(), toml_to_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+fn json_to_toml(value: Json) -> Result<Value, DocumentError> {
+    Ok(match value {
+        Json::Null => return Err(DocumentError::unrepresentable(FORMAT, &value)),
+        Json::Bool(flag) => Value::Boolean(flag),
+        Json::Number(ref number) => match (number.as_i64(), number.as_f64()) {
+            (Some(number), _) => Value::Integer(number),
+            // Integers past i64::MAX are only accepted when they stay exact
+            // as floats, which they generally don't.
+            (None, Some(float)) if number.is_f64() => Value::Float(float),
+            _ => return Err(DocumentError::unrepresentable(FORMAT, &value)),
+        },
+        Json::String(string) => Value::String(string),
+        Json::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .map(json_to_toml)
+                .collect::<Result<_, _>>()?,
+        ),
+        Json::Object(map) => Value::Table(
+            map.into_iter()
+                .map(|(key, value)| Ok((key, json_to_toml(value)?)))
+                .collect::<Result<Table, _>>()?,
+        ),
+    })
+}
+
+/// TOML values. Dates and times are compared and emitted as strings. TOML has
+/// no null, so patches adding `null` fail with
+/// [`DocumentError::Unrepresentable`].
+impl Document for Value {
+    fn from_json(value: Json) -> Result<Self, DocumentError> {
+        json_to_toml(value)
+    }
+
+    fn to_json(&self) -> Cow<'_, Json> {
+        Cow::Owned(toml_to_json(self))
+    }
+
+    fn elements(&self) -> Option<&[Self]> {
+        self.as_array().map(Vec::as_slice)
+    }
+
+    fn elements_mut(&mut self) -> Option<&mut Vec<Self>> {
+        self.as_array_mut()
+    }
+
+    fn object_len(&self) -> Option<usize> {
+        self.as_table().map(Table::len)
+    }
+
+    fn member(&self, key: &str) -> Option<&Self> {
+        self.as_table()?.get(key)
+    }
+
+    fn member_mut(&mut self, key: &str) -> Option<&mut Self> {
+        self.as_table_mut()?.get_mut(key)
+    }
+
+    fn members(&self) -> Members<'_, Self> {
+        match self {
+            Value::Table(table) => Box::new(
+                table
+                    .iter()
+                    .map(|(key, value)| (Cow::Borrowed(key.as_str()), value)),
+            ),
+            _ => Box::new(iter::empty()),
+        }
+    }
+
+    fn insert_member(&mut self, key: String, value: Self) -> Result<Option<Self>, Self> {
+        match self.as_table_mut() {
+            Some(table) => Ok(table.insert(key, value)),
+            None => Err(value),
+        }
+    }
+
+    fn remove_member(&mut self, key: &str) -> Option<Self> {
+        self.as_table_mut()?.remove(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert2::{assert, check};
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        diff::{DiffOptions, PatchOp, diff},
+        patch::{PatchError, apply, apply_in_place},
+        path::Spath,
+    };
+
+    fn path(raw: &str) -> Spath {
+        raw.try_into().unwrap()
+    }
+
+    fn toml(raw: &str) -> Value {
+        Value::Table(raw.parse().unwrap())
+    }
+
+    #[test]
+    fn apply_should_patch_toml_tables() {
+        let doc = toml("[package]\nname = \"spatch\"\nversion = \"0.1.0\"\n");
+        let patch = vec![PatchOp::replace(path("/package/version"), json!("0.2.0"))];
+
+        let result = apply(&doc, &patch).unwrap();
+
+        check!(result == toml("[package]\nname = \"spatch\"\nversion = \"0.2.0\"\n"));
+    }
+
+    #[test]
+    fn apply_in_place_should_restore_the_order_of_members_on_failure() {
+        let mut doc = toml("a = 1\nb = 2\nc = 3\n");
+        let patch = vec![
+            PatchOp::remove(path("/a")),
+            PatchOp::move_op(path("/b"), path("/d")),
+            PatchOp::test(path("/c"), json!(99)),
+        ];
+
+        assert!(let Err(_) = apply_in_place(&mut doc, &patch));
+
+        let keys: Vec<&str> = doc.as_table().unwrap().keys().map(String::as_str).collect();
+        check!(keys == ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn apply_should_reject_null_values() {
+        let doc = toml("name = \"spatch\"\n");
+        let patch = vec![PatchOp::add(path("/license"), json!(null))];
+
+        assert!(let Err(PatchError::MultipleErrors(errors)) = apply(&doc, &patch));
+        check!(
+            errors
+                == [PatchError::DocumentError(DocumentError::unrepresentable(
+                    "TOML",
+                    &json!(null)
+                ))]
+        );
+    }
+
+    #[test]
+    fn diff_should_emit_datetimes_as_strings() {
+        let left = toml("released = 2024-01-01\n");
+        let right = toml("released = 2024-02-01\n");
+
+        let patch = diff(&left, &right, DiffOptions::new()).unwrap();
+
+        check!(*patch == [PatchOp::replace(path("/released"), json!("2024-02-01"))]);
+    }
+}
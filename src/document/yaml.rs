@@ -0,0 +1,300 @@
+use std::{borrow::Cow, iter};
+
+use serde_json::Value as Json;
+use serde_yaml::{Mapping, Number, Value};
+
+use crate::document::{Document, DocumentError, Members};
+
+/// Formats a scalar mapping key the way it's addressed in a path. Sequences,
+/// mappings, and tagged values can't be addressed and are skipped.
+fn key_string(key: &Value) -> Option<Cow<'_, str>> {
+    match key {
+        Value::String(key) => Some(Cow::Borrowed(key)),
+        Value::Number(number) => Some(Cow::Owned(number.to_string())),
+        Value::Bool(flag) => Some(Cow::Owned(flag.to_string())),
+        Value::Null => Some(Cow::Borrowed("null")),
+        _ => None,
+    }
+}
+
+/// Finds the key of the member addressed by `key`, preferring string keys.
+fn find_key<'m>(mapping: &'m Mapping, key: &str) -> Option<&'m Value> {
+    mapping
+        .keys()
+        .find(|candidate| candidate.as_str() == Some(key))
+        .or_else(|| {
+            mapping.keys().find(|candidate| {
+                !candidate.is_string() && key_string(candidate).as_deref() == Some(key)
+            })
+        })
+}
+
+fn number_to_json(number: &Number) -> Json {
+    if let Some(number) = number.as_i64() {
+        Json::from(number)
+    } else if let Some(number) = number.as_u64() {
+        Json::from(number)
+    } else {
+        // NaN and infinities have no JSON equivalent, like in serde_json.
+        number.as_f64().map(Json::from).unwrap_or(Json::Null)
+    }
+}
+
+fn yaml_to_json(value: &Value) -> Json {
+    match value {
+        Value::Null => Json::Null,
+        Value::Bool(flag) => Json::Bool(*flag),
+        Value::Number(number) => number_to_json(number),
+        Value::String(string) => Json::String(string.clone()),
+        Value::Sequence(items) => Json::Array(items.iter().map(yaml_to_json).collect()),
+        Value::Mapping(mapping) => Json::Object(
+            mapping
+                .iter()
+                .filter_map(|(key, value)| {
+                    Some((key_string(key)?.into_owned(), yaml_to_json(value)))
+                })
+                .collect(),
+        ),
+        Value::Tagged(tagged) => yaml_to_json(&tagged.value),
+    }
+}
+
+fn json_to_yaml(value: Json) -> Value {
+    match value {
+        Json::Null => Value::Null,
+        Json::Bool(flag) => Value::Bool(flag),
+        Json::Number(number) => match (number.as_i64(), number.as_u64()) {
+            (Some(number), _) => Value::from(number),
+            (None, Some(number)) => Value::from(number),
+            _ => Value::from(number.as_f64().unwrap_or_default()),
+        },
+        Json::String(string) => Value::String(string),
+        Json::Array(items) => Value::Sequence(items.into_iter().map(json_to_yaml).collect()),
+        Json::Object(map) => Value::Mapping(
+            map.into_iter()
+                .map(|(key, value)| (Value::String(key), json_to_yaml(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// YAML values. Tags are transparent: a tagged value is treated as the value
+/// it wraps. Mapping keys that are numbers, booleans, or null are addressed by
+/// their formatted value, e.g. `/ports/80`; other non-string keys are skipped.
+impl Document for Value {
+    fn from_json(value: Json) -> Result<Self, DocumentError> {
+        Ok(json_to_yaml(value))
+    }
+
+    fn to_json(&self) -> Cow<'_, Json> {
+        Cow::Owned(yaml_to_json(self))
+    }
+
+    fn elements(&self) -> Option<&[Self]> {
+        match self {
+            Value::Sequence(items) => Some(items),
+            Value::Tagged(tagged) => tagged.value.elements(),
+            _ => None,
+        }
+    }
+
+    fn elements_mut(&mut self) -> Option<&mut Vec<Self>> {
+        match self {
+            Value::Sequence(items) => Some(items),
+            Value::Tagged(tagged) => tagged.value.elements_mut(),
+            _ => None,
+        }
+    }
+
+    fn object_len(&self) -> Option<usize> {
+        match self {
+            Value::Mapping(mapping) => Some(
+                mapping
+                    .keys()
+                    .filter(|key| key_string(key).is_some())
+                    .count(),
+            ),
+            Value::Tagged(tagged) => tagged.value.object_len(),
+            _ => None,
+        }
+    }
+
+    fn member(&self, key: &str) -> Option<&Self> {
+        match self {
+            Value::Mapping(mapping) => mapping.get(find_key(mapping, key)?),
+            Value::Tagged(tagged) => tagged.value.member(key),
+            _ => None,
+        }
+    }
+
+    fn member_mut(&mut self, key: &str) -> Option<&mut Self> {
+        match self {
+            Value::Mapping(mapping) => {
+                let key = find_key(mapping, key)?.clone();
+                mapping.get_mut(key)
+            }
+            Value::Tagged(tagged) => tagged.value.member_mut(key),
+            _ => None,
+        }
+    }
+
+    fn members(&self) -> Members<'_, Self> {
+        match self {
+            Value::Mapping(mapping) => Box::new(
+                mapping
+                    .iter()
+                    .filter_map(|(key, value)| Some((key_string(key)?, value))),
+            ),
+            Value::Tagged(tagged) => tagged.value.members(),
+            _ => Box::new(iter::empty()),
+        }
+    }
+
+    fn insert_member(&mut self, key: String, value: Self) -> Result<Option<Self>, Self> {
+        match self {
+            Value::Mapping(mapping) => {
+                let key = find_key(mapping, &key)
+                    .cloned()
+                    .unwrap_or(Value::String(key));
+                Ok(mapping.insert(key, value))
+            }
+            Value::Tagged(tagged) => tagged.value.insert_member(key, value),
+            _ => Err(value),
+        }
+    }
+
+    fn remove_member(&mut self, key: &str) -> Option<Self> {
+        match self {
+            Value::Mapping(mapping) => {
+                let key = find_key(mapping, key)?.clone();
+                // Keep the order of the remaining members.
+                mapping.shift_remove(key)
+            }
+            Value::Tagged(tagged) => tagged.value.remove_member(key),
+            _ => None,
+        }
+    }
+
+    fn shift_remove_member(&mut self, key: &str) -> Option<(usize, Self)> {
+        match self {
+            Value::Mapping(mapping) => {
+                let key = find_key(mapping, key)?.clone();
+                // Positions count every key, including the ones that can't
+                // address a member
+                let position = mapping.keys().position(|candidate| *candidate == key)?;
+                Some((position, mapping.shift_remove(key)?))
+            }
+            Value::Tagged(tagged) => tagged.value.shift_remove_member(key),
+            _ => None,
+        }
+    }
+
+    fn shift_insert_member(
+        &mut self,
+        position: usize,
+        key: String,
+        value: Self,
+    ) -> Result<Option<Self>, Self> {
+        match self {
+            Value::Mapping(mapping) => {
+                if let Some(key) = find_key(mapping, &key).cloned() {
+                    return Ok(mapping.insert(key, value));
+                }
+                mapping.insert(Value::String(key), value);
+                // Mappings can only append, so the members that were after
+                // `position` are moved behind the new one
+                let following: Vec<Value> = mapping
+                    .keys()
+                    .skip(position)
+                    .take(mapping.len().saturating_sub(position + 1))
+                    .cloned()
+                    .collect();
+                for key in following {
+                    if let Some(member) = mapping.shift_remove(&key) {
+                        mapping.insert(key, member);
+                    }
+                }
+                Ok(None)
+            }
+            Value::Tagged(tagged) => tagged.value.shift_insert_member(position, key, value),
+            _ => Err(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert2::{assert, check};
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        diff::{DiffOptions, PatchOp, diff},
+        patch::{apply, apply_in_place},
+        path::Spath,
+    };
+
+    fn path(raw: &str) -> Spath {
+        raw.try_into().unwrap()
+    }
+
+    fn yaml(raw: &str) -> Value {
+        serde_yaml::from_str(raw).unwrap()
+    }
+
+    #[test]
+    fn apply_should_patch_yaml_in_place_of_json() {
+        let doc = yaml("name: spatch\ntags: [a, b]\n");
+        let patch = vec![
+            PatchOp::add(path("/version"), json!(2)),
+            PatchOp::remove(path("/tags/0")),
+        ];
+
+        let result = apply(&doc, &patch).unwrap();
+
+        check!(result == yaml("name: spatch\ntags: [b]\nversion: 2\n"));
+    }
+
+    #[test]
+    fn members_should_address_integer_keys_by_their_formatted_value() {
+        let doc = yaml("ports:\n  80: http\n  443: https\n");
+        let patch = vec![PatchOp::replace(path("/ports/80"), json!("web"))];
+
+        let result = apply(&doc, &patch).unwrap();
+
+        check!(result == yaml("ports:\n  80: web\n  443: https\n"));
+    }
+
+    #[test]
+    fn remove_member_should_keep_the_order_of_the_other_members() {
+        let mut doc = yaml("c: 1\na: 2\nb: 3\n");
+
+        assert!(doc.remove_member("c") == Some(Value::from(1)));
+        check!(serde_yaml::to_string(&doc).unwrap() == "a: 2\nb: 3\n");
+    }
+
+    #[test]
+    fn apply_in_place_should_restore_the_order_of_members_on_failure() {
+        let mut doc = yaml("a: 1\nb: 2\nc: 3\nd: {x: 1}\n");
+        let patch = vec![
+            PatchOp::remove(path("/a")),
+            PatchOp::move_op(path("/c"), path("/e")),
+            PatchOp::move_op(path("/d"), path("/b")),
+            PatchOp::test(path("/b"), json!(99)),
+        ];
+
+        assert!(let Err(_) = apply_in_place(&mut doc, &patch));
+
+        check!(serde_yaml::to_string(&doc).unwrap() == "a: 1\nb: 2\nc: 3\nd:\n  x: 1\n");
+    }
+
+    #[test]
+    fn diff_should_see_through_tags() {
+        let left = yaml("value: !custom 1\n");
+        let right = yaml("value: !custom 2\n");
+
+        let patch = diff(&left, &right, DiffOptions::new()).unwrap();
+
+        check!(*patch == [PatchOp::replace(path("/value"), json!(2))]);
+    }
+}
@@ -1,4 +1,8 @@
+pub mod cst;
 pub mod diff;
+pub mod document;
+pub mod merge_patch;
 pub mod patch;
 pub mod path;
 pub mod resolve;
+pub mod stream;
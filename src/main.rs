@@ -11,6 +11,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     match cli.cmd {
         cli::Command::Query(args) => cli::query::handle_query_command(args)?,
         cli::Command::Diff(diff_args) => cli::diff::handle_diff_command(diff_args)?,
+        cli::Command::Apply(apply_args) => cli::apply::handle_apply_command(apply_args)?,
     }
 
     Ok(())
@@ -0,0 +1,840 @@
+//! JSON Merge Patch ([RFC 7396](https://www.rfc-editor.org/rfc/rfc7396)).
+//!
+//! A merge patch is a partial document: its members replace the members of
+//! the target, nested objects are merged recursively, and `null` removes a
+//! member. Arrays and other values are replaced as a whole.
+//!
+//! ```rust
+//! use serde_json::json;
+//! use spatch::merge_patch;
+//!
+//! let before = json!({"name": "api", "replicas": 2, "labels": {"tier": "web"}});
+//! let after = json!({"name": "api", "replicas": 3, "labels": {}});
+//!
+//! let merge = merge_patch::diff(&before, &after).unwrap();
+//!
+//! assert_eq!(merge, json!({"replicas": 3, "labels": {"tier": null}}));
+//! assert_eq!(merge_patch::apply(&before, &merge), after);
+//! ```
+//!
+//! Merge patches can't set a member to `null` or edit single array elements.
+//! [`from_patch`] converts a JSON Patch when it does neither, and [`to_patch`]
+//! converts the other way.
+//!
+//! With a schema, [`diff_with_schema`] and [`apply_with_schema`] extend merge
+//! patches to merge arrays with an `x-spatch-indexKey` by identity, so a merge
+//! patch can add, change, or remove single elements of keyed collections.
+pub mod strategic;
+
+use serde_json::{Map, Value};
+
+use crate::{
+    diff::{
+        DiffErrorSummary, DiffOptions, Patch, PatchOp, PatchOpRef, PatchSink, diff_into,
+        index_key_value_to_filter,
+    },
+    patch::PatchError,
+    path::{Segment, Spath},
+    resolve::resolve_ref,
+};
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum MergePatchError {
+    #[error("Merge patches can't set {path} to null, null removes the member instead")]
+    ExplicitNull { path: Spath },
+
+    #[error("Merge patches replace arrays as a whole and can't edit the element at {path}")]
+    ArrayElement { path: Spath },
+
+    #[error("Merge patches can't express the test at {path}")]
+    Test { path: Spath },
+
+    #[error("Element {path} of a keyed array has no identity key {key}")]
+    MissingIndexKey { path: Spath, key: String },
+
+    #[error("Unsupported strategic merge patch directive {directive} at {path}")]
+    UnsupportedDirective { path: Spath, directive: String },
+
+    #[error("Failed to apply the patch: {0}")]
+    Patch(#[from] PatchError),
+
+    #[error("Failed to diff the documents: {0}")]
+    Diff(#[from] DiffErrorSummary),
+}
+
+/// Member of an element patch that removes the element from its keyed array,
+/// for example `{"id": "u-2", "$delete": true}`.
+pub const DELETE_MARKER: &str = "$delete";
+
+/// Applies `merge` to `target` and returns the result.
+///
+/// Merging never fails: a patch that isn't an object replaces the target, and
+/// an object patch turns a target that isn't an object into one.
+pub fn apply(target: &Value, merge: &Value) -> Value {
+    let mut target = target.clone();
+    apply_in_place(&mut target, merge);
+    target
+}
+
+/// Applies `merge` to `target` in place.
+pub fn apply_in_place(target: &mut Value, merge: &Value) {
+    // Without a schema, merging can't fail
+    let _ = merge_into(target, merge, DiffOptions::new(), &Spath::default());
+}
+
+/// Applies a semantic merge patch, merging the arrays `schema` gives an
+/// `x-spatch-indexKey` by identity.
+///
+/// In a keyed array, the merge patch lists element patches that each carry
+/// the identity key. An element patch is merged into the element with the
+/// same identity, or appended when there is none, and removes the element
+/// when it holds the [`DELETE_MARKER`]. Elements the merge patch doesn't
+/// mention are kept, in their order. Other arrays are replaced as a whole.
+///
+/// ```rust
+/// use serde_json::json;
+/// use spatch::merge_patch;
+///
+/// let schema = json!({"properties": {"users": {"x-spatch-indexKey": "id"}}});
+/// let target = json!({"users": [
+///     {"id": "u-1", "name": "Ada", "admin": false},
+///     {"id": "u-2", "name": "Grace"}
+/// ]});
+/// let merge = json!({"users": [
+///     {"id": "u-1", "admin": true},
+///     {"id": "u-2", "$delete": true},
+///     {"id": "u-3", "name": "Linus"}
+/// ]});
+///
+/// assert_eq!(
+///     merge_patch::apply_with_schema(&target, &merge, &schema).unwrap(),
+///     json!({"users": [
+///         {"id": "u-1", "name": "Ada", "admin": true},
+///         {"id": "u-3", "name": "Linus"}
+///     ]})
+/// );
+/// ```
+///
+/// Fails with [`MergePatchError::MissingIndexKey`] when an element patch of a
+/// keyed array isn't an object holding the identity key.
+pub fn apply_with_schema(
+    target: &Value,
+    merge: &Value,
+    schema: &Value,
+) -> Result<Value, MergePatchError> {
+    let mut target = target.clone();
+    merge_into(
+        &mut target,
+        merge,
+        DiffOptions::new().with_schema(schema),
+        &Spath::default(),
+    )?;
+    Ok(target)
+}
+
+fn merge_into(
+    target: &mut Value,
+    merge: &Value,
+    options: DiffOptions,
+    path: &Spath,
+) -> Result<(), MergePatchError> {
+    match merge {
+        Value::Object(members) => {
+            if !target.is_object() {
+                *target = Value::Object(Map::new());
+            }
+            let Value::Object(target) = target else {
+                return Ok(());
+            };
+
+            for (name, value) in members {
+                if value.is_null() {
+                    target.remove(name);
+                } else {
+                    let child = target.entry(name).or_insert(Value::Null);
+                    let child_options = options.with_optional_schema(options.property_schema(name));
+                    merge_into(
+                        child,
+                        value,
+                        child_options,
+                        &path.push(Segment::Field(name.clone())),
+                    )?;
+                }
+            }
+        }
+        Value::Array(patches) if options.schema.is_some() => match options.index_key() {
+            Some(key) => merge_keyed(target, patches, key, options, path)?,
+            None => *target = merge.clone(),
+        },
+        _ => *target = merge.clone(),
+    }
+    Ok(())
+}
+
+/// Merges element patches into a keyed array, matching elements by identity.
+fn merge_keyed(
+    target: &mut Value,
+    patches: &[Value],
+    key: &str,
+    options: DiffOptions,
+    path: &Spath,
+) -> Result<(), MergePatchError> {
+    if !target.is_array() {
+        *target = Value::Array(Vec::new());
+    }
+    let Value::Array(elements) = target else {
+        return Ok(());
+    };
+    let item_options = options.with_optional_schema(options.items_schema());
+
+    for (index, patch) in patches.iter().enumerate() {
+        let Some(identity) = patch.get(key).and_then(index_key_value_to_filter) else {
+            return Err(MergePatchError::MissingIndexKey {
+                path: path.push(Segment::Field(index.to_string())),
+                key: key.to_owned(),
+            });
+        };
+        let position = elements.iter().position(|element| {
+            element
+                .get(key)
+                .and_then(index_key_value_to_filter)
+                .as_ref()
+                == Some(&identity)
+        });
+        let element_path = path.push_filter(key, &identity);
+
+        if patch.get(DELETE_MARKER) == Some(&Value::Bool(true)) {
+            if let Some(position) = position {
+                elements.remove(position);
+            }
+            continue;
+        }
+        match position {
+            Some(position) => {
+                merge_into(&mut elements[position], patch, item_options, &element_path)?
+            }
+            None => {
+                let mut element = Value::Null;
+                merge_into(&mut element, patch, item_options, &element_path)?;
+                elements.push(element);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Computes the merge patch turning `left` into `right`.
+///
+/// Objects are walked like [`diff`](crate::diff::diff) walks them in granular
+/// mode, while arrays that differ are replaced as a whole. When either
+/// document isn't an object, the merge patch is `right` itself.
+///
+/// Fails with [`MergePatchError::ExplicitNull`] when `right` holds a `null`
+/// member that `left` doesn't, since a merge patch can only express that as
+/// a removal.
+pub fn diff(left: &Value, right: &Value) -> Result<Value, MergePatchError> {
+    diff_with_options(left, right, DiffOptions::new())
+}
+
+/// Computes the semantic merge patch turning `left` into `right`, which
+/// [`apply_with_schema`] applies.
+///
+/// Arrays `schema` gives an `x-spatch-indexKey` are diffed by identity: the
+/// merge patch lists an element patch for each element that was added,
+/// removed, or changed, and leaves the others out. Other arrays that differ
+/// are replaced as a whole. Like JSON Patches diffed with a schema, the
+/// merge patch doesn't reorder elements, so new elements end up last.
+///
+/// ```rust
+/// use serde_json::json;
+/// use spatch::merge_patch;
+///
+/// let schema = json!({"properties": {"users": {"x-spatch-indexKey": "id"}}});
+/// let before = json!({"users": [{"id": 1, "name": "Ada"}, {"id": 2, "name": "Grace"}]});
+/// let after = json!({"users": [{"id": 1, "name": "Ada L."}]});
+///
+/// assert_eq!(
+///     merge_patch::diff_with_schema(&before, &after, &schema).unwrap(),
+///     json!({"users": [{"id": 2, "$delete": true}, {"id": 1, "name": "Ada L."}]})
+/// );
+/// ```
+///
+/// Fails like [`diff`], and with [`MergePatchError::Diff`] when the elements
+/// of a keyed array don't have unique identities.
+pub fn diff_with_schema(
+    left: &Value,
+    right: &Value,
+    schema: &Value,
+) -> Result<Value, MergePatchError> {
+    diff_with_options(left, right, DiffOptions::new().with_schema(schema))
+}
+
+fn diff_with_options(
+    left: &Value,
+    right: &Value,
+    options: DiffOptions,
+) -> Result<Value, MergePatchError> {
+    let options = DiffOptions {
+        atomic_arrays: true,
+        ..options.granular()
+    };
+    let mut sink = MergePatchSink {
+        left,
+        merge: Value::Object(Map::new()),
+        error: None,
+    };
+    diff_into(left, right, options, &mut sink)?;
+
+    match sink.error {
+        Some(error) => Err(error),
+        // Only objects are merged, anything else is replaced by the patch,
+        // even when it didn't change
+        None if !left.is_object() || !right.is_object() => Ok(right.clone()),
+        None => Ok(sink.merge),
+    }
+}
+
+/// Converts a JSON Patch into a merge patch with the same effect on `target`.
+///
+/// The patch is applied to `target` first, and fails like
+/// [`patch::apply`](crate::patch::apply). Patches that edit array elements,
+/// set members to `null`, or contain `test` operations can't be represented.
+///
+/// ```rust
+/// use serde_json::json;
+/// use spatch::{diff::PatchOp, merge_patch};
+///
+/// let target = json!({"a": {"b": 1, "c": 2}, "tags": ["x"]});
+/// let patch = [
+///     PatchOp::remove("/a/b".try_into().unwrap()),
+///     PatchOp::move_op("/a/c".try_into().unwrap(), "/d".try_into().unwrap()),
+/// ];
+///
+/// assert_eq!(
+///     merge_patch::from_patch(&target, &patch).unwrap(),
+///     json!({"a": {"b": null, "c": null}, "d": 2})
+/// );
+///
+/// let element = [PatchOp::add("/tags/-".try_into().unwrap(), json!("y"))];
+/// assert!(merge_patch::from_patch(&target, &element).is_err());
+/// ```
+pub fn from_patch(target: &Value, patch: &[PatchOp]) -> Result<Value, MergePatchError> {
+    if let Some(PatchOp::Test { path, .. }) =
+        patch.iter().find(|op| matches!(op, PatchOp::Test { .. }))
+    {
+        return Err(MergePatchError::Test { path: path.clone() });
+    }
+
+    let patched = crate::patch::apply(target, patch)?;
+    for op in patch {
+        for path in [Some(op.path()), op.from()].into_iter().flatten() {
+            check_outside_arrays(path, target)?;
+            check_outside_arrays(path, &patched)?;
+        }
+    }
+    diff(target, &patched)
+}
+
+/// Converts a merge patch into a JSON Patch with the same effect on `target`.
+///
+/// Members the merge patch removes become `remove` operations, and only
+/// members that change are added or replaced. Members the merge patch
+/// removes but `target` doesn't have are skipped, since JSON Patch can't
+/// remove them.
+///
+/// ```rust
+/// use serde_json::json;
+/// use spatch::{diff::PatchOp, merge_patch};
+///
+/// let target = json!({"a": {"b": 1, "c": 2}});
+/// let merge = json!({"a": {"b": null, "c": 2, "d": 3}});
+///
+/// assert_eq!(
+///     *merge_patch::to_patch(&target, &merge),
+///     [
+///         PatchOp::remove("/a/b".try_into().unwrap()),
+///         PatchOp::add("/a/d".try_into().unwrap(), json!(3)),
+///     ]
+/// );
+/// ```
+pub fn to_patch(target: &Value, merge: &Value) -> Patch {
+    let mut patch = Patch::default();
+    merge_ops(target, merge, &Spath::default(), &mut patch);
+    patch
+}
+
+fn merge_ops(target: &Value, merge: &Value, path: &Spath, patch: &mut Patch) {
+    let (Value::Object(target), Value::Object(members)) = (target, merge) else {
+        let merged = apply(target, merge);
+        if merged != *target {
+            patch.push(PatchOp::replace(path.clone(), merged));
+        }
+        return;
+    };
+
+    for (name, value) in members {
+        let member_path = path.push(Segment::Field(name.clone()));
+        match target.get(name) {
+            Some(_) if value.is_null() => patch.push(PatchOp::remove(member_path)),
+            Some(old) => merge_ops(old, value, &member_path, patch),
+            None if value.is_null() => {}
+            None => patch.push(PatchOp::add(member_path, apply(&Value::Null, value))),
+        }
+    }
+}
+
+/// Fails when `path` addresses something inside an array of `doc`.
+fn check_outside_arrays(path: &Spath, doc: &Value) -> Result<(), MergePatchError> {
+    let mut node = Some(doc);
+    for segment in path {
+        node = match (segment, node) {
+            (Segment::Filter(_), _) | (_, Some(Value::Array(_))) => {
+                return Err(MergePatchError::ArrayElement { path: path.clone() });
+            }
+            (Segment::Field(name), Some(Value::Object(members))) => members.get(name),
+            _ => None,
+        };
+    }
+    Ok(())
+}
+
+/// Builds a merge patch from the operations of a granular diff.
+struct MergePatchSink<'l> {
+    /// The diffed document, to look up the identities of keyed elements.
+    left: &'l Value,
+    merge: Value,
+    error: Option<MergePatchError>,
+}
+
+impl<'a> PatchSink<'a> for MergePatchSink<'_> {
+    fn push(&mut self, op: PatchOpRef<'a>) {
+        if self.error.is_none()
+            && let Err(error) = self.insert(op)
+        {
+            self.error = Some(error);
+        }
+    }
+}
+
+impl MergePatchSink<'_> {
+    fn insert(&mut self, op: PatchOpRef) -> Result<(), MergePatchError> {
+        let (path, value) = match op {
+            PatchOpRef::Add { path, value } | PatchOpRef::Replace { path, value } => {
+                // A null root replaces the target, like any other value
+                if !(path.is_empty() && value.is_null())
+                    && let Some(path) = find_null(&value, &path)
+                {
+                    return Err(MergePatchError::ExplicitNull { path });
+                }
+                (path, value.into_owned())
+            }
+            PatchOpRef::Remove { path } => (path, Value::Null),
+            // Diffs without tests or move detection emit no other operations
+            _ => return Ok(()),
+        };
+
+        let Some((last, parents)) = path.segments.split_last() else {
+            self.merge = value;
+            return Ok(());
+        };
+        let mut node = &mut self.merge;
+        let mut prefix = Spath::default();
+        for segment in parents {
+            node = child(self.left, node, &prefix, segment)?;
+            prefix = prefix.push(segment.clone());
+        }
+
+        match last {
+            // New elements of keyed arrays are appended
+            Segment::Field(name)
+                if name == "-" && resolve_ref(self.left, &prefix).is_ok_and(Value::is_array) =>
+            {
+                if !node.is_array() {
+                    *node = Value::Array(Vec::new());
+                }
+                if let Value::Array(patches) = node {
+                    patches.push(value);
+                }
+            }
+            Segment::Filter(_) => {
+                let element = child(self.left, node, &prefix, last)?;
+                if let Value::Object(members) = element {
+                    members.insert(DELETE_MARKER.to_owned(), Value::Bool(true));
+                }
+            }
+            Segment::Field(_) => *child(self.left, node, &prefix, last)? = value,
+        }
+        Ok(())
+    }
+}
+
+/// Returns the merge patch node for `segment` under `node`, creating it. Keyed
+/// elements get an element patch holding their identity key.
+fn child<'m>(
+    left: &Value,
+    node: &'m mut Value,
+    prefix: &Spath,
+    segment: &Segment,
+) -> Result<&'m mut Value, MergePatchError> {
+    match segment {
+        Segment::Field(name) => {
+            if !node.is_object() {
+                *node = Value::Object(Map::new());
+            }
+            let Value::Object(members) = node else {
+                unreachable!("the node was just made an object");
+            };
+            Ok(members.entry(name).or_insert(Value::Null))
+        }
+        Segment::Filter(filters) => {
+            let element_path = prefix.push(segment.clone());
+            let identity = filters
+                .first()
+                .and_then(|(key, _)| Some((key, resolve_ref(left, &element_path).ok()?.get(key)?)))
+                .ok_or(MergePatchError::ArrayElement {
+                    path: element_path.clone(),
+                })?;
+
+            if !node.is_array() {
+                *node = Value::Array(Vec::new());
+            }
+            let Value::Array(patches) = node else {
+                unreachable!("the node was just made an array");
+            };
+            let position = patches
+                .iter()
+                .position(|patch| patch.get(identity.0) == Some(identity.1));
+            let position = position.unwrap_or_else(|| {
+                let mut patch = Map::new();
+                patch.insert(identity.0.clone(), identity.1.clone());
+                patches.push(Value::Object(patch));
+                patches.len() - 1
+            });
+            Ok(&mut patches[position])
+        }
+    }
+}
+
+/// Finds a `null` that a merge patch would read as a removal: `value` itself,
+/// or a member of a nested object. Nulls inside arrays are kept as they are.
+fn find_null(value: &Value, path: &Spath) -> Option<Spath> {
+    match value {
+        Value::Null => Some(path.clone()),
+        Value::Object(members) => members
+            .iter()
+            .find_map(|(name, value)| find_null(value, &path.push(Segment::Field(name.clone())))),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert2::{assert, check};
+    use serde_json::json;
+
+    use super::*;
+
+    fn path(raw: &str) -> Spath {
+        raw.try_into().unwrap()
+    }
+
+    #[test]
+    fn apply_should_follow_the_rfc_examples() {
+        let cases = [
+            (json!({"a": "b"}), json!({"a": "c"}), json!({"a": "c"})),
+            (
+                json!({"a": "b"}),
+                json!({"b": "c"}),
+                json!({"a": "b", "b": "c"}),
+            ),
+            (json!({"a": "b"}), json!({"a": null}), json!({})),
+            (
+                json!({"a": "b", "b": "c"}),
+                json!({"a": null}),
+                json!({"b": "c"}),
+            ),
+            (json!({"a": ["b"]}), json!({"a": "c"}), json!({"a": "c"})),
+            (json!({"a": "c"}), json!({"a": ["b"]}), json!({"a": ["b"]})),
+            (
+                json!({"a": {"b": "c"}}),
+                json!({"a": {"b": "d", "c": null}}),
+                json!({"a": {"b": "d"}}),
+            ),
+            (
+                json!({"a": [{"b": "c"}]}),
+                json!({"a": [1]}),
+                json!({"a": [1]}),
+            ),
+            (json!(["a", "b"]), json!(["c", "d"]), json!(["c", "d"])),
+            (json!({"a": "b"}), json!(["c"]), json!(["c"])),
+            (json!({"a": "foo"}), json!(null), json!(null)),
+            (json!({"a": "foo"}), json!("bar"), json!("bar")),
+            (
+                json!({"e": null}),
+                json!({"a": 1}),
+                json!({"e": null, "a": 1}),
+            ),
+            (
+                json!([1, 2]),
+                json!({"a": "b", "c": null}),
+                json!({"a": "b"}),
+            ),
+            (
+                json!({}),
+                json!({"a": {"bb": {"ccc": null}}}),
+                json!({"a": {"bb": {}}}),
+            ),
+        ];
+
+        for (target, merge, expected) in cases {
+            check!(apply(&target, &merge) == expected);
+        }
+    }
+
+    #[test]
+    fn diff_should_merge_objects_and_remove_members_with_null() {
+        let left = json!({"a": {"b": 1, "c": 2}, "d": 3, "e": "same"});
+        let right = json!({"a": {"b": 1, "c": 20}, "f": {"g": true}, "e": "same"});
+
+        let merge = diff(&left, &right).unwrap();
+
+        check!(merge == json!({"a": {"c": 20}, "d": null, "f": {"g": true}}));
+        check!(apply(&left, &merge) == right);
+    }
+
+    #[test]
+    fn diff_should_replace_changed_arrays_as_a_whole() {
+        let left = json!({"tags": ["a", "b", {"c": 1}], "same": [1]});
+        let right = json!({"tags": ["a", {"c": 2}, null], "same": [1]});
+
+        let merge = diff(&left, &right).unwrap();
+
+        check!(merge == json!({"tags": ["a", {"c": 2}, null]}));
+        check!(apply(&left, &merge) == right);
+    }
+
+    #[test]
+    fn diff_should_replace_values_that_are_not_objects() {
+        check!(diff(&json!({"a": 1}), &json!([1])).unwrap() == json!([1]));
+        check!(diff(&json!([1]), &json!({"a": 1})).unwrap() == json!({"a": 1}));
+        check!(diff(&json!({"a": 1}), &json!(null)).unwrap() == json!(null));
+        check!(diff(&json!({"a": 1}), &json!({"a": 1})).unwrap() == json!({}));
+    }
+
+    #[test]
+    fn diff_should_round_trip_documents_that_are_not_objects() {
+        for (left, right) in [
+            (json!(1), json!(1)),
+            (json!([]), json!([])),
+            (json!("a"), json!(["b"])),
+            (json!([1]), json!({"a": [null]})),
+            (json!(null), json!(2)),
+        ] {
+            let merge = diff(&left, &right).unwrap();
+            check!(apply(&left, &merge) == right, "{left} to {right}");
+
+            let merge = diff_with_schema(&left, &right, &keyed_schema()).unwrap();
+            check!(apply_with_schema(&left, &merge, &keyed_schema()).unwrap() == right);
+        }
+    }
+
+    #[test]
+    fn diff_should_reject_explicit_nulls_in_a_new_root() {
+        assert!(
+            let Err(MergePatchError::ExplicitNull { path: null }) =
+                diff(&json!(0), &json!({"c": 1.5, "e": null}))
+        );
+        check!(null == path("/e"));
+
+        assert!(
+            let Err(MergePatchError::ExplicitNull { path: null }) =
+                diff_with_schema(&json!([]), &json!({"a": {"b": null}}), &keyed_schema())
+        );
+        check!(null == path("/a/b"));
+    }
+
+    #[test]
+    fn diff_should_reject_explicit_nulls() {
+        assert!(
+            let Err(MergePatchError::ExplicitNull { path: null }) =
+                diff(&json!({"a": 1}), &json!({"a": null}))
+        );
+        check!(null == path("/a"));
+
+        assert!(
+            let Err(MergePatchError::ExplicitNull { path: null }) =
+                diff(&json!({}), &json!({"a": {"b": null}}))
+        );
+        check!(null == path("/a/b"));
+
+        // Nulls already in the target don't need to be set
+        check!(diff(&json!({"a": null}), &json!({"a": null, "b": 1})).unwrap() == json!({"b": 1}));
+    }
+
+    #[test]
+    fn from_patch_should_merge_the_changes_of_the_patch() {
+        let target = json!({"a": {"b": 1}, "c": [1, 2]});
+        let patch = [
+            PatchOp::add(path("/a/d"), json!({"e": 1})),
+            PatchOp::replace(path("/c"), json!([3])),
+            PatchOp::copy(path("/a/b"), path("/f")),
+        ];
+
+        let merge = from_patch(&target, &patch).unwrap();
+
+        check!(merge == json!({"a": {"d": {"e": 1}}, "c": [3], "f": 1}));
+        check!(apply(&target, &merge) == crate::patch::apply(&target, &patch).unwrap());
+    }
+
+    #[test]
+    fn from_patch_should_reject_what_merge_patches_cannot_express() {
+        let target = json!({"a": 1, "items": [{"id": "x", "n": 1}]});
+
+        assert!(
+            let Err(MergePatchError::ArrayElement { .. }) =
+                from_patch(&target, &[PatchOp::replace(path("/items/0/n"), json!(2))])
+        );
+        assert!(
+            let Err(MergePatchError::ArrayElement { .. }) =
+                from_patch(&target, &[PatchOp::remove(path("/items/[id=x]"))])
+        );
+        assert!(
+            let Err(MergePatchError::ArrayElement { .. }) =
+                from_patch(&target, &[PatchOp::move_op(path("/items/0"), path("/b"))])
+        );
+        assert!(
+            let Err(MergePatchError::ExplicitNull { .. }) =
+                from_patch(&target, &[PatchOp::replace(path("/a"), json!(null))])
+        );
+        assert!(
+            let Err(MergePatchError::Test { .. }) =
+                from_patch(&target, &[PatchOp::test(path("/a"), json!(1))])
+        );
+        assert!(
+            let Err(MergePatchError::Patch(_)) =
+                from_patch(&target, &[PatchOp::remove(path("/missing"))])
+        );
+    }
+
+    #[test]
+    fn to_patch_should_have_the_same_effect_as_the_merge_patch() {
+        let target = json!({"a": {"b": 1}, "c": "text", "d": [1]});
+        let merge = json!({"a": {"b": null, "x": {"y": null}}, "c": {"z": 1}, "d": [1], "e": null});
+
+        let patch = to_patch(&target, &merge);
+
+        check!(
+            *patch
+                == [
+                    PatchOp::remove(path("/a/b")),
+                    PatchOp::add(path("/a/x"), json!({})),
+                    PatchOp::replace(path("/c"), json!({"z": 1})),
+                ]
+        );
+        check!(crate::patch::apply(&target, &patch).unwrap() == apply(&target, &merge));
+    }
+
+    #[test]
+    fn to_patch_should_replace_the_root_with_values_that_are_not_objects() {
+        check!(
+            *to_patch(&json!({"a": 1}), &json!([1])) == [PatchOp::replace(path(""), json!([1]))]
+        );
+        check!(
+            *to_patch(&json!([1]), &json!({"a": 1, "b": null}))
+                == [PatchOp::replace(path(""), json!({"a": 1}))]
+        );
+    }
+
+    fn keyed_schema() -> Value {
+        json!({
+            "properties": {
+                "users": {
+                    "x-spatch-indexKey": "id",
+                    "items": {
+                        "properties": {
+                            "roles": { "x-spatch-indexKey": "name" }
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn diff_with_schema_should_merge_keyed_arrays_by_identity() {
+        let left = json!({"users": [
+            {"id": "a", "age": 30, "roles": [{"name": "dev", "level": 1}], "tags": ["x"]},
+            {"id": "b", "age": 40},
+            {"id": "c", "age": 50}
+        ]});
+        let right = json!({"users": [
+            {"id": "a", "age": 31, "roles": [{"name": "dev", "level": 2}, {"name": "ops"}], "tags": ["y"]},
+            {"id": "c", "age": 50},
+            {"id": "d", "age": 20}
+        ]});
+
+        let merge = diff_with_schema(&left, &right, &keyed_schema()).unwrap();
+
+        check!(
+            merge
+                == json!({"users": [
+                    {"id": "b", "$delete": true},
+                    {"id": "d", "age": 20},
+                    {"id": "a", "age": 31, "roles": [{"name": "ops"}, {"name": "dev", "level": 2}], "tags": ["y"]}
+                ]})
+        );
+        check!(apply_with_schema(&left, &merge, &keyed_schema()).unwrap() == right);
+    }
+
+    #[test]
+    fn diff_with_schema_should_remove_members_of_keyed_elements() {
+        let left = json!({"users": [{"id": 1, "age": 30, "email": "a@example.com"}]});
+        let right = json!({"users": [{"id": 1, "age": 30}]});
+
+        let merge = diff_with_schema(&left, &right, &keyed_schema()).unwrap();
+
+        check!(merge == json!({"users": [{"id": 1, "email": null}]}));
+        check!(apply_with_schema(&left, &merge, &keyed_schema()).unwrap() == right);
+    }
+
+    #[test]
+    fn diff_with_schema_should_fail_on_duplicate_identities() {
+        let left = json!({"users": [{"id": "a"}, {"id": "a"}]});
+        let right = json!({"users": []});
+
+        assert!(let Err(MergePatchError::Diff(_)) = diff_with_schema(&left, &right, &keyed_schema()));
+    }
+
+    #[test]
+    fn apply_with_schema_should_keep_unmentioned_elements_in_place() {
+        let target = json!({"users": [{"id": "a"}, {"id": "b", "age": 1}, {"id": "c"}]});
+        let merge = json!({"users": [{"id": "x"}, {"id": "b", "age": 2}, {"id": "missing", "$delete": true}]});
+
+        check!(
+            apply_with_schema(&target, &merge, &keyed_schema()).unwrap()
+                == json!({"users": [{"id": "a"}, {"id": "b", "age": 2}, {"id": "c"}, {"id": "x"}]})
+        );
+    }
+
+    #[test]
+    fn apply_with_schema_should_replace_arrays_without_identity() {
+        let target = json!({"tags": [1, 2], "users": [{"id": "a"}]});
+
+        check!(
+            apply_with_schema(&target, &json!({"tags": [3]}), &keyed_schema()).unwrap()
+                == json!({"tags": [3], "users": [{"id": "a"}]})
+        );
+    }
+
+    #[test]
+    fn apply_with_schema_should_reject_element_patches_without_identity() {
+        let target = json!({"users": [{"id": "a"}]});
+
+        assert!(
+            let Err(MergePatchError::MissingIndexKey { path: element, key }) =
+                apply_with_schema(&target, &json!({"users": [{"age": 1}]}), &keyed_schema())
+        );
+        check!(element == path("/users/0"));
+        check!(key == "id");
+    }
+}
@@ -0,0 +1,317 @@
+//! Kubernetes strategic merge patches.
+//!
+//! A strategic merge patch is a merge patch whose lists are merged by the
+//! `x-kubernetes-patch-merge-key` of their schema, like semantic merge patches
+//! merge arrays with an `x-spatch-indexKey`. Elements are removed with a
+//! `"$patch": "delete"` directive instead of the [`DELETE_MARKER`].
+//!
+//! These converters translate between strategic merge patches and semantic
+//! [`Patch`]es for a target document, so CRDs can be diffed and patched with
+//! their own schemas:
+//!
+//! ```rust
+//! use serde_json::json;
+//! use spatch::{diff::PatchOp, merge_patch::strategic};
+//!
+//! let schema = json!({"properties": {"containers": {
+//!     "x-kubernetes-patch-merge-key": "name"
+//! }}});
+//! let deployment = json!({"containers": [
+//!     {"name": "app", "image": "app:1"},
+//!     {"name": "sidecar", "image": "proxy:1"}
+//! ]});
+//! let strategic_patch = json!({"containers": [
+//!     {"name": "sidecar", "$patch": "delete"},
+//!     {"name": "app", "image": "app:2"}
+//! ]});
+//!
+//! let patch = strategic::to_patch(&deployment, &strategic_patch, &schema).unwrap();
+//!
+//! assert_eq!(
+//!     *patch,
+//!     [
+//!         PatchOp::remove("/containers/[name=sidecar]".try_into().unwrap()),
+//!         PatchOp::replace("/containers/[name=app]/image".try_into().unwrap(), json!("app:2")),
+//!     ]
+//! );
+//! assert_eq!(strategic::from_patch(&deployment, &patch, &schema).unwrap(), strategic_patch);
+//! ```
+//!
+//! Only the `"$patch": "delete"` and `"$patch": "merge"` directives are
+//! translated. Element order isn't part of a semantic patch, so
+//! `$setElementOrder` directives are dropped, and other directives such as
+//! `"$patch": "replace"` or `$retainKeys` are rejected.
+use serde_json::{Map, Value};
+
+use crate::{
+    diff::{DiffOptions, Patch, PatchOp},
+    merge_patch::{DELETE_MARKER, MergePatchError, apply_with_schema, diff_with_schema},
+    path::{Segment, Spath},
+};
+
+/// Member holding the directives of a strategic merge patch.
+const PATCH_DIRECTIVE: &str = "$patch";
+
+/// Prefix of the directives giving the order of a list's elements.
+const SET_ELEMENT_ORDER: &str = "$setElementOrder/";
+
+/// Directives spatch can't translate.
+const UNSUPPORTED_DIRECTIVES: [&str; 2] = ["$retainKeys", "$deleteFromPrimitiveList/"];
+
+/// Converts a strategic merge patch into a semantic JSON Patch with the same
+/// effect on `target`.
+///
+/// Lists are matched by the merge keys of `schema`, so the operations address
+/// their elements with semantic paths like `/containers/[name=app]`.
+pub fn to_patch(
+    target: &Value,
+    strategic: &Value,
+    schema: &Value,
+) -> Result<Patch, MergePatchError> {
+    let merge = from_directives(strategic, &Spath::default(), false)?;
+    let patched = apply_with_schema(target, &merge, schema)?;
+    Ok(crate::diff::diff(
+        target,
+        &patched,
+        DiffOptions::new().with_schema(schema).granular(),
+    )?)
+}
+
+/// Converts a JSON Patch into a strategic merge patch with the same effect on
+/// `target`.
+///
+/// Fails like [`merge_patch::from_patch`](super::from_patch), except that the
+/// elements of lists with a merge key can be edited.
+pub fn from_patch(
+    target: &Value,
+    patch: &[PatchOp],
+    schema: &Value,
+) -> Result<Value, MergePatchError> {
+    if let Some(PatchOp::Test { path, .. }) =
+        patch.iter().find(|op| matches!(op, PatchOp::Test { .. }))
+    {
+        return Err(MergePatchError::Test { path: path.clone() });
+    }
+
+    let patched = crate::patch::apply(target, patch)?;
+    let merge = diff_with_schema(target, &patched, schema)?;
+    Ok(to_directives(merge))
+}
+
+/// Turns the directives of a strategic merge patch into a semantic merge
+/// patch.
+fn from_directives(
+    strategic: &Value,
+    path: &Spath,
+    element: bool,
+) -> Result<Value, MergePatchError> {
+    let unsupported = |directive: &str| MergePatchError::UnsupportedDirective {
+        path: path.clone(),
+        directive: directive.to_owned(),
+    };
+
+    match strategic {
+        Value::Object(members) => {
+            let mut merge = Map::new();
+            for (name, value) in members {
+                if name == PATCH_DIRECTIVE {
+                    match value.as_str() {
+                        Some("merge") => {}
+                        Some("delete") if element => {
+                            merge.insert(DELETE_MARKER.to_owned(), Value::Bool(true));
+                        }
+                        // Deleting a map sets it to null
+                        Some("delete") => return Ok(Value::Null),
+                        _ => return Err(unsupported(&format!("{PATCH_DIRECTIVE}: {value}"))),
+                    }
+                } else if name.starts_with(SET_ELEMENT_ORDER) {
+                    continue;
+                } else if UNSUPPORTED_DIRECTIVES
+                    .iter()
+                    .any(|directive| name.starts_with(directive))
+                {
+                    return Err(unsupported(name));
+                } else {
+                    let member_path = path.push(Segment::Field(name.clone()));
+                    merge.insert(name.clone(), from_directives(value, &member_path, false)?);
+                }
+            }
+            Ok(Value::Object(merge))
+        }
+        Value::Array(elements) => elements
+            .iter()
+            .enumerate()
+            .map(|(index, value)| {
+                from_directives(value, &path.push(Segment::Field(index.to_string())), true)
+            })
+            .collect(),
+        scalar => Ok(scalar.clone()),
+    }
+}
+
+/// Turns the deletion markers of a semantic merge patch into `$patch`
+/// directives.
+fn to_directives(merge: Value) -> Value {
+    match merge {
+        Value::Object(members) => Value::Object(
+            members
+                .into_iter()
+                .map(|(name, value)| (name, to_directives(value)))
+                .collect(),
+        ),
+        Value::Array(elements) => Value::Array(
+            elements
+                .into_iter()
+                .map(|element| match element {
+                    Value::Object(mut members)
+                        if members.get(DELETE_MARKER) == Some(&Value::Bool(true)) =>
+                    {
+                        members.remove(DELETE_MARKER);
+                        members.insert(PATCH_DIRECTIVE.to_owned(), Value::from("delete"));
+                        Value::Object(members)
+                    }
+                    element => to_directives(element),
+                })
+                .collect(),
+        ),
+        scalar => scalar,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert2::{assert, check};
+    use serde_json::json;
+
+    use super::*;
+
+    fn path(raw: &str) -> Spath {
+        raw.try_into().unwrap()
+    }
+
+    fn pod_schema() -> Value {
+        json!({
+            "properties": {
+                "spec": {
+                    "properties": {
+                        "containers": {
+                            "x-kubernetes-patch-merge-key": "name",
+                            "items": {
+                                "properties": {
+                                    "ports": {
+                                        "x-kubernetes-list-type": "map",
+                                        "x-kubernetes-list-map-keys": ["containerPort"]
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    fn pod() -> Value {
+        json!({"spec": {"containers": [
+            {"name": "app", "image": "app:1", "ports": [{"containerPort": 80}]},
+            {"name": "sidecar", "image": "proxy:1"}
+        ]}})
+    }
+
+    #[test]
+    fn to_patch_should_address_list_elements_by_merge_key() {
+        let strategic = json!({
+            "spec": {"$setElementOrder/containers": [{"name": "app"}], "containers": [
+                {"name": "app", "ports": [{"containerPort": 443}, {"containerPort": 80, "$patch": "delete"}]},
+                {"name": "log", "image": "log:1"},
+                {"$patch": "merge", "name": "sidecar", "image": "proxy:2"}
+            ]}
+        });
+
+        let patch = to_patch(&pod(), &strategic, &pod_schema()).unwrap();
+
+        check!(
+            *patch
+                == [
+                    PatchOp::add(
+                        path("/spec/containers/-"),
+                        json!({"name": "log", "image": "log:1"})
+                    ),
+                    PatchOp::remove(path("/spec/containers/[name=app]/ports/[containerPort=80]")),
+                    PatchOp::add(
+                        path("/spec/containers/[name=app]/ports/-"),
+                        json!({"containerPort": 443})
+                    ),
+                    PatchOp::replace(
+                        path("/spec/containers/[name=sidecar]/image"),
+                        json!("proxy:2")
+                    ),
+                ]
+        );
+    }
+
+    #[test]
+    fn to_patch_should_delete_maps_with_a_directive() {
+        let target = json!({"metadata": {"labels": {"a": "b"}, "name": "pod"}});
+        let strategic = json!({"metadata": {"labels": {"$patch": "delete"}}});
+
+        let patch = to_patch(&target, &strategic, &json!({})).unwrap();
+
+        check!(*patch == [PatchOp::remove(path("/metadata/labels"))]);
+    }
+
+    #[test]
+    fn to_patch_should_reject_unsupported_directives() {
+        let replace = json!({"spec": {"$patch": "replace", "containers": []}});
+        let retain = json!({"spec": {"$retainKeys": ["containers"]}});
+
+        assert!(
+            let Err(MergePatchError::UnsupportedDirective { path: at, .. }) =
+                to_patch(&pod(), &replace, &pod_schema())
+        );
+        check!(at == path("/spec"));
+        assert!(
+            let Err(MergePatchError::UnsupportedDirective { .. }) =
+                to_patch(&pod(), &retain, &pod_schema())
+        );
+    }
+
+    #[test]
+    fn from_patch_should_write_delete_directives() {
+        let patch = [
+            PatchOp::remove(path("/spec/containers/[name=sidecar]")),
+            PatchOp::replace(
+                path("/spec/containers/[name=app]/ports/[containerPort=80]/containerPort"),
+                json!(8080),
+            ),
+        ];
+
+        let strategic = from_patch(&pod(), &patch, &pod_schema()).unwrap();
+
+        check!(
+            strategic
+                == json!({"spec": {"containers": [
+                    {"name": "sidecar", "$patch": "delete"},
+                    {"name": "app", "ports": [
+                        {"containerPort": 80, "$patch": "delete"},
+                        {"containerPort": 8080}
+                    ]}
+                ]}})
+        );
+        check!(
+            crate::patch::apply(
+                &pod(),
+                &to_patch(&pod(), &strategic, &pod_schema()).unwrap()
+            )
+            .unwrap()
+                == crate::patch::apply(&pod(), &patch).unwrap()
+        );
+    }
+
+    #[test]
+    fn from_patch_should_reject_tests() {
+        let patch = [PatchOp::test(path("/spec"), json!({}))];
+
+        assert!(let Err(MergePatchError::Test { .. }) = from_patch(&pod(), &patch, &pod_schema()));
+    }
+}
@@ -1,9 +1,8 @@
-use serde_json::Value;
-
 use crate::{
-    patch::error::PatchError,
-    path::Spath,
-    resolve::{resolve_mut, value_type_desc},
+    document::Document,
+    patch::{error::PatchError, journal::Undo},
+    path::{Segment, Spath},
+    resolve::{resolve_mut_concrete, value_type_desc},
 };
 
 /// The "add" operation performs one of the following functions,
@@ -62,50 +61,89 @@ use crate::{
 /// { "q": { "bar": 2 } }
 ///
 /// because "a" does not exist.
-pub fn add(doc: &mut Value, path: Spath, value: Value) -> Result<(), PatchError> {
-    if path.is_empty() {
-        *doc = value;
-        return Ok(());
-    }
-
-    let parent = path.parent().ok_or(PatchError::missing_parent(&path))?;
+pub fn add<D: Document>(doc: &mut D, path: Spath, value: D) -> Result<(), PatchError> {
+    add_with_undo(doc, path, value).map(|_undo| ())
+}
 
-    let target = resolve_mut(doc, &parent)?;
-    let field = path.field().ok_or(PatchError::missing_final_token(&path))?;
+/// Performs [`add`] and returns what undoes it.
+pub(crate) fn add_with_undo<D: Document>(
+    doc: &mut D,
+    path: Spath,
+    value: D,
+) -> Result<Undo<D>, PatchError> {
+    try_add(doc, path, value).map_err(|(error, _value)| error)
+}
 
-    match target {
-        Value::Object(obj) => {
-            obj.insert(field, value);
-        }
-        Value::Array(arr) => {
-            if field == "-" {
-                arr.push(value);
-            } else {
-                let index: usize = field
-                    .parse()
-                    .map_err(|_e| PatchError::invalid_array_index_token(&path, &field))?;
-
-                // Spec defines that index must not be greater than the number of elements
-                if index > arr.len() {
-                    return Err(PatchError::index_out_of_bounds(&path, index, arr.len()));
-                }
-                // serde implements insert with shifting elements to the right
-                // which is the desired behavior according to the spec
-                arr.insert(index, value);
-            }
-        }
-        val => {
-            return Err(PatchError::not_a_container(&parent, &value_type_desc(val)));
+/// Performs [`add`] and returns what undoes it. Hands the value back on
+/// failure, so callers that moved it out of the document can put it back.
+pub(crate) fn try_add<D: Document>(
+    doc: &mut D,
+    path: Spath,
+    value: D,
+) -> Result<Undo<D>, (PatchError, D)> {
+    if path.is_empty() {
+        let old = std::mem::replace(doc, value);
+        return Ok(Undo::Replace { path, value: old });
+    }
+
+    let Some(parent) = path.parent() else {
+        return Err((PatchError::missing_parent(&path), value));
+    };
+
+    let (target, concrete_parent) = match resolve_mut_concrete(doc, &parent) {
+        Ok(resolved) => resolved,
+        Err(error) => return Err((error.into(), value)),
+    };
+    let Some(field) = path.field() else {
+        return Err((PatchError::missing_final_token(&path), value));
+    };
+
+    if target.is_object() {
+        let child = concrete_parent.push(Segment::Field(field.clone()));
+        return match target.insert_member(field, value) {
+            Ok(Some(old)) => Ok(Undo::Replace {
+                path: child,
+                value: old,
+            }),
+            Ok(None) => Ok(Undo::Remove { path: child }),
+            Err(value) => Err((
+                PatchError::not_a_container(&parent, &value_type_desc(target)),
+                value,
+            )),
+        };
+    }
+
+    let Some(arr) = target.elements_mut() else {
+        let error = PatchError::not_a_container(&parent, &value_type_desc(target));
+        return Err((error, value));
+    };
+    let index = if field == "-" {
+        arr.len()
+    } else {
+        let Ok(index) = field.parse::<usize>() else {
+            return Err((PatchError::invalid_array_index_token(&path, &field), value));
+        };
+
+        // Spec defines that index must not be greater than the number of elements
+        if index > arr.len() {
+            let error = PatchError::index_out_of_bounds(&path, index, arr.len());
+            return Err((error, value));
         }
-    }
-
-    Ok(())
+        index
+    };
+    // Vec::insert shifts elements to the right, which is the desired
+    // behavior according to the spec
+    arr.insert(index, value);
+
+    Ok(Undo::Remove {
+        path: concrete_parent.push(Segment::Field(index.to_string())),
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use assert2::{assert, check};
-    use serde_json::{from_str, json};
+    use serde_json::{Value, from_str, json};
 
     use crate::resolve::ResolveError;
 
@@ -1,7 +1,6 @@
-use serde_json::Value;
-
 use crate::{
-    patch::{add, error::PatchError},
+    document::Document,
+    patch::{add::add_with_undo, error::PatchError, journal::Undo},
     path::Spath,
     resolve::resolve_ref,
 };
@@ -21,10 +20,19 @@ use crate::{
 ///
 /// This operation is functionally identical to an "add" operation at the
 /// target location using the value specified in the "from" member.
-pub fn copy(doc: &mut Value, from: Spath, path: Spath) -> Result<(), PatchError> {
+pub fn copy<D: Document>(doc: &mut D, from: Spath, path: Spath) -> Result<(), PatchError> {
+    copy_with_undo(doc, from, path).map(|_undo| ())
+}
+
+/// Performs [`copy`] and returns what undoes it.
+pub(crate) fn copy_with_undo<D: Document>(
+    doc: &mut D,
+    from: Spath,
+    path: Spath,
+) -> Result<Undo<D>, PatchError> {
     let value = resolve_ref(doc, &from)?.clone();
 
-    add(doc, path, value)
+    add_with_undo(doc, path, value)
 }
 
 #[cfg(test)]
@@ -5,6 +5,9 @@ pub enum PatchError {
     #[error("Failed to resolve path: {0}")]
     ResolveError(#[from] crate::resolve::ResolveError),
 
+    #[error("Invalid operation value: {0}")]
+    DocumentError(#[from] crate::document::DocumentError),
+
     #[error("Parent for path {path} does not exist")]
     MissingParent { path: Spath },
 
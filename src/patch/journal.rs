@@ -0,0 +1,93 @@
+use crate::{
+    document::Document,
+    patch::{
+        remove,
+        remove::{put_back, take},
+        replace,
+    },
+    path::Spath,
+};
+
+/// Reverts a single applied operation. Paths are concrete: filter segments
+/// are resolved to array indexes when the operation is applied, so they keep
+/// addressing the same location when the operation changed the filtered value.
+#[derive(Debug)]
+pub(crate) enum Undo<D> {
+    /// Adds back a removed value, at its former position among the members
+    /// of its parent object.
+    Add {
+        path: Spath,
+        value: D,
+        position: Option<usize>,
+    },
+
+    /// Removes an added value.
+    Remove { path: Spath },
+
+    /// Restores a replaced value.
+    Replace { path: Spath, value: D },
+
+    /// Moves a value back from `to` to `from`, at its former position among
+    /// the members of its parent object, restoring the value it overwrote at
+    /// `to`.
+    Unmove {
+        from: Spath,
+        position: Option<usize>,
+        to: Spath,
+        overwritten: Option<D>,
+    },
+}
+
+/// Records how to undo the operations applied to a document, so a failed
+/// patch can be rolled back in time proportional to the size of its changes
+/// rather than the size of the document.
+#[derive(Debug)]
+pub(crate) struct Journal<D>(Vec<Undo<D>>);
+
+impl<D> Default for Journal<D> {
+    fn default() -> Self {
+        Journal(Vec::new())
+    }
+}
+
+impl<D: Document> Journal<D> {
+    pub(crate) fn record(&mut self, undo: Undo<D>) {
+        self.0.push(undo);
+    }
+
+    /// Reverts the recorded operations, last one first.
+    ///
+    /// `doc` must be in the state the recorded operations left it in.
+    pub(crate) fn rollback(self, doc: &mut D) {
+        for undo in self.0.into_iter().rev() {
+            undo.revert(doc)
+                .expect("undo journal must match the document it was recorded on");
+        }
+    }
+}
+
+impl<D: Document> Undo<D> {
+    fn revert(self, doc: &mut D) -> Result<(), crate::patch::PatchError> {
+        match self {
+            Undo::Add {
+                path,
+                value,
+                position,
+            } => put_back(doc, path, value, position),
+            Undo::Remove { path } => remove(doc, path),
+            Undo::Replace { path, value } => replace(doc, path, value),
+            Undo::Unmove {
+                from,
+                position,
+                to,
+                overwritten,
+            } => {
+                let moved = take(doc, &to)?;
+                if let Some(overwritten) = overwritten {
+                    put_back(doc, to, overwritten, moved.position)?;
+                }
+                put_back(doc, from, moved.value, position)
+            }
+        }
+    }
+}
@@ -1,6 +1,7 @@
 mod add;
 mod copy;
 mod error;
+mod journal;
 mod move_op;
 mod remove;
 mod replace;
@@ -8,37 +9,87 @@ mod test;
 
 pub use add::add;
 pub use copy::copy;
+pub use error::PatchError;
 pub use move_op::move_op;
 pub use remove::remove;
 pub use replace::replace;
-use serde_json::Value;
 pub use test::test;
 
-use crate::{diff::PatchOp, patch::error::PatchError};
+use crate::{diff::PatchOp, document::Document, patch::journal::Journal};
 
-pub fn apply(doc: &Value, patch: &[PatchOp]) -> Result<Value, PatchError> {
+/// Applies `patch` to a copy of `doc` and returns the patched document.
+///
+/// See [`apply_in_place`] for how failures are handled.
+pub fn apply<D: Document>(doc: &D, patch: &[PatchOp]) -> Result<D, PatchError> {
     let mut doc = doc.clone();
+    apply_in_place(&mut doc, patch)?;
+    Ok(doc)
+}
+
+/// Applies `patch` to `doc` atomically, without copying the document.
+///
+/// Every operation is attempted, and all failures are reported together in
+/// [`PatchError::MultipleErrors`]. If any operation fails, `doc` is restored
+/// to its original state. Each applied operation records how to undo itself,
+/// so the rollback costs as much as the changes made, not a copy of the whole
+/// document.
+///
+/// Operation values are converted with [`Document::from_json`]. A value the
+/// document format can't represent fails its operation with
+/// [`PatchError::DocumentError`].
+///
+/// ```rust
+/// use serde_json::json;
+/// use spatch::{diff::PatchOp, patch::apply_in_place};
+///
+/// let mut doc = json!({"a": 1});
+/// let patch = [
+///     PatchOp::replace("/a".try_into().unwrap(), json!(2)),
+///     PatchOp::test("/a".try_into().unwrap(), json!(1)),
+/// ];
+///
+/// assert!(apply_in_place(&mut doc, &patch).is_err());
+/// assert_eq!(doc, json!({"a": 1}));
+/// ```
+pub fn apply_in_place<D: Document>(doc: &mut D, patch: &[PatchOp]) -> Result<(), PatchError> {
+    let mut journal = Journal::default();
     let mut failures = Vec::new();
     for op in patch {
         let result = match op {
-            PatchOp::Add { path, value } => add(&mut doc, path.clone(), value.clone()),
-            PatchOp::Remove { path } => remove(&mut doc, path.clone()),
-            PatchOp::Replace { path, value } => replace(&mut doc, path.clone(), value.clone()),
-            PatchOp::Move { from, path } => move_op(&mut doc, from.clone(), path.clone()),
-            PatchOp::Copy { from, path } => copy(&mut doc, from.clone(), path.clone()),
-            PatchOp::Test { path, value } => test(&mut doc, path.clone(), value.clone()),
+            PatchOp::Add { path, value } => D::from_json(value.clone())
+                .map_err(PatchError::from)
+                .and_then(|value| add::add_with_undo(doc, path.clone(), value)),
+            PatchOp::Remove { path } => remove::remove_with_undo(doc, path.clone()),
+            PatchOp::Replace { path, value } => D::from_json(value.clone())
+                .map_err(PatchError::from)
+                .and_then(|value| replace::replace_with_undo(doc, path.clone(), value)),
+            PatchOp::Move { from, path } => {
+                move_op::move_with_undo(doc, from.clone(), path.clone())
+            }
+            PatchOp::Copy { from, path } => copy::copy_with_undo(doc, from.clone(), path.clone()),
+            PatchOp::Test { path, value } => {
+                // Tests don't change the document, there is nothing to undo.
+                let tested = D::from_json(value.clone())
+                    .map_err(PatchError::from)
+                    .and_then(|value| test(doc, path.clone(), value));
+                if let Err(e) = tested {
+                    failures.push(e);
+                }
+                continue;
+            }
         };
 
         match result {
-            Ok(_) => {}
+            Ok(undo) => journal.record(undo),
             Err(e) => failures.push(e),
         }
     }
 
     if !failures.is_empty() {
+        journal.rollback(doc);
         Err(PatchError::MultipleErrors(failures))
     } else {
-        Ok(doc)
+        Ok(())
     }
 }
 
@@ -97,6 +148,43 @@ mod tests {
         );
     }
 
+    #[test]
+    fn apply_in_place_should_roll_back_all_changes_when_an_operation_fails() {
+        let original = json!({
+            "users": [{"id": "u-1", "name": "Ada"}, {"id": "u-2", "name": "Grace"}],
+            "a": {"b": {"c": 1}},
+            "items": [1, 2, 3]
+        });
+        let mut doc = original.clone();
+        let patches = vec![
+            PatchOp::replace("/users/[id=u-1]/id".try_into().unwrap(), json!("u-3")),
+            PatchOp::remove("/users/[id=u-2]".try_into().unwrap()),
+            PatchOp::add("/items/0".try_into().unwrap(), json!(0)),
+            PatchOp::move_op("/a/b".try_into().unwrap(), "/a".try_into().unwrap()),
+            PatchOp::copy("/items".try_into().unwrap(), "/copy".try_into().unwrap()),
+            PatchOp::add("/a".try_into().unwrap(), json!("overwritten")),
+            PatchOp::test("/missing".try_into().unwrap(), json!(1)),
+        ];
+
+        assert!(let Err(PatchError::MultipleErrors(errors)) = apply_in_place(&mut doc, &patches));
+
+        check!(errors.len() == 1);
+        check!(doc == original);
+    }
+
+    #[test]
+    fn apply_in_place_should_keep_changes_when_all_operations_succeed() {
+        let mut doc = json!({"a": {"b": 1}, "items": [1]});
+        let patches = vec![
+            PatchOp::move_op("/a/b".try_into().unwrap(), "/items/0".try_into().unwrap()),
+            PatchOp::replace("/a".try_into().unwrap(), json!(2)),
+        ];
+
+        assert!(let Ok(()) = apply_in_place(&mut doc, &patches));
+
+        check!(doc == json!({"a": 2, "items": [1, 1]}));
+    }
+
     #[test]
     fn test_apply_against_the_jsonpatch_spec_tests() {
         let mut test_cases =
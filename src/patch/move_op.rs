@@ -1,7 +1,11 @@
-use serde_json::Value;
-
 use crate::{
-    patch::{add, error::PatchError, remove},
+    document::Document,
+    patch::{
+        add::try_add,
+        error::PatchError,
+        journal::Undo,
+        remove::{put_back, take},
+    },
     path::Spath,
     resolve::resolve_ref,
 };
@@ -25,25 +29,49 @@ use crate::{
 ///
 /// The "from" location MUST NOT be a proper prefix of the "path"
 /// location; i.e., a location cannot be moved into one of its children.
-pub fn move_op(doc: &mut Value, from: Spath, path: Spath) -> Result<(), PatchError> {
-    let value = resolve_ref(doc, &from)?.clone();
+pub fn move_op<D: Document>(doc: &mut D, from: Spath, path: Spath) -> Result<(), PatchError> {
+    move_with_undo(doc, from, path).map(|_undo| ())
+}
+
+/// Performs [`move_op`] and returns what undoes it.
+///
+/// The value is moved without cloning it. If it cannot be added at `path`, it
+/// is put back where it was taken from, so a failed move leaves the document
+/// unchanged.
+pub(crate) fn move_with_undo<D: Document>(
+    doc: &mut D,
+    from: Spath,
+    path: Spath,
+) -> Result<Undo<D>, PatchError> {
+    resolve_ref(doc, &from)?;
 
     if from.is_parent_of(&path) {
         return Err(PatchError::CannotMoveIntoChild);
     }
 
-    // NOTE: This is not ideal as it clones the entire document, but we need to do it in order to
-    // ensure the document is correct if any operation fails. It would be better to resolve the
-    // parent of the target path and then do the remove and add operations directly on the
-    // document. But for not, this will do.
-    let mut doc_cloned = doc.clone();
-
-    remove(&mut doc_cloned, from)?;
-    add(&mut doc_cloned, path, value)?;
-
-    *doc = doc_cloned;
-
-    Ok(())
+    let taken = take(doc, &from)?;
+
+    match try_add(doc, path, taken.value) {
+        Ok(Undo::Replace { path, value }) => Ok(Undo::Unmove {
+            from: taken.path,
+            position: taken.position,
+            to: path,
+            overwritten: Some(value),
+        }),
+        Ok(Undo::Remove { path }) => Ok(Undo::Unmove {
+            from: taken.path,
+            position: taken.position,
+            to: path,
+            overwritten: None,
+        }),
+        Ok(_) => unreachable!("an add is undone by a replace or a remove"),
+        Err((error, value)) => {
+            // Re-inserting at the concrete source restores the original position.
+            put_back(doc, taken.path, value, taken.position)
+                .expect("a taken value can be put back");
+            Err(error)
+        }
+    }
 }
 
 #[cfg(test)]
@@ -438,4 +466,18 @@ mod tests {
 
         check!(doc == json!({"a": 1, "b": 2}));
     }
+
+    #[test]
+    fn move_with_failing_add_should_leave_the_document_unchanged() {
+        let mut doc = json!({"a": [1, 2, 3], "b": []});
+
+        assert!(let
+            Err(PatchError::ArrayIndexOutOfBounds { .. }) = move_op(
+                &mut doc,
+                "/a/1".try_into().unwrap(),
+                "/b/5".try_into().unwrap()
+            )
+        );
+        check!(doc == json!({"a": [1, 2, 3], "b": []}));
+    }
 }
@@ -1,9 +1,8 @@
-use serde_json::Value;
-
 use crate::{
-    patch::error::PatchError,
+    document::Document,
+    patch::{add, error::PatchError, journal::Undo},
     path::{Segment, Spath},
-    resolve::{ResolveError, resolve_mut, value_type_desc},
+    resolve::{ResolveError, matches_filter, resolve_mut_concrete, value_type_desc},
 };
 
 /// The "remove" operation removes the value at the target location.
@@ -16,54 +15,107 @@ use crate::{
 ///
 /// If removing an element from an array, any elements above the
 /// specified index are shifted one position to the left.
-pub fn remove(doc: &mut Value, path: Spath) -> Result<(), PatchError> {
+pub fn remove<D: Document>(doc: &mut D, path: Spath) -> Result<(), PatchError> {
+    take(doc, &path).map(|_taken| ())
+}
+
+/// Performs [`remove`] and returns what undoes it.
+pub(crate) fn remove_with_undo<D: Document>(
+    doc: &mut D,
+    path: Spath,
+) -> Result<Undo<D>, PatchError> {
+    let taken = take(doc, &path)?;
+    Ok(Undo::Add {
+        path: taken.path,
+        value: taken.value,
+        position: taken.position,
+    })
+}
+
+/// A value removed from a document by [`take`].
+pub(crate) struct Taken<D> {
+    pub(crate) value: D,
+
+    /// The concrete path the value was removed from.
+    pub(crate) path: Spath,
+
+    /// The position of the value among the members of its parent, when the
+    /// parent is an object.
+    pub(crate) position: Option<usize>,
+}
+
+/// Removes the value at `path` and returns it, together with where it was
+/// removed from.
+pub(crate) fn take<D: Document>(doc: &mut D, path: &Spath) -> Result<Taken<D>, PatchError> {
     if path.is_empty() {
         return Err(PatchError::CannotRemoveRoot);
     }
 
-    let parent = path.parent().ok_or(PatchError::missing_parent(&path))?;
+    let parent = path.parent().ok_or(PatchError::missing_parent(path))?;
 
-    let target = resolve_mut(doc, &parent)?;
+    let (target, concrete_parent) = resolve_mut_concrete(doc, &parent)?;
 
-    match target {
-        Value::Object(map) => {
-            let field = path.field().ok_or(PatchError::missing_final_token(&path))?;
-            map.remove(&field)
-                .ok_or(PatchError::target_not_found(&path))?;
-        }
-        Value::Array(arr) => {
+    if target.is_object() {
+        let field = path.field().ok_or(PatchError::missing_final_token(path))?;
+        let (position, value) = target
+            .shift_remove_member(&field)
+            .ok_or(PatchError::target_not_found(path))?;
+        return Ok(Taken {
+            value,
+            path: concrete_parent.push(Segment::Field(field)),
+            position: Some(position),
+        });
+    }
+
+    match target.elements_mut() {
+        Some(arr) => {
             let segment = path.last_segment().ok_or(PatchError::CannotRemoveRoot)?;
             let index: usize = match segment {
                 Segment::Field(field) => field
                     .parse()
-                    .map_err(|_| PatchError::invalid_array_index_token(&path, field))?,
-                Segment::Filter(filters) => find_array_index(arr, filters)
+                    .map_err(|_| PatchError::invalid_array_index_token(path, field))?,
+                Segment::Filter(filters) => arr
+                    .iter()
+                    .position(|item| matches_filter(item, filters))
                     .ok_or(PatchError::ResolveError(ResolveError::NotFound))?,
             };
 
             if index >= arr.len() {
-                return Err(PatchError::index_out_of_bounds(&path, index, arr.len()));
+                return Err(PatchError::index_out_of_bounds(path, index, arr.len()));
             }
-            arr.remove(index);
-        }
-        val => {
-            return Err(PatchError::not_a_container(&parent, &value_type_desc(val)));
+            Ok(Taken {
+                value: arr.remove(index),
+                path: concrete_parent.push(Segment::Field(index.to_string())),
+                position: None,
+            })
         }
+        None => Err(PatchError::not_a_container(
+            &parent,
+            &value_type_desc(target),
+        )),
     }
-    Ok(())
 }
 
-fn find_array_index(arr: &[Value], filters: &[(String, String)]) -> Option<usize> {
-    for (index, item) in arr.iter().enumerate() {
-        if filters.iter().all(|(k, v)| {
-            item.get(k)
-                .and_then(|val| val.as_str())
-                .is_some_and(|val_str| val_str == v)
-        }) {
-            return Some(index);
-        }
+/// Puts a value taken from `path` back where it was. Object members are
+/// inserted at their former `position`, array elements at their index.
+pub(crate) fn put_back<D: Document>(
+    doc: &mut D,
+    path: Spath,
+    value: D,
+    position: Option<usize>,
+) -> Result<(), PatchError> {
+    let (Some(position), Some(parent), Some(field)) = (position, path.parent(), path.field())
+    else {
+        return add(doc, path, value);
+    };
+    let (target, _) = resolve_mut_concrete(doc, &parent)?;
+    match target.shift_insert_member(position, field, value) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(PatchError::not_a_container(
+            &parent,
+            &value_type_desc(target),
+        )),
     }
-    None
 }
 
 #[cfg(test)]
@@ -274,6 +326,15 @@ mod tests {
         );
     }
 
+    #[test]
+    fn remove_with_numeric_semantic_filter_should_succeed() {
+        let mut doc = json!({"ports": [{"port": 80}, {"port": 443}]});
+
+        assert!(let Ok(()) = remove(&mut doc, "/ports/[port=80]".try_into().unwrap()));
+
+        check!(doc == json!({"ports": [{"port": 443}]}));
+    }
+
     #[test]
     fn remove_nested_with_semantic_filter_should_succeed() {
         let mut doc = json!({
@@ -1,6 +1,9 @@
-use serde_json::Value;
-
-use crate::{patch::error::PatchError, path::Spath, resolve::resolve_mut};
+use crate::{
+    document::Document,
+    patch::{error::PatchError, journal::Undo},
+    path::Spath,
+    resolve::resolve_mut_concrete,
+};
 
 /// The "replace" operation replaces the value at the target location
 /// with a new value.  The operation object MUST contain a "value" member
@@ -15,15 +18,22 @@ use crate::{patch::error::PatchError, path::Spath, resolve::resolve_mut};
 /// This operation is functionally identical to a "remove" operation for
 /// a value, followed immediately by an "add" operation at the same
 /// location with the replacement value.
-pub fn replace(doc: &mut Value, path: Spath, value: Value) -> Result<(), PatchError> {
-    if path.is_empty() {
-        *doc = value;
-        return Ok(());
-    }
+pub fn replace<D: Document>(doc: &mut D, path: Spath, value: D) -> Result<(), PatchError> {
+    replace_with_undo(doc, path, value).map(|_undo| ())
+}
 
-    let target = resolve_mut(doc, &path)?;
-    *target = value;
-    Ok(())
+/// Performs [`replace`] and returns what undoes it.
+pub(crate) fn replace_with_undo<D: Document>(
+    doc: &mut D,
+    path: Spath,
+    value: D,
+) -> Result<Undo<D>, PatchError> {
+    let (target, concrete) = resolve_mut_concrete(doc, &path)?;
+    let old = std::mem::replace(target, value);
+    Ok(Undo::Replace {
+        path: concrete,
+        value: old,
+    })
 }
 
 #[cfg(test)]
@@ -1,6 +1,4 @@
-use serde_json::Value;
-
-use crate::{patch::error::PatchError, path::Spath, resolve::resolve_ref};
+use crate::{document::Document, patch::error::PatchError, path::Spath, resolve::resolve_ref};
 
 /// The "test" operation tests that a value at the target location is
 /// equal to a specified value.
@@ -43,7 +41,7 @@ use crate::{patch::error::PatchError, path::Spath, resolve::resolve_ref};
 /// For example:
 ///
 /// { "op": "test", "path": "/a/b/c", "value": "foo" }
-pub fn test(doc: &mut Value, path: Spath, value: Value) -> Result<(), PatchError> {
+pub fn test<D: Document>(doc: &mut D, path: Spath, value: D) -> Result<(), PatchError> {
     let value_at_path = resolve_ref(doc, &path)?;
 
     if value_at_path != &value {
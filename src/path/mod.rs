@@ -1,9 +1,11 @@
 mod error;
 mod parser;
+mod pattern;
 
 use std::fmt::Display;
 
 pub use crate::path::error::PathError;
+pub use pattern::{PathFilter, PathPattern};
 
 use parser::parse_path;
 
@@ -102,6 +104,13 @@ impl Spath {
         Spath { segments }
     }
 
+    /// Returns this path followed by the segments of `other`.
+    pub fn join(&self, other: &Spath) -> Self {
+        let mut segments = self.segments.clone();
+        segments.extend(other.segments.iter().cloned());
+        Spath { segments }
+    }
+
     /// Returns a parent path, or None if there is no parent.
     pub fn parent(&self) -> Option<Spath> {
         if self.segments.is_empty() {
@@ -192,6 +201,15 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn join_should_append_the_other_segments() {
+        let prefix = Spath::try_from("/0").unwrap();
+        let path = Spath::try_from("/items/[id=1]").unwrap();
+
+        check!(prefix.join(&path).to_string() == "/0/items/[id=1]");
+        check!(Spath::default().join(&path) == path);
+    }
+
     #[test]
     fn test_spath_try_from_str() {
         let path_str = "/field1/field2/[filterKey=filterValue]/field3";
@@ -0,0 +1,210 @@
+use std::fmt::Display;
+
+use super::{PathError, Segment, Spath};
+
+/// Segment of a [`PathPattern`] matching any single segment.
+const WILDCARD: &str = "*";
+
+/// A path with wildcards, selecting subtrees of a document.
+///
+/// Patterns are written like [`Spath`]s, where a `*` segment matches any
+/// member name, array index, or semantic filter. `/items/*/etag` matches
+/// `/items/0/etag` as well as `/items/[id=a-1]/etag`.
+///
+/// ```rust
+/// use spatch::path::{PathPattern, Spath};
+///
+/// let pattern = PathPattern::try_from("/items/*/etag").unwrap();
+/// let path = Spath::try_from("/items/[id=a-1]/etag").unwrap();
+///
+/// assert!(pattern.matches(&path));
+/// assert!(pattern.contains(&Spath::try_from("/items/3/etag/value").unwrap()));
+/// assert!(!pattern.contains(&Spath::try_from("/items/3/name").unwrap()));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PathPattern {
+    path: Spath,
+}
+
+impl PathPattern {
+    /// Returns `true` when `path` is one of the paths the pattern selects.
+    pub fn matches(&self, path: &Spath) -> bool {
+        self.path.segments.len() == path.segments.len() && self.matches_prefix_of(path)
+    }
+
+    /// Returns `true` when `path` is a selected path, or inside one.
+    pub fn contains(&self, path: &Spath) -> bool {
+        self.path.segments.len() <= path.segments.len() && self.matches_prefix_of(path)
+    }
+
+    /// Returns `true` when a selected path may be below `path`.
+    pub(crate) fn may_match_below(&self, path: &Spath) -> bool {
+        path.segments.len() < self.path.segments.len() && self.matches_prefix_of(path)
+    }
+
+    /// Compares the segments `path` and the pattern have in common.
+    fn matches_prefix_of(&self, path: &Spath) -> bool {
+        self.path
+            .segments
+            .iter()
+            .zip(&path.segments)
+            .all(|(pattern, segment)| segment_matches(pattern, segment))
+    }
+}
+
+fn segment_matches(pattern: &Segment, segment: &Segment) -> bool {
+    match pattern {
+        Segment::Field(field) if field == WILDCARD => true,
+        pattern => pattern == segment,
+    }
+}
+
+impl TryFrom<&str> for PathPattern {
+    type Error = PathError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Ok(PathPattern {
+            path: Spath::try_from(value)?,
+        })
+    }
+}
+
+impl TryFrom<String> for PathPattern {
+    type Error = PathError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        PathPattern::try_from(value.as_str())
+    }
+}
+
+impl Display for PathPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.path.fmt(f)
+    }
+}
+
+/// Selects the parts of a document a diff or patch is about.
+///
+/// Changes inside a subtree matched by an `ignore` pattern are dropped. When
+/// `only` patterns are given, changes outside of the subtrees they match are
+/// dropped too.
+///
+/// ```rust
+/// use spatch::path::{PathFilter, PathPattern, Spath};
+///
+/// let ignore = [PathPattern::try_from("/meta/updatedAt").unwrap()];
+/// let only = [PathPattern::try_from("/meta").unwrap()];
+/// let filter = PathFilter::new().ignore(&ignore).only(&only);
+///
+/// assert!(filter.includes(&Spath::try_from("/meta/name").unwrap()));
+/// assert!(!filter.includes(&Spath::try_from("/meta/updatedAt").unwrap()));
+/// assert!(!filter.includes(&Spath::try_from("/spec").unwrap()));
+/// ```
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PathFilter<'a> {
+    /// Subtrees whose changes are dropped.
+    pub ignore: &'a [PathPattern],
+
+    /// Subtrees whose changes are kept, or every subtree when empty.
+    pub only: &'a [PathPattern],
+}
+
+impl<'a> PathFilter<'a> {
+    /// Creates a filter that keeps every change.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drops the changes inside subtrees matched by `patterns`.
+    pub fn ignore(mut self, patterns: &'a [PathPattern]) -> Self {
+        self.ignore = patterns;
+        self
+    }
+
+    /// Keeps only the changes inside subtrees matched by `patterns`.
+    pub fn only(mut self, patterns: &'a [PathPattern]) -> Self {
+        self.only = patterns;
+        self
+    }
+
+    /// Returns `true` when a change at `path` is kept.
+    pub fn includes(&self, path: &Spath) -> bool {
+        !self.ignore.iter().any(|pattern| pattern.contains(path))
+            && (self.only.is_empty() || self.only.iter().any(|pattern| pattern.contains(path)))
+    }
+
+    /// Returns `true` when a change at `path`, or below it, may be kept.
+    pub(crate) fn includes_below(&self, path: &Spath) -> bool {
+        !self.ignore.iter().any(|pattern| pattern.contains(path))
+            && (self.only.is_empty()
+                || self
+                    .only
+                    .iter()
+                    .any(|pattern| pattern.contains(path) || pattern.may_match_below(path)))
+    }
+
+    /// Returns `true` when the filter keeps every change.
+    pub fn is_empty(&self) -> bool {
+        self.ignore.is_empty() && self.only.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert2::check;
+
+    use super::*;
+
+    fn path(raw: &str) -> Spath {
+        raw.try_into().unwrap()
+    }
+
+    fn pattern(raw: &str) -> PathPattern {
+        raw.try_into().unwrap()
+    }
+
+    #[test]
+    fn wildcard_should_match_fields_indexes_and_filters() {
+        let pattern = pattern("/items/*/etag");
+
+        check!(pattern.matches(&path("/items/0/etag")));
+        check!(pattern.matches(&path("/items/[id=a-1]/etag")));
+        check!(pattern.matches(&path("/items/-/etag")));
+        check!(!pattern.matches(&path("/items/0/name")));
+        check!(!pattern.matches(&path("/items/0")));
+        check!(!pattern.matches(&path("/items/0/etag/value")));
+    }
+
+    #[test]
+    fn contains_should_match_paths_inside_selected_subtrees() {
+        let pattern = pattern("/meta/*");
+
+        check!(pattern.contains(&path("/meta/updatedAt")));
+        check!(pattern.contains(&path("/meta/labels/app")));
+        check!(!pattern.contains(&path("/meta")));
+        check!(!pattern.contains(&path("/spec/meta/updatedAt")));
+    }
+
+    #[test]
+    fn filter_should_keep_ancestors_of_only_patterns_below() {
+        let only = [pattern("/items/*/name")];
+        let filter = PathFilter::new().only(&only);
+
+        check!(filter.includes_below(&path("")));
+        check!(filter.includes_below(&path("/items/[id=1]")));
+        check!(!filter.includes(&path("/items/[id=1]")));
+        check!(filter.includes(&path("/items/[id=1]/name")));
+        check!(!filter.includes_below(&path("/other")));
+    }
+
+    #[test]
+    fn filter_should_prefer_ignore_over_only() {
+        let only = [pattern("/meta")];
+        let ignore = [pattern("/meta/updatedAt")];
+        let filter = PathFilter::new().only(&only).ignore(&ignore);
+
+        check!(filter.includes(&path("/meta/name")));
+        check!(!filter.includes(&path("/meta/updatedAt/seconds")));
+        check!(!filter.includes_below(&path("/meta/updatedAt")));
+    }
+}
@@ -1,7 +1,10 @@
 mod ext;
 
 pub use crate::diff::SchemaResolver;
-use crate::path::{PathError, Spath};
+use crate::{
+    document::Document,
+    path::{PathError, Spath},
+};
 pub use ext::SerdeValueExt;
 use std::{ops::Deref, str::FromStr};
 
@@ -32,7 +35,8 @@ impl ResolveError {
 }
 
 pub trait ValueAccess<'a> {
-    type Out: Deref<Target = serde_json::Value> + 'a;
+    type Doc: Document + 'a;
+    type Out: Deref<Target = Self::Doc> + 'a;
     type ArrayIter: Iterator<Item = Self::Out> + 'a;
 
     fn is_object(&self) -> bool;
@@ -44,66 +48,104 @@ pub trait ValueAccess<'a> {
     fn array_iter(self) -> Option<Self::ArrayIter>;
 }
 
-impl<'a> ValueAccess<'a> for &'a serde_json::Value {
-    type Out = &'a serde_json::Value;
-    type ArrayIter = std::slice::Iter<'a, serde_json::Value>;
+impl<'a, D: Document + 'a> ValueAccess<'a> for &'a D {
+    type Doc = D;
+    type Out = &'a D;
+    type ArrayIter = std::slice::Iter<'a, D>;
 
     fn is_object(&self) -> bool {
-        serde_json::Value::is_object(self)
+        D::is_object(self)
     }
     fn is_array(&self) -> bool {
-        serde_json::Value::is_array(self)
+        D::is_array(self)
     }
     fn get_key(self, key: &str) -> Option<Self::Out> {
-        self.get(key)
+        self.member(key)
     }
     fn get_index(self, index: usize) -> Option<Self::Out> {
-        self.get(index)
+        self.elements()?.get(index)
     }
     fn array_iter(self) -> Option<Self::ArrayIter> {
-        self.as_array().map(|v| v.iter())
+        self.elements().map(|v| v.iter())
     }
 }
 
-impl<'a> ValueAccess<'a> for &'a mut serde_json::Value {
-    type Out = &'a mut serde_json::Value;
-    type ArrayIter = std::slice::IterMut<'a, serde_json::Value>;
+impl<'a, D: Document + 'a> ValueAccess<'a> for &'a mut D {
+    type Doc = D;
+    type Out = &'a mut D;
+    type ArrayIter = std::slice::IterMut<'a, D>;
 
     fn is_object(&self) -> bool {
-        serde_json::Value::is_object(self)
+        D::is_object(self)
     }
     fn is_array(&self) -> bool {
-        serde_json::Value::is_array(self)
+        D::is_array(self)
     }
     fn get_key(self, key: &str) -> Option<Self::Out> {
-        self.get_mut(key)
+        self.member_mut(key)
     }
     fn get_index(self, index: usize) -> Option<Self::Out> {
-        self.get_mut(index)
+        self.elements_mut()?.get_mut(index)
     }
     fn array_iter(self) -> Option<Self::ArrayIter> {
-        self.as_array_mut().map(|v| v.iter_mut())
+        self.elements_mut().map(|v| v.iter_mut())
     }
 }
 
-pub fn resolve_ref<'a>(
-    doc: &'a serde_json::Value,
-    path: &Spath,
-) -> Result<&'a serde_json::Value, ResolveError> {
+pub fn resolve_ref<'a, D: Document>(doc: &'a D, path: &Spath) -> Result<&'a D, ResolveError> {
     resolve_inner(doc, path)
 }
 
-pub fn resolve_mut<'a>(
-    doc: &'a mut serde_json::Value,
+pub fn resolve_mut<'a, D: Document>(
+    doc: &'a mut D,
     path: &'a Spath,
-) -> Result<&'a mut serde_json::Value, ResolveError> {
+) -> Result<&'a mut D, ResolveError> {
     resolve_inner(doc, path)
 }
 
+/// Resolves `path` like [`resolve_mut`], and also returns the concrete path of
+/// the target, with every filter segment replaced by the index of the array
+/// element it matched.
+///
+/// Concrete paths keep addressing the same location after the filtered value
+/// changes, which is what undoing a mutation needs.
+pub(crate) fn resolve_mut_concrete<'a, D: Document>(
+    doc: &'a mut D,
+    path: &Spath,
+) -> Result<(&'a mut D, Spath), ResolveError> {
+    let mut current = doc;
+    let mut concrete = Spath { segments: vec![] };
+    for segment in path {
+        match segment {
+            crate::path::Segment::Field(field) => {
+                current = resolve_field(current, field, &concrete)?;
+                concrete.segments.push(segment.clone());
+            }
+            crate::path::Segment::Filter(conditions) => {
+                let type_name = value_type_desc(current);
+                let arr = current
+                    .elements_mut()
+                    .ok_or(ResolveError::type_mismatch("array", &type_name, &concrete))?;
+                let index = arr
+                    .iter()
+                    .position(|item| matches_filter(item, conditions))
+                    .ok_or(ResolveError::NotFound)?;
+
+                current = &mut arr[index];
+                concrete
+                    .segments
+                    .push(crate::path::Segment::Field(index.to_string()));
+            }
+        }
+    }
+
+    Ok((current, concrete))
+}
+
 fn resolve_inner<'a, 'b, A>(doc: A, path: &'b Spath) -> Result<A::Out, ResolveError>
 where
     A: ValueAccess<'a, Out = A>, // output type is the same as input type
-    A: std::ops::Deref<Target = serde_json::Value>,
+    A: std::ops::Deref<Target = A::Doc>,
 {
     let mut current: A::Out = doc;
     let mut current_path = Spath { segments: vec![] };
@@ -124,9 +166,9 @@ where
 fn resolve_field<'a, A>(doc: A, field: &str, path: &Spath) -> Result<A::Out, ResolveError>
 where
     A: ValueAccess<'a>,
-    A: Deref<Target = serde_json::Value>,
+    A: Deref<Target = A::Doc>,
 {
-    let type_name = value_type_desc(&doc);
+    let type_name = value_type_desc(&*doc);
     if !doc.is_object() && !doc.is_array() {
         return Err(ResolveError::type_mismatch(
             "object or array",
@@ -157,9 +199,9 @@ fn resolve_filter<'a, A>(
 ) -> Result<A::Out, ResolveError>
 where
     A: ValueAccess<'a>,
-    A: Deref<Target = serde_json::Value>,
+    A: Deref<Target = A::Doc>,
 {
-    let type_name = value_type_desc(&doc);
+    let type_name = value_type_desc(&*doc);
     let arr = doc
         .array_iter()
         .ok_or(ResolveError::type_mismatch("array", &type_name, path))?;
@@ -167,11 +209,7 @@ where
     arr.into_iter()
         .find_map(|item| {
             // Find an item that matches all conditions
-            let matches = conditions.iter().all(|(k, v)| {
-                item.deref()
-                    .get(k) // use shared view for matching
-                    .is_some_and(|val| value_matches_filter(val, v))
-            });
+            let matches = matches_filter(item.deref(), conditions);
 
             // If matches, return ref to item
             matches.then_some(item)
@@ -179,11 +217,16 @@ where
         .ok_or(ResolveError::NotFound)
 }
 
-fn value_matches_filter<V>(val: V, filter_value: &str) -> bool
-where
-    V: Deref<Target = serde_json::Value>,
-{
-    match val.deref() {
+/// Checks whether an array element satisfies all filter `conditions`.
+pub(crate) fn matches_filter<D: Document>(item: &D, conditions: &[(String, String)]) -> bool {
+    conditions.iter().all(|(k, v)| {
+        item.member(k)
+            .is_some_and(|val| value_matches_filter(&val.to_json(), v))
+    })
+}
+
+fn value_matches_filter(val: &serde_json::Value, filter_value: &str) -> bool {
+    match val {
         serde_json::Value::String(s) => s == filter_value,
         serde_json::Value::Number(n) => value_match_number(n, filter_value),
         serde_json::Value::Bool(b) => value_match_bool(b, filter_value),
@@ -207,8 +250,15 @@ fn value_match_bool(value: &bool, filter_value: &str) -> bool {
     )
 }
 
-pub fn value_type_desc(val: &serde_json::Value) -> String {
-    match val {
+pub fn value_type_desc<D: Document>(val: &D) -> String {
+    if val.is_array() {
+        return "array".to_string();
+    }
+    if val.is_object() {
+        return "object".to_string();
+    }
+
+    match &*val.to_json() {
         serde_json::Value::Null => "null".to_string(),
         serde_json::Value::Bool(b) => format!("boolean({b})"),
         serde_json::Value::Number(n) => format!("number({n})"),
@@ -616,4 +666,15 @@ mod tests {
         check!(result == &expected);
         check!(doc["items"][0] == expected);
     }
+
+    #[test]
+    fn resolve_mut_concrete_should_replace_filters_with_indexes() {
+        let mut doc = json!({"users": [{"id": "u-1"}, {"id": 2, "tags": ["x"]}]});
+        let path: Spath = "/users/[id=2]/tags/0".try_into().unwrap();
+
+        let (value, concrete) = resolve_mut_concrete(&mut doc, &path).unwrap();
+
+        check!(value == &mut json!("x"));
+        check!(concrete.to_string() == "/users/1/tags/0");
+    }
 }
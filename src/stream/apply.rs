@@ -0,0 +1,889 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufWriter, Read, Write};
+
+use serde_json::Value;
+
+use crate::{
+    diff::PatchOp,
+    patch::{self, PatchError},
+    path::{Segment, Spath},
+    resolve::{ResolveError, matches_filter},
+    stream::{
+        StreamError,
+        reader::{JsonReader, Start},
+    },
+};
+
+/// Applies `patch` to the JSON document read from `input` and writes the
+/// patched document to `output` as compact JSON.
+///
+/// The document is never loaded as a whole. Members and elements the patch
+/// doesn't touch are copied token by token, and operations are resolved as
+/// their paths stream by:
+///
+/// - object members are matched by key. Members that don't exist yet are
+///   appended when the object ends;
+/// - array indexes are mapped back to the elements of the input, so inserts
+///   and removals shift later indexes just like applying the operations one
+///   after another would;
+/// - filter segments are matched against each element of the array, which is
+///   read into memory one element at a time until every filter has matched.
+///   Each filter is resolved once, against the first element of the input that
+///   matches it.
+///
+/// Only the targets of operations are materialized. A target that can't be
+/// located while streaming, like an array mixing index and filter paths, is
+/// read into memory and patched there.
+///
+/// `move` and `copy` operations need values from elsewhere in the document
+/// and are rejected before anything is written. When an operation fails, the
+/// rest of the document is still written and all failures are returned in
+/// [`PatchError::MultipleErrors`]. The output is incomplete in that case and
+/// should be discarded.
+///
+/// ```rust
+/// use serde_json::json;
+/// use spatch::{diff::PatchOp, stream::apply_reader};
+///
+/// let doc = r#"{"users": [{"id": "a", "name": "Ada"}, {"id": "b", "name": "Bob"}]}"#;
+/// let patch = [PatchOp::replace(
+///     "/users/[id=b]/name".try_into().unwrap(),
+///     json!("Bobby"),
+/// )];
+///
+/// let mut output = Vec::new();
+/// apply_reader(doc.as_bytes(), &mut output, &patch).unwrap();
+///
+/// assert_eq!(
+///     serde_json::from_slice::<serde_json::Value>(&output).unwrap(),
+///     json!({"users": [{"id": "a", "name": "Ada"}, {"id": "b", "name": "Bobby"}]})
+/// );
+/// ```
+pub fn apply_reader<R: Read, W: Write>(
+    input: R,
+    output: W,
+    patch: &[PatchOp],
+) -> Result<(), StreamError> {
+    if let Some(op) = patch
+        .iter()
+        .find(|op| matches!(op, PatchOp::Move { .. } | PatchOp::Copy { .. }))
+    {
+        return Err(StreamError::UnsupportedOp {
+            op: op.kind().as_str(),
+        });
+    }
+
+    let ops = patch
+        .iter()
+        .map(|op| LocalOp {
+            op,
+            rest: &op.path().segments,
+        })
+        .collect();
+
+    let mut applier = StreamApplier {
+        reader: JsonReader::new(input, "document"),
+        output: BufWriter::new(output),
+        failures: Vec::new(),
+    };
+    let start = applier.reader.next_value()?;
+    applier.write_item(None, Some(start), ops, &Spath::default(), &mut true)?;
+    applier.reader.finish()?;
+    applier.output.flush()?;
+
+    if applier.failures.is_empty() {
+        Ok(())
+    } else {
+        Err(PatchError::MultipleErrors(applier.failures).into())
+    }
+}
+
+/// An operation seen from the value it is being applied to.
+#[derive(Debug, Clone, Copy)]
+struct LocalOp<'p> {
+    op: &'p PatchOp,
+    /// Segments of the operation path below the value. Empty when the
+    /// operation targets the value itself.
+    rest: &'p [Segment],
+}
+
+impl<'p> LocalOp<'p> {
+    fn targets_self(&self) -> bool {
+        self.rest.is_empty()
+    }
+
+    /// The same operation, seen from the child its next segment selects.
+    fn descend(self) -> LocalOp<'p> {
+        LocalOp {
+            op: self.op,
+            rest: &self.rest[1..],
+        }
+    }
+}
+
+/// How the operations on an array are resolved against its elements.
+enum ArrayPlan<'p> {
+    Indexed(IndexPlan<'p>),
+    Filtered(FilterPlan<'p>),
+    /// Read the array into memory and apply the operations there.
+    Materialize,
+}
+
+/// A position of the patched array, as far as the operations reach into it.
+enum Slot {
+    /// An element of the input, by its index in the input.
+    Original(usize),
+    /// A value added by the patch.
+    Inserted(Value),
+}
+
+/// Array operations by index, replayed against a model of the array that
+/// only knows the positions the operations reach.
+#[derive(Default)]
+struct IndexPlan<'p> {
+    /// The first positions of the patched array.
+    slots: Vec<Slot>,
+    /// Index in the input of the first element after `slots`. Input elements
+    /// before it that no slot refers to were removed.
+    next_original: usize,
+    /// Operations on elements of the input, by their index in the input.
+    elements: BTreeMap<usize, Vec<LocalOp<'p>>>,
+    /// Values appended with `-`.
+    tail: Vec<Value>,
+    /// Operations that need the input to have a number of elements, with the
+    /// index they used and how many positions `slots` was ahead of the input
+    /// at the time.
+    bounds: Vec<(usize, &'p PatchOp, usize, isize)>,
+    failures: Vec<PatchError>,
+}
+
+impl IndexPlan<'_> {
+    /// Pulls elements of the input into `slots` until it has `len` positions.
+    fn extend(&mut self, len: usize) {
+        while self.slots.len() < len {
+            self.slots.push(Slot::Original(self.next_original));
+            self.next_original += 1;
+        }
+    }
+}
+
+/// Array operations by filter, applied to the first element matching each
+/// filter.
+#[derive(Default)]
+struct FilterPlan<'p> {
+    groups: Vec<FilterGroup<'p>>,
+    /// Values appended with `-`.
+    tail: Vec<Value>,
+}
+
+struct FilterGroup<'p> {
+    segment: &'p Segment,
+    conditions: &'p [(String, String)],
+    ops: Vec<LocalOp<'p>>,
+    matched: bool,
+}
+
+struct StreamApplier<R, W: Write> {
+    reader: JsonReader<R>,
+    output: BufWriter<W>,
+    failures: Vec<PatchError>,
+}
+
+impl<'p, R: Read, W: Write> StreamApplier<R, W> {
+    /// Writes a member (with `key`) or an element (without) whose input value
+    /// starts with `start`, or which is missing from the input. Nothing is
+    /// written when the operations leave no value.
+    fn write_item(
+        &mut self,
+        key: Option<&str>,
+        start: Option<Start>,
+        ops: Vec<LocalOp<'p>>,
+        location: &Spath,
+        first: &mut bool,
+    ) -> Result<(), StreamError> {
+        match start {
+            Some(start) if !ops.iter().any(LocalOp::targets_self) => {
+                self.write_separator(key, first)?;
+                self.write_value(start, ops, location)
+            }
+            start => {
+                if let Some(value) = self.materialize(start, &ops, location)? {
+                    self.write_separator(key, first)?;
+                    serde_json::to_writer(&mut self.output, &value).map_err(io::Error::from)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Writes the value starting with `start`, applying `ops`, none of which
+    /// targets the value itself.
+    fn write_value(
+        &mut self,
+        start: Start,
+        ops: Vec<LocalOp<'p>>,
+        location: &Spath,
+    ) -> Result<(), StreamError> {
+        if ops.is_empty() {
+            return self.copy_value(start);
+        }
+
+        match start {
+            Start::Object => self.write_object(ops, location),
+            Start::Array => self.write_array(ops, location),
+            Start::Scalar(value) => self.write_patched(value, &ops, location),
+        }
+    }
+
+    fn write_object(&mut self, ops: Vec<LocalOp<'p>>, location: &Spath) -> Result<(), StreamError> {
+        let mut members: Vec<(&'p str, Vec<LocalOp<'p>>)> = Vec::new();
+        let mut by_key = HashMap::new();
+        for op in ops.iter().copied() {
+            let Segment::Field(key) = &op.rest[0] else {
+                // Filters don't select object members, let the in-memory
+                // patch report it.
+                let object = self.reader.read_value(Start::Object)?;
+                return self.write_patched(object, &ops, location);
+            };
+            let index = *by_key.entry(key.as_str()).or_insert_with(|| {
+                members.push((key.as_str(), Vec::new()));
+                members.len() - 1
+            });
+            members[index].1.push(op.descend());
+        }
+
+        self.output.write_all(b"{")?;
+        let mut first = true;
+        while let Some(key) = self.reader.next_key()? {
+            let start = self.reader.next_value()?;
+            match by_key.get(key.as_str()) {
+                Some(&index) => {
+                    let ops = std::mem::take(&mut members[index].1);
+                    let member = location.push(Segment::Field(key.clone()));
+                    self.write_item(Some(&key), Some(start), ops, &member, &mut first)?;
+                }
+                None => {
+                    self.write_separator(Some(&key), &mut first)?;
+                    self.copy_value(start)?;
+                }
+            }
+        }
+
+        for (key, ops) in members {
+            // Members seen in the input have had their operations taken.
+            if !ops.is_empty() {
+                let member = location.push(Segment::Field(key.to_owned()));
+                self.write_item(Some(key), None, ops, &member, &mut first)?;
+            }
+        }
+        self.output.write_all(b"}")?;
+        Ok(())
+    }
+
+    fn write_array(&mut self, ops: Vec<LocalOp<'p>>, location: &Spath) -> Result<(), StreamError> {
+        match plan_array(&ops, location) {
+            ArrayPlan::Indexed(plan) => self.write_indexed(plan, location),
+            ArrayPlan::Filtered(plan) => self.write_filtered(plan, location),
+            ArrayPlan::Materialize => {
+                let array = self.reader.read_value(Start::Array)?;
+                self.write_patched(array, &ops, location)
+            }
+        }
+    }
+
+    fn write_indexed(&mut self, plan: IndexPlan<'p>, location: &Spath) -> Result<(), StreamError> {
+        let IndexPlan {
+            slots,
+            next_original,
+            mut elements,
+            tail,
+            bounds,
+            failures,
+        } = plan;
+        self.failures.extend(failures);
+
+        self.output.write_all(b"[")?;
+        let mut first = true;
+        // Number of input elements read so far, and whether that's all of them.
+        let mut read = 0;
+        let mut ended = false;
+        for slot in slots {
+            match slot {
+                Slot::Inserted(value) => {
+                    self.write_separator(None, &mut first)?;
+                    serde_json::to_writer(&mut self.output, &value).map_err(io::Error::from)?;
+                }
+                Slot::Original(index) => {
+                    while read < index && !ended {
+                        if self.drop_element(&mut elements, read, location)? {
+                            read += 1;
+                        } else {
+                            ended = true;
+                        }
+                    }
+                    let Some(start) = self.next_element(&mut ended)? else {
+                        continue;
+                    };
+                    read += 1;
+                    let ops = elements.remove(&index).unwrap_or_default();
+                    let element = location.push(Segment::Field(index.to_string()));
+                    self.write_item(None, Some(start), ops, &element, &mut first)?;
+                }
+            }
+        }
+
+        while read < next_original && !ended {
+            if self.drop_element(&mut elements, read, location)? {
+                read += 1;
+            } else {
+                ended = true;
+            }
+        }
+        while let Some(start) = self.next_element(&mut ended)? {
+            read += 1;
+            self.write_separator(None, &mut first)?;
+            self.copy_value(start)?;
+        }
+
+        for value in tail {
+            self.write_separator(None, &mut first)?;
+            serde_json::to_writer(&mut self.output, &value).map_err(io::Error::from)?;
+        }
+        self.output.write_all(b"]")?;
+
+        for (needed, op, index, ahead) in bounds {
+            if needed > read {
+                let len = (read as isize + ahead).max(0) as usize;
+                self.failures
+                    .push(PatchError::index_out_of_bounds(op.path(), index, len));
+            }
+        }
+        Ok(())
+    }
+
+    fn write_filtered(
+        &mut self,
+        mut plan: FilterPlan<'p>,
+        location: &Spath,
+    ) -> Result<(), StreamError> {
+        self.output.write_all(b"[")?;
+        let mut first = true;
+        while let Some(start) = self.reader.next_element()? {
+            if plan.groups.iter().all(|group| group.matched) {
+                self.write_separator(None, &mut first)?;
+                self.copy_value(start)?;
+                continue;
+            }
+
+            let mut element = Some(self.reader.read_value(start)?);
+            for group in plan.groups.iter_mut().filter(|group| !group.matched) {
+                let Some(value) = &element else {
+                    break;
+                };
+                if !matches_filter(value, group.conditions) {
+                    continue;
+                }
+
+                group.matched = true;
+                let matched = location.push(group.segment.clone());
+                for op in &group.ops {
+                    if let Err(error) = apply_local(&mut element, op, &matched) {
+                        self.failures.push(error);
+                    }
+                }
+            }
+
+            if let Some(value) = element {
+                self.write_separator(None, &mut first)?;
+                serde_json::to_writer(&mut self.output, &value).map_err(io::Error::from)?;
+            }
+        }
+
+        for value in plan.tail {
+            self.write_separator(None, &mut first)?;
+            serde_json::to_writer(&mut self.output, &value).map_err(io::Error::from)?;
+        }
+        self.output.write_all(b"]")?;
+
+        for group in plan.groups.into_iter().filter(|group| !group.matched) {
+            self.failures
+                .extend(group.ops.iter().map(|_| ResolveError::NotFound.into()));
+        }
+        Ok(())
+    }
+
+    /// Reads an input element that the patch removed. Returns `false` when
+    /// the array has no more elements.
+    fn drop_element(
+        &mut self,
+        elements: &mut BTreeMap<usize, Vec<LocalOp<'p>>>,
+        index: usize,
+        location: &Spath,
+    ) -> Result<bool, StreamError> {
+        let Some(start) = self.reader.next_element()? else {
+            return Ok(false);
+        };
+        match elements.remove(&index) {
+            // Operations before the removal, such as tests, still apply.
+            Some(ops) => {
+                let element = location.push(Segment::Field(index.to_string()));
+                self.materialize(Some(start), &ops, &element)?;
+            }
+            None => self.reader.skip_value(start)?,
+        }
+        Ok(true)
+    }
+
+    fn next_element(&mut self, ended: &mut bool) -> Result<Option<Start>, StreamError> {
+        if *ended {
+            return Ok(None);
+        }
+        let start = self.reader.next_element()?;
+        *ended = start.is_none();
+        Ok(start)
+    }
+
+    /// Reads the value starting with `start`, if any, and applies `ops` to it
+    /// in memory.
+    fn materialize(
+        &mut self,
+        start: Option<Start>,
+        ops: &[LocalOp<'p>],
+        location: &Spath,
+    ) -> Result<Option<Value>, StreamError> {
+        let mut value = match start {
+            // The input value is overwritten before anything reads it.
+            Some(start)
+                if ops.first().is_some_and(|op| {
+                    op.targets_self()
+                        && matches!(op.op, PatchOp::Add { .. } | PatchOp::Replace { .. })
+                }) =>
+            {
+                self.reader.skip_value(start)?;
+                Some(Value::Null)
+            }
+            Some(start) => Some(self.reader.read_value(start)?),
+            None => None,
+        };
+
+        for op in ops {
+            if let Err(error) = apply_local(&mut value, op, location) {
+                self.failures.push(error);
+            }
+        }
+        Ok(value)
+    }
+
+    /// Applies `ops`, none of which removes the value, to `value` in memory and
+    /// writes the result.
+    fn write_patched(
+        &mut self,
+        value: Value,
+        ops: &[LocalOp<'p>],
+        location: &Spath,
+    ) -> Result<(), StreamError> {
+        let mut value = Some(value);
+        for op in ops {
+            if let Err(error) = apply_local(&mut value, op, location) {
+                self.failures.push(error);
+            }
+        }
+        let value = value.unwrap_or_default();
+        serde_json::to_writer(&mut self.output, &value).map_err(io::Error::from)?;
+        Ok(())
+    }
+
+    /// Copies the value starting with `start` from the input to the output.
+    fn copy_value(&mut self, start: Start) -> Result<(), StreamError> {
+        match start {
+            Start::Scalar(value) => {
+                serde_json::to_writer(&mut self.output, &value).map_err(io::Error::from)?;
+            }
+            Start::Object => {
+                self.output.write_all(b"{")?;
+                let mut first = true;
+                while let Some(key) = self.reader.next_key()? {
+                    self.write_separator(Some(&key), &mut first)?;
+                    let start = self.reader.next_value()?;
+                    self.copy_value(start)?;
+                }
+                self.output.write_all(b"}")?;
+            }
+            Start::Array => {
+                self.output.write_all(b"[")?;
+                let mut first = true;
+                while let Some(start) = self.reader.next_element()? {
+                    self.write_separator(None, &mut first)?;
+                    self.copy_value(start)?;
+                }
+                self.output.write_all(b"]")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the comma before every item but the first, and the key of
+    /// object members.
+    fn write_separator(&mut self, key: Option<&str>, first: &mut bool) -> Result<(), StreamError> {
+        if !std::mem::take(first) {
+            self.output.write_all(b",")?;
+        }
+        if let Some(key) = key {
+            serde_json::to_writer(&mut self.output, key).map_err(io::Error::from)?;
+            self.output.write_all(b":")?;
+        }
+        Ok(())
+    }
+}
+
+/// Decides how the operations on an array are resolved. Operations on
+/// inserted values are applied while planning.
+fn plan_array<'p>(ops: &[LocalOp<'p>], location: &Spath) -> ArrayPlan<'p> {
+    let mut indexed = IndexPlan::default();
+    let mut filtered = FilterPlan::default();
+    let (mut has_index, mut has_filter) = (false, false);
+
+    for &op in ops {
+        let targets_element = op.rest.len() == 1;
+        match (&op.rest[0], op.op) {
+            (Segment::Field(token), PatchOp::Add { value, .. })
+                if token == "-" && targets_element =>
+            {
+                indexed.tail.push(value.clone());
+                filtered.tail.push(value.clone());
+            }
+            (segment @ Segment::Filter(conditions), _) => {
+                // Adding at a filter is an error, and filters after an append
+                // may match the appended values.
+                let after_append = !filtered.tail.is_empty();
+                if after_append || (targets_element && matches!(op.op, PatchOp::Add { .. })) {
+                    return ArrayPlan::Materialize;
+                }
+                has_filter = true;
+                let group = match filtered
+                    .groups
+                    .iter_mut()
+                    .find(|group| group.segment == segment)
+                {
+                    Some(group) => group,
+                    None => {
+                        filtered.groups.push(FilterGroup {
+                            segment,
+                            conditions,
+                            ops: Vec::new(),
+                            matched: false,
+                        });
+                        filtered.groups.last_mut().expect("a group was just added")
+                    }
+                };
+                group.ops.push(op.descend());
+            }
+            (Segment::Field(token), _) => {
+                let Ok(index) = token.parse::<usize>() else {
+                    return ArrayPlan::Materialize;
+                };
+                // Once values are appended, positions past the known ones
+                // depend on the length of the input.
+                if !indexed.tail.is_empty() && index >= indexed.slots.len() {
+                    return ArrayPlan::Materialize;
+                }
+                has_index = true;
+                plan_index(&mut indexed, op, index, location);
+            }
+        }
+
+        if has_index && has_filter {
+            return ArrayPlan::Materialize;
+        }
+    }
+
+    if has_filter {
+        ArrayPlan::Filtered(filtered)
+    } else {
+        ArrayPlan::Indexed(indexed)
+    }
+}
+
+fn plan_index<'p>(plan: &mut IndexPlan<'p>, op: LocalOp<'p>, index: usize, location: &Spath) {
+    let targets_element = op.rest.len() == 1;
+    let needed = match (targets_element, op.op) {
+        (true, PatchOp::Add { .. }) => index,
+        _ => index + 1,
+    };
+    let ahead = plan.slots.len() as isize - plan.next_original as isize;
+    plan.extend(needed);
+    // Input elements are in order, so the last one in reach is the one the
+    // input must have.
+    let last_original = plan.slots[..needed]
+        .iter()
+        .rev()
+        .find_map(|slot| match slot {
+            Slot::Original(original) => Some(*original),
+            Slot::Inserted(_) => None,
+        });
+    if let Some(last_original) = last_original {
+        plan.bounds.push((last_original + 1, op.op, index, ahead));
+    }
+
+    match (targets_element, op.op) {
+        (true, PatchOp::Add { value, .. }) => {
+            plan.slots.insert(index, Slot::Inserted(value.clone()));
+        }
+        (true, PatchOp::Remove { .. }) => {
+            if let Slot::Original(original) = plan.slots.remove(index) {
+                plan.elements
+                    .entry(original)
+                    .or_default()
+                    .push(op.descend());
+            }
+        }
+        _ => match &mut plan.slots[index] {
+            Slot::Original(original) => {
+                plan.elements
+                    .entry(*original)
+                    .or_default()
+                    .push(op.descend());
+            }
+            Slot::Inserted(value) => {
+                let element = location.push(Segment::Field(index.to_string()));
+                let mut state = Some(std::mem::take(value));
+                if let Err(error) = apply_local(&mut state, &op.descend(), &element) {
+                    plan.failures.push(error);
+                }
+                *value = state.unwrap_or_default();
+            }
+        },
+    }
+}
+
+/// Applies `op` to `value` in memory. `value` is `None` when it doesn't
+/// exist, and becomes `None` when the operation removes it.
+fn apply_local(
+    value: &mut Option<Value>,
+    op: &LocalOp,
+    location: &Spath,
+) -> Result<(), PatchError> {
+    if !op.targets_self() {
+        let Some(value) = value else {
+            return Err(ResolveError::NotFound.into());
+        };
+        let path = Spath {
+            segments: op.rest.to_vec(),
+        };
+        let result = match op.op {
+            PatchOp::Add { value: new, .. } => patch::add(value, path, new.clone()),
+            PatchOp::Remove { .. } => patch::remove(value, path),
+            PatchOp::Replace { value: new, .. } => patch::replace(value, path, new.clone()),
+            PatchOp::Test {
+                value: expected, ..
+            } => patch::test(value, path, expected.clone()),
+            PatchOp::Move { .. } | PatchOp::Copy { .. } => {
+                unreachable!("move and copy are rejected before streaming")
+            }
+        };
+        return result.map_err(|error| rebase(error, location));
+    }
+
+    match op.op {
+        PatchOp::Add { value: new, .. } => *value = Some(new.clone()),
+        PatchOp::Replace { value: new, .. } => match value {
+            Some(value) => *value = new.clone(),
+            None => return Err(ResolveError::NotFound.into()),
+        },
+        PatchOp::Remove { path } => {
+            if path.is_empty() {
+                return Err(PatchError::CannotRemoveRoot);
+            }
+            value.take().ok_or(PatchError::target_not_found(path))?;
+        }
+        PatchOp::Test {
+            value: expected, ..
+        } => match value {
+            Some(value) if value == expected => {}
+            Some(_) => return Err(PatchError::ValuesNotEqual),
+            None => return Err(ResolveError::NotFound.into()),
+        },
+        PatchOp::Move { .. } | PatchOp::Copy { .. } => {
+            unreachable!("move and copy are rejected before streaming")
+        }
+    }
+    Ok(())
+}
+
+/// Prefixes the paths in `error`, which are relative to the value an
+/// operation was applied to, with the location of that value.
+fn rebase(error: PatchError, location: &Spath) -> PatchError {
+    let join = |path: Spath| Spath {
+        segments: location.segments.iter().cloned().chain(path).collect(),
+    };
+    match error {
+        PatchError::ResolveError(ResolveError::TypeMismatch {
+            expected,
+            actual,
+            path,
+        }) => PatchError::ResolveError(ResolveError::TypeMismatch {
+            expected,
+            actual,
+            path: join(path),
+        }),
+        PatchError::MissingParent { path } => PatchError::MissingParent { path: join(path) },
+        PatchError::MissingFinalToken { path } => {
+            PatchError::MissingFinalToken { path: join(path) }
+        }
+        PatchError::NotAContainer { parent, actual } => PatchError::NotAContainer {
+            parent: join(parent),
+            actual,
+        },
+        PatchError::InvalidArrayIndexToken { path, token } => PatchError::InvalidArrayIndexToken {
+            path: join(path),
+            token,
+        },
+        PatchError::ArrayIndexOutOfBounds { path, index, len } => {
+            PatchError::ArrayIndexOutOfBounds {
+                path: join(path),
+                index,
+                len,
+            }
+        }
+        PatchError::TargetNotFound { path } => PatchError::TargetNotFound { path: join(path) },
+        error => error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert2::{assert, check};
+    use serde_json::json;
+
+    use super::*;
+
+    fn path(raw: &str) -> Spath {
+        raw.try_into().unwrap()
+    }
+
+    fn stream_apply(doc: &Value, patch: &[PatchOp]) -> Result<Value, StreamError> {
+        let input = serde_json::to_vec(doc).unwrap();
+        let mut output = Vec::new();
+        apply_reader(input.as_slice(), &mut output, patch)?;
+        Ok(serde_json::from_slice(&output).unwrap())
+    }
+
+    fn sample() -> Value {
+        json!({
+            "name": "team",
+            "meta": {"tags": ["a", "b"], "escaped": "quote \" and \\n"},
+            "items": [10, 20, 30, 40],
+            "users": [
+                {"id": "a", "name": "Ada", "roles": ["admin"]},
+                {"id": "b", "name": "Bob", "roles": []},
+                {"id": 3, "name": "Cy", "roles": ["dev"]}
+            ]
+        })
+    }
+
+    #[test]
+    fn apply_reader_without_operations_should_copy_the_document() {
+        let doc = sample();
+
+        check!(stream_apply(&doc, &[]).unwrap() == doc);
+    }
+
+    #[test]
+    fn apply_reader_should_match_apply() {
+        let patches = [
+            vec![
+                PatchOp::replace(path("/name"), json!("crew")),
+                PatchOp::add(path("/meta/owner"), json!({"id": 1})),
+                PatchOp::add(path("/meta/owner/team"), json!("core")),
+                PatchOp::remove(path("/meta/tags")),
+            ],
+            vec![
+                PatchOp::remove(path("/items/3")),
+                PatchOp::remove(path("/items/2")),
+            ],
+            vec![
+                PatchOp::add(path("/items/0"), json!(5)),
+                PatchOp::remove(path("/items/2")),
+                PatchOp::replace(path("/items/1"), json!(11)),
+                PatchOp::add(path("/items/-"), json!(50)),
+            ],
+            vec![
+                PatchOp::add(path("/items/1"), json!({"n": 1})),
+                PatchOp::add(path("/items/1/m"), json!(2)),
+                PatchOp::test(path("/items/2"), json!(20)),
+                PatchOp::add(path("/items/4"), json!(45)),
+            ],
+            vec![
+                PatchOp::replace(path("/users/[id=b]/name"), json!("Bobby")),
+                PatchOp::add(path("/users/[id=3]/roles/-"), json!("ops")),
+                PatchOp::remove(path("/users/[id=a]")),
+                PatchOp::add(path("/users/-"), json!({"id": "d"})),
+            ],
+            vec![
+                PatchOp::test(path("/users/[id=a]/name"), json!("Ada")),
+                PatchOp::replace(path("/users/[id=a]"), json!({"id": "a"})),
+            ],
+            // Index and filter paths into one array are applied in memory.
+            vec![
+                PatchOp::remove(path("/users/0")),
+                PatchOp::replace(path("/users/[id=b]/name"), json!("Bobby")),
+            ],
+            vec![PatchOp::replace(path(""), json!([1, 2]))],
+        ];
+
+        for patch in patches {
+            let expected = patch::apply(&sample(), &patch).unwrap();
+            check!(
+                stream_apply(&sample(), &patch).unwrap() == expected,
+                "{patch:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn apply_reader_should_append_missing_members_at_the_end_of_the_object() {
+        let patch = [PatchOp::add(path("/a"), json!(1))];
+        let mut output = Vec::new();
+
+        apply_reader(r#"{"z": 0}"#.as_bytes(), &mut output, &patch).unwrap();
+
+        check!(String::from_utf8(output).unwrap() == r#"{"z":0,"a":1}"#);
+    }
+
+    #[test]
+    fn apply_reader_should_report_indexes_past_the_end_of_the_input() {
+        let patch = [
+            PatchOp::remove(path("/items/0")),
+            PatchOp::add(path("/items/5"), json!(1)),
+        ];
+
+        assert!(let Err(StreamError::Patch(PatchError::MultipleErrors(errors))) = stream_apply(&sample(), &patch));
+        check!(errors == vec![PatchError::index_out_of_bounds(&path("/items/5"), 5, 3)]);
+    }
+
+    #[test]
+    fn apply_reader_should_report_failed_operations() {
+        let patch = [
+            PatchOp::test(path("/users/[id=b]/name"), json!("Ada")),
+            PatchOp::replace(path("/users/[id=z]/name"), json!("Zed")),
+            PatchOp::add(path("/name/first"), json!("t")),
+        ];
+
+        assert!(let Err(StreamError::Patch(PatchError::MultipleErrors(errors))) = stream_apply(&sample(), &patch));
+        check!(
+            errors
+                == vec![
+                    PatchError::not_a_container(&path("/name"), r#"string("team")"#),
+                    PatchError::ValuesNotEqual,
+                    ResolveError::NotFound.into(),
+                ]
+        );
+    }
+
+    #[test]
+    fn apply_reader_should_reject_move_and_copy() {
+        let patch = [PatchOp::copy(path("/name"), path("/title"))];
+
+        assert!(let Err(StreamError::UnsupportedOp { op: "copy" }) = stream_apply(&sample(), &patch));
+    }
+}
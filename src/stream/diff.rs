@@ -0,0 +1,629 @@
+use std::{borrow::Cow, cmp::Ordering, io::Read};
+
+use serde_json::{Map, Value};
+
+use crate::{
+    diff::{
+        DiffError, DiffErrorSummary, DiffOptions, PatchOpRef, PatchSink, asserts_old_value,
+        diff_into_sink, index_key_value_to_filter,
+    },
+    path::{Segment, Spath},
+    stream::{
+        StreamError, StreamOptions,
+        reader::{JsonReader, Start},
+        spill::{KeyedItem, SortedItems, SpillSort},
+    },
+};
+
+/// Computes the diff of two JSON documents read from `left` and `right`,
+/// pushing operations into `sink` as they are found.
+///
+/// Only the parts of the documents that differ are materialized, see the
+/// [module documentation](crate::stream) for details. Invalid JSON and I/O
+/// failures abort the diff. Diff errors, such as missing index keys, are
+/// collected and returned once both documents were read.
+pub fn diff_readers<L: Read, R: Read>(
+    left: L,
+    right: R,
+    options: DiffOptions<'_>,
+    stream_options: StreamOptions,
+    sink: &mut impl for<'a> PatchSink<'a>,
+) -> Result<(), StreamError> {
+    let mut differ = StreamDiffer {
+        left: JsonReader::new(left, "left document"),
+        right: JsonReader::new(right, "right document"),
+        sink,
+        errors: DiffErrorSummary::empty(),
+        spill_threshold: stream_options.spill_threshold,
+    };
+
+    let left_start = differ.left.next_value()?;
+    let right_start = differ.right.next_value()?;
+    let options = options.with_optional_schema(options.schema);
+    differ.diff_value(left_start, right_start, options, &Spath::default())?;
+    differ.left.finish()?;
+    differ.right.finish()?;
+
+    if differ.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(differ.errors.into())
+    }
+}
+
+struct StreamDiffer<'s, L, R, S> {
+    left: JsonReader<L>,
+    right: JsonReader<R>,
+    sink: &'s mut S,
+    errors: DiffErrorSummary,
+    spill_threshold: usize,
+}
+
+impl<L: Read, R: Read, S: for<'a> PatchSink<'a>> StreamDiffer<'_, L, R, S> {
+    fn diff_value(
+        &mut self,
+        left: Start,
+        right: Start,
+        options: DiffOptions,
+        path: &Spath,
+    ) -> Result<(), StreamError> {
+        let options = options.with_schema_comparison();
+        if !options.path_filter.includes_below(path) || options.is_ignored() {
+            self.left.skip_value(left)?;
+            self.right.skip_value(right)?;
+            return Ok(());
+        }
+        // Custom comparisons and atomic subtrees need whole values
+        if options.comparison.is_some() || options.is_atomic_at(path) {
+            let left = self.left.read_value(left)?;
+            let right = self.right.read_value(right)?;
+            self.diff_values(&left, &right, options, path);
+            return Ok(());
+        }
+
+        match (left, right) {
+            (Start::Object, Start::Object) => self.diff_object(options, path),
+            (Start::Array, Start::Array) => self.diff_array(options, path),
+            (left, right) => {
+                let left = self.left.read_value(left)?;
+                let right = self.right.read_value(right)?;
+                self.diff_values(&left, &right, options, path);
+                Ok(())
+            }
+        }
+    }
+
+    /// Diffs materialized values with the in-memory engine.
+    fn diff_values(&mut self, left: &Value, right: &Value, options: DiffOptions, path: &Spath) {
+        let errors = diff_into_sink(left, right, options, path, self.sink);
+        self.errors.left.extend(errors.left);
+        self.errors.right.extend(errors.right);
+    }
+
+    fn diff_object(&mut self, options: DiffOptions, path: &Spath) -> Result<(), StreamError> {
+        // Members whose counterpart has not been read yet.
+        let mut left_pending = Map::new();
+        let mut right_pending = Map::new();
+        // Whether the objects still have unread members.
+        let (mut left_open, mut right_open) = (true, true);
+
+        loop {
+            let left_key = if left_open {
+                self.left.next_key()?
+            } else {
+                None
+            };
+            let right_key = if right_open {
+                self.right.next_key()?
+            } else {
+                None
+            };
+            left_open = left_key.is_some();
+            right_open = right_key.is_some();
+
+            match (left_key, right_key) {
+                (None, None) => break,
+                (Some(left_key), Some(right_key)) if left_key == right_key => {
+                    let child_options =
+                        options.with_optional_schema(options.property_schema(&left_key));
+                    let child_path = path.push(Segment::Field(left_key));
+                    let left_start = self.left.next_value()?;
+                    let right_start = self.right.next_value()?;
+                    self.diff_value(left_start, right_start, child_options, &child_path)?;
+                }
+                (left_key, right_key) => {
+                    if let Some(key) = left_key {
+                        let start = self.left.next_value()?;
+                        let value = self.left.read_value(start)?;
+                        match right_pending.remove(&key) {
+                            Some(right_value) => {
+                                self.diff_member(&key, &value, &right_value, options, path)
+                            }
+                            None => {
+                                left_pending.insert(key, value);
+                            }
+                        }
+                    }
+                    if let Some(key) = right_key {
+                        let start = self.right.next_value()?;
+                        let value = self.right.read_value(start)?;
+                        match left_pending.remove(&key) {
+                            Some(left_value) => {
+                                self.diff_member(&key, &left_value, &value, options, path)
+                            }
+                            None => {
+                                right_pending.insert(key, value);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        // What is left only exists on one side. The engine emits removals and
+        // additions for it, detecting moves between them when enabled. Granular
+        // mode keeps it from collapsing the partial objects into a `replace`.
+        if !left_pending.is_empty() || !right_pending.is_empty() {
+            self.diff_values(
+                &Value::Object(left_pending),
+                &Value::Object(right_pending),
+                options.granular(),
+                path,
+            );
+        }
+        Ok(())
+    }
+
+    fn diff_member(
+        &mut self,
+        key: &str,
+        left: &Value,
+        right: &Value,
+        options: DiffOptions,
+        path: &Spath,
+    ) {
+        let child_options = options.with_optional_schema(options.property_schema(key));
+        let child_path = path.push(Segment::Field(key.to_owned()));
+        self.diff_values(left, right, child_options, &child_path);
+    }
+
+    fn diff_array(&mut self, options: DiffOptions, path: &Spath) -> Result<(), StreamError> {
+        match options.index_key() {
+            Some(index_key) if options.schema.is_some() => {
+                self.diff_array_keyed(index_key, options, path)
+            }
+            _ => self.diff_array_indexed(options, path),
+        }
+    }
+
+    fn diff_array_indexed(
+        &mut self,
+        options: DiffOptions,
+        path: &Spath,
+    ) -> Result<(), StreamError> {
+        let item_options = match options.items_schema() {
+            Some(sub_schema) => options.with_optional_schema(Some(sub_schema)),
+            None => options.without_schema(),
+        };
+
+        let mut index = 0;
+        loop {
+            let item_path = path.push(Segment::Field(index.to_string()));
+            match (self.left.next_element()?, self.right.next_element()?) {
+                (Some(left), Some(right)) => {
+                    self.diff_value(left, right, item_options, &item_path)?;
+                    index += 1;
+                }
+                (None, None) => return Ok(()),
+                (Some(left), None) => {
+                    // Every remaining element shifts down to `index` once the
+                    // previous one is removed.
+                    let included = options.path_filter.includes(&item_path);
+                    let mut next = Some(left);
+                    while let Some(start) = next {
+                        if !included {
+                            self.left.skip_value(start)?;
+                        } else if asserts_old_value(options, !matches!(start, Start::Scalar(_))) {
+                            let value = self.left.read_value(start)?;
+                            self.sink.push(PatchOpRef::Test {
+                                path: item_path.clone(),
+                                value: Cow::Owned(value),
+                            });
+                        } else {
+                            self.left.skip_value(start)?;
+                        }
+                        if included {
+                            self.sink.push(PatchOpRef::Remove {
+                                path: item_path.clone(),
+                            });
+                        }
+                        next = self.left.next_element()?;
+                    }
+                    return Ok(());
+                }
+                (None, Some(right)) => {
+                    let append_path = path.push(Segment::Field("-".to_owned()));
+                    let included = options.path_filter.includes(&append_path);
+                    let mut next = Some(right);
+                    while let Some(start) = next {
+                        if included {
+                            let value = self.right.read_value(start)?;
+                            self.sink.push(PatchOpRef::Add {
+                                path: append_path.clone(),
+                                value: Cow::Owned(value),
+                            });
+                        } else {
+                            self.right.skip_value(start)?;
+                        }
+                        next = self.right.next_element()?;
+                    }
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn diff_array_keyed(
+        &mut self,
+        index_key: &str,
+        options: DiffOptions,
+        path: &Spath,
+    ) -> Result<(), StreamError> {
+        let mut left_sort = SpillSort::new(self.spill_threshold);
+        let mut index = 0;
+        while let Some(start) = self.left.next_element()? {
+            let item = self.left.read_value(start)?;
+            match keyed_item(item, index, index_key, path) {
+                Ok(keyed) => left_sort.push(keyed)?,
+                Err(error) => self.errors.left.push(error),
+            }
+            index += 1;
+        }
+
+        let mut right_sort = SpillSort::new(self.spill_threshold);
+        let mut index = 0;
+        while let Some(start) = self.right.next_element()? {
+            let item = self.right.read_value(start)?;
+            match keyed_item(item, index, index_key, path) {
+                Ok(keyed) => right_sort.push(keyed)?,
+                Err(error) => self.errors.right.push(error),
+            }
+            index += 1;
+        }
+
+        let mut left_items = UniqueItems::new(left_sort.into_sorted()?, index_key, path);
+        let mut right_items = UniqueItems::new(right_sort.into_sorted()?, index_key, path);
+        let mut left = left_items.next(&mut self.errors.left)?;
+        let mut right = right_items.next(&mut self.errors.right)?;
+
+        // Each matched pair is diffed as a pair of one-element arrays, so the
+        // engine emits the same semantic paths and guards as for a whole array.
+        loop {
+            let order = match (&left, &right) {
+                (None, None) => return Ok(()),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((left_key, ..)), Some((right_key, ..))) => left_key.cmp(right_key),
+            };
+
+            let (left_item, right_item) = match order {
+                Ordering::Less => (left.take().map(|(_, _, value)| value), None),
+                Ordering::Greater => (None, right.take().map(|(_, _, value)| value)),
+                Ordering::Equal => (
+                    left.take().map(|(_, _, value)| value),
+                    right.take().map(|(_, _, value)| value),
+                ),
+            };
+            self.diff_values(
+                &Value::Array(left_item.into_iter().collect()),
+                &Value::Array(right_item.into_iter().collect()),
+                options,
+                path,
+            );
+
+            if left.is_none() {
+                left = left_items.next(&mut self.errors.left)?;
+            }
+            if right.is_none() {
+                right = right_items.next(&mut self.errors.right)?;
+            }
+        }
+    }
+}
+
+/// Extracts the identity of a keyed array element, reporting the same errors
+/// as the in-memory engine.
+fn keyed_item(
+    item: Value,
+    index: usize,
+    index_key: &str,
+    path: &Spath,
+) -> Result<KeyedItem, DiffError> {
+    let item_path = path.push(Segment::Field(index.to_string()));
+    let Value::Object(obj) = &item else {
+        return Err(DiffError::non_object_array_item(path, &item));
+    };
+    let value = obj
+        .get(index_key)
+        .ok_or_else(|| DiffError::missing_index_key(&item_path, index_key))?;
+    let key = index_key_value_to_filter(value)
+        .ok_or_else(|| DiffError::non_string_index_key(&item_path, value))?;
+    Ok((key, index, item))
+}
+
+/// Sorted keyed elements, skipping and reporting duplicate keys. The first
+/// element with a key wins, like in the in-memory engine.
+struct UniqueItems<'p> {
+    items: SortedItems,
+    previous_key: Option<String>,
+    index_key: &'p str,
+    path: &'p Spath,
+}
+
+impl<'p> UniqueItems<'p> {
+    fn new(items: SortedItems, index_key: &'p str, path: &'p Spath) -> Self {
+        UniqueItems {
+            items,
+            previous_key: None,
+            index_key,
+            path,
+        }
+    }
+
+    fn next(&mut self, errors: &mut Vec<DiffError>) -> Result<Option<KeyedItem>, StreamError> {
+        while let Some(item) = self.items.next_item()? {
+            if self.previous_key.as_ref() == Some(&item.0) {
+                let item_path = self.path.push(Segment::Field(item.1.to_string()));
+                errors.push(DiffError::duplicate_index_key(
+                    &item_path,
+                    self.index_key,
+                    &item.0,
+                ));
+                continue;
+            }
+            self.previous_key = Some(item.0.clone());
+            return Ok(Some(item));
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert2::{assert, check};
+    use serde_json::json;
+
+    use crate::diff::{Patch, PatchOp, TestMode};
+
+    use super::*;
+
+    fn path(raw: &str) -> Spath {
+        raw.try_into().unwrap()
+    }
+
+    fn stream_diff(
+        left: &Value,
+        right: &Value,
+        options: DiffOptions,
+        stream_options: StreamOptions,
+    ) -> Result<Patch, StreamError> {
+        let left = serde_json::to_vec(left).unwrap();
+        let right = serde_json::to_vec(right).unwrap();
+        let mut patch = Patch::default();
+        diff_readers(&left[..], &right[..], options, stream_options, &mut patch)?;
+        Ok(patch)
+    }
+
+    #[test]
+    fn diff_readers_should_only_emit_changed_members() {
+        let left = json!({"a": {"b": 1, "c": [1, 2]}, "d": "same"});
+        let right = json!({"a": {"b": 2, "c": [1, 2, 3]}, "d": "same"});
+
+        assert!(let Ok(patch) = stream_diff(&left, &right, DiffOptions::new(), StreamOptions::new()));
+
+        check!(
+            patch
+                == Patch::new(vec![
+                    PatchOp::replace(path("/a/b"), json!(2)),
+                    PatchOp::add(path("/a/c/-"), json!(3)),
+                ])
+        );
+    }
+
+    #[test]
+    fn diff_readers_should_match_out_of_order_members() {
+        let left = r#"{"x": {"v": 1}, "y": 2, "gone": true}"#;
+        let right = r#"{"y": 3, "new": null, "x": {"v": 1}}"#;
+
+        let mut patch = Patch::default();
+        assert!(let Ok(()) = diff_readers(
+            left.as_bytes(),
+            right.as_bytes(),
+            DiffOptions::new(),
+            StreamOptions::new(),
+            &mut patch,
+        ));
+
+        check!(
+            patch
+                == Patch::new(vec![
+                    PatchOp::replace(path("/y"), json!(3)),
+                    PatchOp::add(path("/new"), json!(null)),
+                    PatchOp::remove(path("/gone")),
+                ])
+        );
+    }
+
+    #[test]
+    fn diff_readers_should_keep_unmatched_members_granular_under_compact_schemas() {
+        let schema = json!({"x-spatch-granularity": "compact"});
+        let long = "a value long enough to make a replace of the object cheaper";
+        let left = format!(r#"{{"b": "{long}", "a": 1, "d": "{long}"}}"#);
+        let right = r#"{"a": 1}"#;
+
+        let mut patch = Patch::default();
+        assert!(let Ok(()) = diff_readers(
+            left.as_bytes(),
+            right.as_bytes(),
+            DiffOptions::new().with_schema(&schema),
+            StreamOptions::new(),
+            &mut patch,
+        ));
+
+        check!(
+            patch
+                == Patch::new(vec![
+                    PatchOp::remove(path("/b")),
+                    PatchOp::remove(path("/d")),
+                ])
+        );
+        let left: Value = serde_json::from_str(&left).unwrap();
+        check!(crate::patch::apply(&left, &patch).unwrap() == json!({"a": 1}));
+    }
+
+    #[test]
+    fn diff_readers_should_remove_trailing_elements_at_the_same_index() {
+        let left = json!({"items": [1, 2, 3, 4]});
+        let right = json!({"items": [1, 5]});
+
+        assert!(let Ok(patch) = stream_diff(
+            &left,
+            &right,
+            DiffOptions::new().with_tests(TestMode::LeafValuesOnly),
+            StreamOptions::new(),
+        ));
+
+        check!(
+            patch
+                == Patch::new(vec![
+                    PatchOp::test(path("/items/1"), json!(2)),
+                    PatchOp::replace(path("/items/1"), json!(5)),
+                    PatchOp::test(path("/items/2"), json!(3)),
+                    PatchOp::remove(path("/items/2")),
+                    PatchOp::test(path("/items/2"), json!(4)),
+                    PatchOp::remove(path("/items/2")),
+                ])
+        );
+        check!(crate::patch::apply(&left, &patch).unwrap() == right);
+    }
+
+    #[test]
+    fn diff_readers_should_match_keyed_arrays_spilled_to_disk() {
+        let schema = json!({
+            "properties": {
+                "users": { "x-spatch-indexKey": "id" }
+            }
+        });
+        let left = json!({"users": [
+            {"id": "u-3", "name": "Edsger"},
+            {"id": "u-1", "name": "Ada"},
+            {"id": "u-2", "name": "Grace"}
+        ]});
+        let right = json!({"users": [
+            {"id": "u-2", "name": "Grace Hopper"},
+            {"id": "u-4", "name": "Barbara"},
+            {"id": "u-1", "name": "Ada"}
+        ]});
+        let options = DiffOptions::new().with_schema(&schema).granular();
+
+        assert!(let Ok(patch) = stream_diff(
+            &left,
+            &right,
+            options,
+            StreamOptions::new().with_spill_threshold(1),
+        ));
+
+        check!(
+            patch
+                == Patch::new(vec![
+                    PatchOp::replace(path("/users/[id=u-2]/name"), json!("Grace Hopper")),
+                    PatchOp::remove(path("/users/[id=u-3]")),
+                    PatchOp::add(path("/users/-"), json!({"id": "u-4", "name": "Barbara"})),
+                ])
+        );
+        check!(
+            patch.normalize()
+                == crate::diff::diff(&left, &right, options)
+                    .unwrap()
+                    .normalize()
+        );
+    }
+
+    #[test]
+    fn diff_readers_should_report_duplicate_index_keys() {
+        let schema = json!({ "x-spatch-indexKey": "id" });
+        let left = json!([{"id": "a"}, {"id": "a"}, {"name": "no id"}]);
+        let right = json!([{"id": "a"}]);
+
+        assert!(let Err(StreamError::Diff(errors)) = stream_diff(
+            &left,
+            &right,
+            DiffOptions::new().with_schema(&schema),
+            StreamOptions::new(),
+        ));
+
+        check!(
+            errors.left
+                == vec![
+                    DiffError::missing_index_key(&path("/2"), "id"),
+                    DiffError::duplicate_index_key(&path("/1"), "id", "a"),
+                ]
+        );
+        check!(errors.right.is_empty());
+    }
+
+    #[test]
+    fn diff_readers_should_fail_on_invalid_json() {
+        let mut patch = Patch::default();
+
+        assert!(let Err(StreamError::Syntax { input: "right document", .. }) = diff_readers(
+            r#"{"a": 1}"#.as_bytes(),
+            r#"{"a": 1"#.as_bytes(),
+            DiffOptions::new(),
+            StreamOptions::new(),
+            &mut patch,
+        ));
+    }
+
+    #[test]
+    fn diff_readers_should_skip_ignored_subtrees() {
+        let schema = json!({"properties": {
+            "meta": {"x-spatch-ignore": true},
+            "tags": {"x-spatch-compare": "unordered"}
+        }});
+        let ignore = ["/items/*".try_into().unwrap()];
+        let left = json!({"meta": {"at": 1}, "tags": ["a", "b"], "items": [1], "n": 1});
+        let right = json!({"meta": {"at": 2}, "tags": ["b", "a"], "items": [1, 2], "n": 2});
+
+        assert!(let Ok(patch) = stream_diff(
+            &left,
+            &right,
+            DiffOptions::new().with_schema(&schema).ignore(&ignore),
+            StreamOptions::new(),
+        ));
+
+        check!(patch == Patch::new(vec![PatchOp::replace(path("/n"), json!(2))]));
+    }
+
+    #[test]
+    fn diff_readers_should_replace_atomic_subtrees() {
+        let schema = json!({"properties": {"geometry": {"x-spatch-atomic": true}}});
+        let left = json!({"geometry": {"points": [1, 2]}, "n": 1});
+        let right = json!({"geometry": {"points": [1, 3]}, "n": 1});
+
+        assert!(let Ok(patch) = stream_diff(
+            &left,
+            &right,
+            DiffOptions::new().with_schema(&schema),
+            StreamOptions::new(),
+        ));
+
+        check!(
+            patch
+                == Patch::new(vec![PatchOp::replace(
+                    path("/geometry"),
+                    json!({"points": [1, 3]})
+                )])
+        );
+    }
+}
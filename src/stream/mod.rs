@@ -0,0 +1,109 @@
+//! Diff and patch JSON documents without loading them into memory.
+//!
+//! [`diff_readers`] tokenizes both inputs and walks them in lockstep. Members
+//! and elements that are identical on both sides are compared token by token
+//! and never materialized, so memory use depends on the size of the changes
+//! rather than the size of the documents:
+//!
+//! - object members are streamed while both documents list them in the same
+//!   order. Members that appear out of order are buffered until their
+//!   counterpart shows up;
+//! - arrays without an index key are compared position by position;
+//! - keyed arrays are matched by identity. Their elements are read one at a
+//!   time and sorted by key, spilling to temporary files once they exceed
+//!   [`StreamOptions::spill_threshold`].
+//!
+//! Operations are pushed into a [`PatchSink`] as soon as they are known. They
+//! describe the same changes as [`diff`](crate::diff::diff), but streamed
+//! objects are never collapsed into a single `replace`, and operations follow
+//! document order rather than sorted key order.
+//!
+//! ```rust
+//! use serde_json::json;
+//! use spatch::{
+//!     diff::{DiffOptions, Patch, PatchOp},
+//!     stream::{StreamOptions, diff_readers},
+//! };
+//!
+//! let before = r#"{"name": "Ada", "tags": ["math"]}"#;
+//! let after = r#"{"name": "Ada", "tags": ["math", "poetry"]}"#;
+//!
+//! let mut patch = Patch::default();
+//! diff_readers(
+//!     before.as_bytes(),
+//!     after.as_bytes(),
+//!     DiffOptions::new(),
+//!     StreamOptions::new(),
+//!     &mut patch,
+//! )
+//! .unwrap();
+//!
+//! assert_eq!(*patch, vec![PatchOp::add("/tags/-".try_into().unwrap(), json!("poetry"))]);
+//! ```
+//!
+//! [`apply_reader`] is the other direction: it copies a document from a reader
+//! to a writer and applies a patch to the parts its paths select on the way.
+mod apply;
+mod diff;
+mod reader;
+mod spill;
+
+use std::io;
+
+pub use apply::apply_reader;
+pub use diff::diff_readers;
+
+use crate::{diff::DiffErrorSummary, patch::PatchError};
+
+#[derive(Debug, thiserror::Error)]
+pub enum StreamError {
+    #[error("Failed to read input: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Invalid JSON in {input} at byte {offset}: {message}")]
+    Syntax {
+        input: &'static str,
+        offset: u64,
+        message: String,
+    },
+
+    #[error(transparent)]
+    Diff(#[from] DiffErrorSummary),
+
+    #[error(transparent)]
+    Patch(#[from] PatchError),
+
+    #[error("`{op}` operations can't be applied to a stream")]
+    UnsupportedOp { op: &'static str },
+}
+
+/// Options for streaming diffs.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct StreamOptions {
+    /// Size in bytes of the keyed array elements held in memory per array
+    /// before they are spilled to temporary files.
+    pub spill_threshold: usize,
+}
+
+impl Default for StreamOptions {
+    /// Spills keyed arrays once their elements exceed 64 MiB.
+    fn default() -> Self {
+        StreamOptions {
+            spill_threshold: 64 * 1024 * 1024,
+        }
+    }
+}
+
+impl StreamOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how many bytes of keyed array elements are held in memory before
+    /// they are spilled to temporary files.
+    pub fn with_spill_threshold(mut self, bytes: usize) -> Self {
+        self.spill_threshold = bytes;
+        self
+    }
+}
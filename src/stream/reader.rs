@@ -0,0 +1,352 @@
+use std::io::{self, BufRead, BufReader, Read};
+use std::str::FromStr;
+
+use serde_json::{Map, Number, Value};
+
+use crate::stream::StreamError;
+
+/// The first token of a JSON value.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Start {
+    Object,
+    Array,
+    Scalar(Value),
+}
+
+/// A pull tokenizer over a JSON document.
+///
+/// Values are read one token at a time, so callers decide which subtrees are
+/// materialized and which are only walked. Nesting is tracked by the reader,
+/// callers only ask for the next member or element of the innermost open
+/// container.
+pub(crate) struct JsonReader<R> {
+    input: BufReader<R>,
+    /// Name of the input, reported in syntax errors.
+    name: &'static str,
+    /// Byte offset of the next unread byte, reported in syntax errors.
+    offset: u64,
+    /// For each open container, whether it has at least one member.
+    containers: Vec<bool>,
+}
+
+impl<R: Read> JsonReader<R> {
+    pub(crate) fn new(input: R, name: &'static str) -> Self {
+        JsonReader {
+            input: BufReader::new(input),
+            name,
+            offset: 0,
+            containers: Vec::new(),
+        }
+    }
+
+    /// Reads the first token of the next value.
+    pub(crate) fn next_value(&mut self) -> Result<Start, StreamError> {
+        self.skip_whitespace()?;
+        match self.peek()? {
+            Some(b'{') => {
+                self.consume(1);
+                self.containers.push(false);
+                Ok(Start::Object)
+            }
+            Some(b'[') => {
+                self.consume(1);
+                self.containers.push(false);
+                Ok(Start::Array)
+            }
+            Some(b'"') => Ok(Start::Scalar(Value::String(self.read_string()?))),
+            Some(b't') => self.read_literal("true", Value::Bool(true)),
+            Some(b'f') => self.read_literal("false", Value::Bool(false)),
+            Some(b'n') => self.read_literal("null", Value::Null),
+            Some(b'-' | b'0'..=b'9') => Ok(Start::Scalar(Value::Number(self.read_number()?))),
+            Some(byte) => Err(self.syntax(format!("unexpected character {:?}", byte as char))),
+            None => Err(self.syntax("unexpected end of input")),
+        }
+    }
+
+    /// Reads the key of the next member of the innermost open object, or
+    /// returns `None` and closes the object when it has no more members.
+    pub(crate) fn next_key(&mut self) -> Result<Option<String>, StreamError> {
+        if !self.next_item(b'}')? {
+            return Ok(None);
+        }
+
+        if self.peek()? != Some(b'"') {
+            return Err(self.syntax("expected an object key"));
+        }
+        let key = self.read_string()?;
+        self.skip_whitespace()?;
+        self.expect(b':')?;
+        Ok(Some(key))
+    }
+
+    /// Reads the first token of the next element of the innermost open array,
+    /// or returns `None` and closes the array when it has no more elements.
+    pub(crate) fn next_element(&mut self) -> Result<Option<Start>, StreamError> {
+        if !self.next_item(b']')? {
+            return Ok(None);
+        }
+        self.next_value().map(Some)
+    }
+
+    /// Reads the rest of a value whose first token is `start`.
+    pub(crate) fn read_value(&mut self, start: Start) -> Result<Value, StreamError> {
+        match start {
+            Start::Scalar(value) => Ok(value),
+            Start::Object => {
+                let mut map = Map::new();
+                while let Some(key) = self.next_key()? {
+                    let start = self.next_value()?;
+                    map.insert(key, self.read_value(start)?);
+                }
+                Ok(Value::Object(map))
+            }
+            Start::Array => {
+                let mut items = Vec::new();
+                while let Some(start) = self.next_element()? {
+                    items.push(self.read_value(start)?);
+                }
+                Ok(Value::Array(items))
+            }
+        }
+    }
+
+    /// Reads past the rest of a value whose first token is `start`.
+    pub(crate) fn skip_value(&mut self, start: Start) -> Result<(), StreamError> {
+        match start {
+            Start::Scalar(_) => Ok(()),
+            Start::Object => {
+                while self.next_key()?.is_some() {
+                    let start = self.next_value()?;
+                    self.skip_value(start)?;
+                }
+                Ok(())
+            }
+            Start::Array => {
+                while let Some(start) = self.next_element()? {
+                    self.skip_value(start)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Checks that nothing but whitespace follows the document.
+    pub(crate) fn finish(&mut self) -> Result<(), StreamError> {
+        self.skip_whitespace()?;
+        match self.peek()? {
+            None => Ok(()),
+            Some(_) => Err(self.syntax("trailing characters after the document")),
+        }
+    }
+
+    /// Moves to the next item of the innermost container. Returns `false` and
+    /// closes the container when `close` is found instead.
+    fn next_item(&mut self, close: u8) -> Result<bool, StreamError> {
+        self.skip_whitespace()?;
+        if self.peek()? == Some(close) {
+            self.consume(1);
+            self.containers.pop();
+            return Ok(false);
+        }
+
+        let has_items = self
+            .containers
+            .last_mut()
+            .expect("an item is only read inside a container");
+        if *has_items {
+            self.expect(b',')?;
+            self.skip_whitespace()?;
+        } else {
+            *has_items = true;
+        }
+        Ok(true)
+    }
+
+    fn read_literal(&mut self, literal: &str, value: Value) -> Result<Start, StreamError> {
+        for expected in literal.bytes() {
+            if self.peek()? != Some(expected) {
+                return Err(self.syntax(format!("invalid literal, expected `{literal}`")));
+            }
+            self.consume(1);
+        }
+        Ok(Start::Scalar(value))
+    }
+
+    fn read_number(&mut self) -> Result<Number, StreamError> {
+        let mut raw = String::new();
+        while let Some(byte @ (b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')) = self.peek()? {
+            raw.push(byte as char);
+            self.consume(1);
+        }
+        Number::from_str(&raw).map_err(|_| self.syntax(format!("invalid number `{raw}`")))
+    }
+
+    fn read_string(&mut self) -> Result<String, StreamError> {
+        self.expect(b'"')?;
+        let mut bytes = Vec::new();
+        loop {
+            let byte = self
+                .peek()?
+                .ok_or_else(|| self.syntax("unterminated string"))?;
+            self.consume(1);
+            match byte {
+                b'"' => break,
+                b'\\' => self.read_escape(&mut bytes)?,
+                0x00..=0x1f => return Err(self.syntax("control character in string")),
+                _ => bytes.push(byte),
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| self.syntax("invalid UTF-8 in string"))
+    }
+
+    fn read_escape(&mut self, bytes: &mut Vec<u8>) -> Result<(), StreamError> {
+        let escaped = self
+            .peek()?
+            .ok_or_else(|| self.syntax("unterminated string"))?;
+        self.consume(1);
+        let unescaped = match escaped {
+            b'"' => '"',
+            b'\\' => '\\',
+            b'/' => '/',
+            b'b' => '\u{8}',
+            b'f' => '\u{c}',
+            b'n' => '\n',
+            b'r' => '\r',
+            b't' => '\t',
+            b'u' => self.read_unicode_escape()?,
+            _ => return Err(self.syntax("invalid escape sequence")),
+        };
+        let mut buf = [0; 4];
+        bytes.extend_from_slice(unescaped.encode_utf8(&mut buf).as_bytes());
+        Ok(())
+    }
+
+    fn read_unicode_escape(&mut self) -> Result<char, StreamError> {
+        let high = self.read_hex4()?;
+        if !(0xD800..0xDC00).contains(&high) {
+            return char::from_u32(high).ok_or_else(|| self.syntax("invalid unicode escape"));
+        }
+
+        // A high surrogate must be followed by an escaped low surrogate.
+        for expected in [b'\\', b'u'] {
+            if self.peek()? != Some(expected) {
+                return Err(self.syntax("unpaired surrogate in unicode escape"));
+            }
+            self.consume(1);
+        }
+        let low = self.read_hex4()?;
+        if !(0xDC00..0xE000).contains(&low) {
+            return Err(self.syntax("unpaired surrogate in unicode escape"));
+        }
+        char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00))
+            .ok_or_else(|| self.syntax("invalid unicode escape"))
+    }
+
+    fn read_hex4(&mut self) -> Result<u32, StreamError> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self
+                .peek()?
+                .and_then(|byte| (byte as char).to_digit(16))
+                .ok_or_else(|| self.syntax("invalid unicode escape"))?;
+            self.consume(1);
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    fn skip_whitespace(&mut self) -> Result<(), StreamError> {
+        while let Some(b' ' | b'\n' | b'\r' | b'\t') = self.peek()? {
+            self.consume(1);
+        }
+        Ok(())
+    }
+
+    fn expect(&mut self, expected: u8) -> Result<(), StreamError> {
+        match self.peek()? {
+            Some(byte) if byte == expected => {
+                self.consume(1);
+                Ok(())
+            }
+            Some(byte) => Err(self.syntax(format!(
+                "expected {:?}, found {:?}",
+                expected as char, byte as char
+            ))),
+            None => Err(self.syntax(format!(
+                "expected {:?}, found end of input",
+                expected as char
+            ))),
+        }
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>, StreamError> {
+        loop {
+            match self.input.fill_buf() {
+                Ok(buf) => return Ok(buf.first().copied()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.input.consume(amount);
+        self.offset += amount as u64;
+    }
+
+    fn syntax(&self, message: impl Into<String>) -> StreamError {
+        StreamError::Syntax {
+            input: self.name,
+            offset: self.offset,
+            message: message.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert2::{assert, check};
+    use serde_json::json;
+
+    use super::*;
+
+    fn read(input: &str) -> Result<Value, StreamError> {
+        let mut reader = JsonReader::new(input.as_bytes(), "input");
+        let start = reader.next_value()?;
+        let value = reader.read_value(start)?;
+        reader.finish()?;
+        Ok(value)
+    }
+
+    #[test]
+    fn read_value_should_match_serde_json() {
+        let input = r#" {"a": [1, -2.5e3, true, false, null], "b\"é😀": {"c": "x\ny"}, "d": []} "#;
+
+        assert!(let Ok(value) = read(input));
+        check!(value == serde_json::from_str::<Value>(input).unwrap());
+    }
+
+    #[test]
+    fn next_key_should_walk_object_members() {
+        let mut reader = JsonReader::new(r#"{"a": 1, "b": {"c": 2}}"#.as_bytes(), "input");
+
+        check!(reader.next_value().unwrap() == Start::Object);
+        check!(reader.next_key().unwrap() == Some("a".to_owned()));
+        check!(reader.next_value().unwrap() == Start::Scalar(json!(1)));
+        check!(reader.next_key().unwrap() == Some("b".to_owned()));
+        let start = reader.next_value().unwrap();
+        reader.skip_value(start).unwrap();
+        check!(reader.next_key().unwrap() == None);
+        check!(let Ok(()) = reader.finish());
+    }
+
+    #[test]
+    fn malformed_documents_should_fail_with_the_offset() {
+        check!(let Err(StreamError::Syntax { offset: 6, .. }) = read(r#"[1, 2,]"#));
+        check!(let Err(StreamError::Syntax { .. }) = read(r#"{"a" 1}"#));
+        check!(let Err(StreamError::Syntax { .. }) = read(r#"{"a": tru}"#));
+        check!(let Err(StreamError::Syntax { .. }) = read(r#""\ud83d""#));
+        check!(let Err(StreamError::Syntax { .. }) = read(r#"[1] 2"#));
+        check!(let Err(StreamError::Syntax { .. }) = read(r#"{"a": 1"#));
+    }
+}
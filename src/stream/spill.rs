@@ -0,0 +1,204 @@
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Seek, Write},
+};
+
+use serde_json::Value;
+
+use crate::{diff::json_len, stream::StreamError};
+
+/// An element of a keyed array: its identity key, its position in the array,
+/// and the element itself.
+pub(crate) type KeyedItem = (String, usize, Value);
+
+/// Collects keyed array elements and returns them sorted by key.
+///
+/// Elements are kept in memory until they exceed the memory limit. Then they
+/// are sorted and written to a temporary file as a run, and the runs are
+/// merged when the elements are read back. Only one element per run is held
+/// in memory while merging, so arrays of any length can be matched by key.
+pub(crate) struct SpillSort {
+    memory_limit: usize,
+    buffered: Vec<KeyedItem>,
+    buffered_len: usize,
+    runs: Vec<File>,
+}
+
+impl SpillSort {
+    pub(crate) fn new(memory_limit: usize) -> Self {
+        SpillSort {
+            memory_limit,
+            buffered: Vec::new(),
+            buffered_len: 0,
+            runs: Vec::new(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, item: KeyedItem) -> Result<(), StreamError> {
+        self.buffered_len += item.0.len() + json_len(&item.2);
+        self.buffered.push(item);
+        if self.buffered_len > self.memory_limit {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the elements sorted by key, then by position.
+    pub(crate) fn into_sorted(mut self) -> Result<SortedItems, StreamError> {
+        if self.runs.is_empty() {
+            self.buffered.sort_by(|a, b| (&a.0, a.1).cmp(&(&b.0, b.1)));
+            return Ok(SortedItems::Memory(self.buffered.into_iter()));
+        }
+
+        self.spill()?;
+        let mut runs = Vec::with_capacity(self.runs.len());
+        let mut heap = BinaryHeap::new();
+        for (run, mut file) in self.runs.into_iter().enumerate() {
+            file.rewind()?;
+            let mut reader = BufReader::new(file);
+            if let Some(item) = read_item(&mut reader)? {
+                heap.push(Reverse(HeapEntry { item, run }));
+            }
+            runs.push(reader);
+        }
+        Ok(SortedItems::Merge { runs, heap })
+    }
+
+    fn spill(&mut self) -> Result<(), StreamError> {
+        self.buffered.sort_by(|a, b| (&a.0, a.1).cmp(&(&b.0, b.1)));
+
+        let mut writer = BufWriter::new(tempfile::tempfile()?);
+        for item in self.buffered.drain(..) {
+            serde_json::to_writer(&mut writer, &item).map_err(std::io::Error::from)?;
+            writer.write_all(b"\n")?;
+        }
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+
+        self.runs.push(file);
+        self.buffered_len = 0;
+        Ok(())
+    }
+}
+
+/// Elements of a [`SpillSort`], in key order.
+pub(crate) enum SortedItems {
+    Memory(std::vec::IntoIter<KeyedItem>),
+    Merge {
+        runs: Vec<BufReader<File>>,
+        heap: BinaryHeap<Reverse<HeapEntry>>,
+    },
+}
+
+impl SortedItems {
+    pub(crate) fn next_item(&mut self) -> Result<Option<KeyedItem>, StreamError> {
+        match self {
+            SortedItems::Memory(items) => Ok(items.next()),
+            SortedItems::Merge { runs, heap } => {
+                let Some(Reverse(HeapEntry { item, run })) = heap.pop() else {
+                    return Ok(None);
+                };
+                if let Some(next) = read_item(&mut runs[run])? {
+                    heap.push(Reverse(HeapEntry { item: next, run }));
+                }
+                Ok(Some(item))
+            }
+        }
+    }
+}
+
+pub(crate) struct HeapEntry {
+    item: KeyedItem,
+    run: usize,
+}
+
+impl HeapEntry {
+    fn order_key(&self) -> (&str, usize) {
+        (&self.item.0, self.item.1)
+    }
+}
+
+impl PartialEq for HeapEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.order_key() == other.order_key()
+    }
+}
+
+impl Eq for HeapEntry {}
+
+impl PartialOrd for HeapEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for HeapEntry {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.order_key().cmp(&other.order_key())
+    }
+}
+
+fn read_item(reader: &mut BufReader<File>) -> Result<Option<KeyedItem>, StreamError> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let item = serde_json::from_str(&line).map_err(std::io::Error::from)?;
+    Ok(Some(item))
+}
+
+#[cfg(test)]
+mod tests {
+    use assert2::check;
+    use serde_json::json;
+
+    use super::*;
+
+    fn collect(mut items: SortedItems) -> Vec<KeyedItem> {
+        let mut collected = Vec::new();
+        while let Some(item) = items.next_item().unwrap() {
+            collected.push(item);
+        }
+        collected
+    }
+
+    #[test]
+    fn spill_sort_should_merge_runs_in_key_order() {
+        // A limit this low writes a run for every element.
+        let mut sort = SpillSort::new(1);
+        for (index, key) in ["c", "a", "b", "a"].into_iter().enumerate() {
+            sort.push((key.to_owned(), index, json!({"id": key, "index": index})))
+                .unwrap();
+        }
+
+        let sorted: Vec<(String, usize)> = collect(sort.into_sorted().unwrap())
+            .into_iter()
+            .map(|(key, index, _)| (key, index))
+            .collect();
+
+        check!(
+            sorted
+                == vec![
+                    ("a".to_owned(), 1),
+                    ("a".to_owned(), 3),
+                    ("b".to_owned(), 2),
+                    ("c".to_owned(), 0),
+                ]
+        );
+    }
+
+    #[test]
+    fn spill_sort_should_keep_small_inputs_in_memory() {
+        let mut sort = SpillSort::new(usize::MAX);
+        sort.push(("b".to_owned(), 0, json!(1))).unwrap();
+        sort.push(("a".to_owned(), 1, json!(2))).unwrap();
+
+        let sorted = sort.into_sorted().unwrap();
+
+        check!(let SortedItems::Memory(_) = &sorted);
+        check!(
+            collect(sorted) == vec![("a".to_owned(), 1, json!(2)), ("b".to_owned(), 0, json!(1))]
+        );
+    }
+}